@@ -0,0 +1,512 @@
+use log::debug;
+
+mod dmc;
+mod envelope;
+pub mod expansion;
+mod noise;
+mod pulse;
+mod triangle;
+
+use dmc::Dmc;
+use noise::Noise;
+use pulse::Pulse;
+use triangle::Triangle;
+
+/// CPU cycles at which the frame counter clocks envelopes, sweeps and length counters
+const STEP_1: usize = 7457;
+const STEP_2: usize = 14913;
+const STEP_3: usize = 22371;
+const STEP_4: usize = 29829;
+const STEP_5: usize = 37281;
+
+/// the sound channels built into the APU
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Pulse1,
+    Pulse2,
+    Triangle,
+    Noise,
+    Dmc,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 5] = [
+        Channel::Pulse1,
+        Channel::Pulse2,
+        Channel::Triangle,
+        Channel::Noise,
+        Channel::Dmc,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Channel::Pulse1 => "pulse1",
+            Channel::Pulse2 => "pulse2",
+            Channel::Triangle => "triangle",
+            Channel::Noise => "noise",
+            Channel::Dmc => "dmc",
+        }
+    }
+}
+
+/// Combines the channel levels into a single output
+///
+/// The DACs don't add up linearly, louder channels reduce the volume of the others. On top of
+/// the hardware each channel has a gain and can be muted or soloed, which is applied to its
+/// level before mixing. Expansion audio from the cartridge is added on afterwards and is
+/// silenced while any of the APU's channels are soloed.
+///
+/// - https://www.nesdev.org/wiki/APU_Mixer
+struct Mixer {
+    gain: [f32; 5],
+    muted: [bool; 5],
+    solo: [bool; 5],
+    /// gain after applying mute and solo
+    effective: [f32; 5],
+    expansion: f32,
+}
+
+impl Mixer {
+    fn new() -> Self {
+        Mixer {
+            gain: [1.0; 5],
+            muted: [false; 5],
+            solo: [false; 5],
+            effective: [1.0; 5],
+            expansion: 1.0,
+        }
+    }
+
+    fn update(&mut self) {
+        let any_solo = self.solo.contains(&true);
+        for i in 0..5 {
+            let audible = !self.muted[i] && (!any_solo || self.solo[i]);
+            self.effective[i] = if audible { self.gain[i] } else { 0.0 };
+        }
+        self.expansion = if any_solo { 0.0 } else { 1.0 };
+    }
+
+    /// mixes channel levels, in Channel::ALL order, into a single sample in 0.0-1.0
+    fn mix(levels: [f32; 5]) -> f32 {
+        let [pulse1, pulse2, triangle, noise, dmc] = levels;
+        let pulse = if pulse1 + pulse2 > 0.0 {
+            95.88 / (8128.0 / (pulse1 + pulse2) + 100.0)
+        } else {
+            0.0
+        };
+        let tnd = triangle / 8227.0 + noise / 12241.0 + dmc / 22638.0;
+        let tnd = if tnd > 0.0 {
+            159.79 / (1.0 / tnd + 100.0)
+        } else {
+            0.0
+        };
+        pulse + tnd
+    }
+
+    /// mixes the channels with mute, solo and gain applied
+    fn output(&self, levels: [u8; 5]) -> f32 {
+        Mixer::mix(std::array::from_fn(|i| {
+            levels[i] as f32 * self.effective[i]
+        }))
+    }
+
+    /// mixes a single channel as if the others were silent, ignoring mute and solo
+    fn output_channel(&self, levels: [u8; 5], channel: usize) -> f32 {
+        let mut solo = [0.0; 5];
+        solo[channel] = levels[channel] as f32 * self.gain[channel];
+        Mixer::mix(solo)
+    }
+}
+
+/// Audio processing unit
+///
+/// Holds the sound channels along with the frame counter which clocks their envelopes, sweeps
+/// and length counters at roughly 240Hz. Clocked once per CPU cycle, every tick appends one
+/// sample to a buffer which the frontend drains with take_samples(). Each channel can also be
+/// captured on its own, passed through the mixer as if the others were silent.
+///
+/// - https://www.nesdev.org/wiki/APU
+pub struct Apu {
+    pulse1: Pulse,
+    pulse2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+    cycle: usize,
+    frame_cycle: usize,
+    five_step: bool,
+    irq_inhibit: bool,
+    frame_irq: bool,
+    mixer: Mixer,
+    /// latest output of the cartridge's sound hardware
+    expansion: f32,
+    samples: Vec<f32>,
+    channel_samples: Option<[Vec<f32>; 5]>,
+    peaks: [u8; 5],
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Apu {
+            pulse1: Pulse::new(true),
+            pulse2: Pulse::new(false),
+            triangle: Triangle::new(),
+            noise: Noise::new(),
+            dmc: Dmc::new(),
+            cycle: 0,
+            frame_cycle: 0,
+            five_step: false,
+            irq_inhibit: false,
+            frame_irq: false,
+            mixer: Mixer::new(),
+            expansion: 0.0,
+            samples: Vec::new(),
+            channel_samples: None,
+            peaks: [0; 5],
+        }
+    }
+
+    /// writes to an APU register in $4000-$4013, $4015 or $4017
+    pub fn write_register(&mut self, address: u16, data: u8) {
+        match address {
+            0x4000..=0x4003 => self.pulse1.write(address, data),
+            0x4004..=0x4007 => self.pulse2.write(address, data),
+            0x4008..=0x400b => self.triangle.write(address, data),
+            0x400c..=0x400f => self.noise.write(address, data),
+            0x4010..=0x4013 => self.dmc.write(address, data),
+            0x4015 => {
+                self.pulse1.length.set_enabled(data & 0x01 != 0);
+                self.pulse2.length.set_enabled(data & 0x02 != 0);
+                self.triangle.length.set_enabled(data & 0x04 != 0);
+                self.noise.length.set_enabled(data & 0x08 != 0);
+                self.dmc.set_enabled(data & 0x10 != 0);
+            }
+            0x4017 => {
+                self.five_step = data & 0x80 != 0;
+                self.irq_inhibit = data & 0x40 != 0;
+                if self.irq_inhibit {
+                    self.frame_irq = false;
+                }
+                // writing resets the sequencer, the 5-step mode also clocks every unit at once
+                self.frame_cycle = 0;
+                if self.five_step {
+                    self.clock_quarter_frame();
+                    self.clock_half_frame();
+                }
+            }
+            _ => debug!("ignoring apu write {:02X} @ {:04X}", data, address),
+        }
+    }
+
+    /// reads $4015, returning which channels are active and the interrupt flags
+    ///
+    /// Reading clears the frame interrupt flag but not the DMC one.
+    pub fn read_status(&mut self) -> u8 {
+        let mut status = 0;
+        if self.pulse1.length.active() {
+            status |= 0x01;
+        }
+        if self.pulse2.length.active() {
+            status |= 0x02;
+        }
+        if self.triangle.length.active() {
+            status |= 0x04;
+        }
+        if self.noise.length.active() {
+            status |= 0x08;
+        }
+        if self.dmc.active() {
+            status |= 0x10;
+        }
+        if self.frame_irq {
+            status |= 0x40;
+        }
+        if self.dmc.irq {
+            status |= 0x80;
+        }
+        self.frame_irq = false;
+        status
+    }
+
+    /// returns true while the frame counter or the DMC is asserting the IRQ line
+    pub fn irq(&self) -> bool {
+        self.frame_irq || self.dmc.irq
+    }
+
+    /// returns the address the DMC needs read from the bus, see Dmc::fetch_address()
+    pub fn dmc_fetch_address(&self) -> Option<u16> {
+        self.dmc.fetch_address()
+    }
+
+    /// hands the DMC the byte read from dmc_fetch_address()
+    pub fn dmc_load_sample(&mut self, data: u8) {
+        self.dmc.load_sample(data);
+    }
+
+    /// sets the output of the cartridge's sound hardware, mixed into the following samples
+    pub fn set_expansion_output(&mut self, output: f32) {
+        self.expansion = output;
+    }
+
+    /// advances the APU by one CPU cycle
+    pub fn tick(&mut self) {
+        self.cycle += 1;
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+        if self.cycle % 2 == 0 {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+        self.clock_frame_counter();
+        let levels = self.levels();
+        for (peak, level) in self.peaks.iter_mut().zip(levels) {
+            *peak = (*peak).max(level);
+        }
+        self.samples
+            .push(self.mixer.output(levels) + self.expansion * self.mixer.expansion);
+        if let Some(channel_samples) = &mut self.channel_samples {
+            for (i, samples) in channel_samples.iter_mut().enumerate() {
+                samples.push(self.mixer.output_channel(levels, i));
+            }
+        }
+    }
+
+    fn clock_frame_counter(&mut self) {
+        self.frame_cycle += 1;
+        match (self.frame_cycle, self.five_step) {
+            (STEP_1, _) | (STEP_3, _) => self.clock_quarter_frame(),
+            (STEP_2, _) | (STEP_4, false) | (STEP_5, true) => {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+            }
+            _ => {}
+        }
+        if !self.five_step
+            && !self.irq_inhibit
+            && (STEP_4 - 1..=STEP_4 + 1).contains(&self.frame_cycle)
+        {
+            self.frame_irq = true;
+        }
+        let last = if self.five_step { STEP_5 } else { STEP_4 };
+        if self.frame_cycle > last {
+            self.frame_cycle = 0;
+        }
+    }
+
+    /// clocks envelopes and the triangle's linear counter
+    fn clock_quarter_frame(&mut self) {
+        self.pulse1.envelope.clock();
+        self.pulse2.envelope.clock();
+        self.triangle.clock_linear();
+        self.noise.envelope.clock();
+    }
+
+    /// clocks length counters and sweep units
+    fn clock_half_frame(&mut self) {
+        self.pulse1.length.clock();
+        self.pulse1.clock_sweep();
+        self.pulse2.length.clock();
+        self.pulse2.clock_sweep();
+        self.triangle.length.clock();
+        self.noise.length.clock();
+    }
+
+    /// returns the current output level of each channel, in Channel::ALL order
+    fn levels(&self) -> [u8; 5] {
+        [
+            self.pulse1.output(),
+            self.pulse2.output(),
+            self.triangle.output(),
+            self.noise.output(),
+            self.dmc.output(),
+        ]
+    }
+
+    /// silences or restores a channel
+    pub fn set_muted(&mut self, channel: Channel, muted: bool) {
+        self.mixer.muted[channel as usize] = muted;
+        self.mixer.update();
+    }
+
+    pub fn muted(&self, channel: Channel) -> bool {
+        self.mixer.muted[channel as usize]
+    }
+
+    /// while any channel is soloed only soloed channels are heard
+    pub fn set_solo(&mut self, channel: Channel, solo: bool) {
+        self.mixer.solo[channel as usize] = solo;
+        self.mixer.update();
+    }
+
+    pub fn solo(&self, channel: Channel) -> bool {
+        self.mixer.solo[channel as usize]
+    }
+
+    /// scales a channel's level before mixing, 1.0 is the hardware volume
+    pub fn set_gain(&mut self, channel: Channel, gain: f32) {
+        self.mixer.gain[channel as usize] = gain.max(0.0);
+        self.mixer.update();
+    }
+
+    pub fn gain(&self, channel: Channel) -> f32 {
+        self.mixer.gain[channel as usize]
+    }
+
+    /// returns true if the channel can currently be heard in the mixed output
+    pub fn audible(&self, channel: Channel) -> bool {
+        self.mixer.effective[channel as usize] > 0.0
+    }
+
+    /// returns the samples produced since the last call, one per CPU cycle
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+
+    /// returns the loudest level each channel reached since the last call, in Channel::ALL
+    /// order, for showing channel activity
+    pub fn take_peaks(&mut self) -> [u8; 5] {
+        std::mem::take(&mut self.peaks)
+    }
+
+    /// starts or stops capturing each channel separately
+    pub fn record_channels(&mut self, enabled: bool) {
+        self.channel_samples = if enabled {
+            Some(Default::default())
+        } else {
+            None
+        };
+    }
+
+    /// returns the per channel samples produced since the last call, in Channel::ALL order
+    pub fn take_channel_samples(&mut self) -> Option<[Vec<f32>; 5]> {
+        self.channel_samples.as_mut().map(std::mem::take)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(apu: &mut Apu, cycles: usize) {
+        for _ in 0..cycles {
+            apu.tick();
+        }
+    }
+
+    #[test]
+    fn test_status() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4015, 0x0f);
+        apu.write_register(0x4003, 0x08);
+        apu.write_register(0x400f, 0x08);
+        assert_eq!(apu.read_status(), 0x09);
+        apu.write_register(0x4015, 0x01);
+        assert_eq!(apu.read_status(), 0x01);
+    }
+
+    #[test]
+    fn test_frame_irq() {
+        let mut apu = Apu::new();
+        run(&mut apu, STEP_4 - 2);
+        assert!(!apu.irq());
+        run(&mut apu, 1);
+        assert!(apu.irq());
+        assert_eq!(apu.read_status(), 0x40);
+        assert!(!apu.irq());
+
+        // the 5-step sequence never raises an IRQ
+        apu.write_register(0x4017, 0x80);
+        run(&mut apu, STEP_5 * 2);
+        assert!(!apu.irq());
+    }
+
+    #[test]
+    fn test_length_counter_clocked_by_half_frames() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4015, 0x01);
+        // length index 3 loads a count of 2
+        apu.write_register(0x4003, 0x18);
+        run(&mut apu, STEP_2);
+        assert_eq!(apu.read_status() & 0x01, 0x01);
+        run(&mut apu, STEP_4 - STEP_2);
+        assert_eq!(apu.read_status() & 0x01, 0x00);
+    }
+
+    #[test]
+    fn test_dmc_status() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4010, 0x80);
+        apu.write_register(0x4015, 0x10);
+        assert_eq!(apu.read_status(), 0x10);
+        apu.dmc_load_sample(0);
+        assert_eq!(apu.read_status(), 0x80);
+        assert!(apu.irq());
+        apu.write_register(0x4015, 0x00);
+        assert!(!apu.irq());
+    }
+
+    #[test]
+    fn test_mixer() {
+        assert_eq!(Mixer::mix([0.0; 5]), 0.0);
+        let full = Mixer::mix([15.0, 15.0, 15.0, 15.0, 127.0]);
+        assert!((full - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_mute_solo_gain() {
+        let mut apu = Apu::new();
+        // the silent triangle sits at step 0 which outputs 15
+        let triangle = Mixer::mix([0.0, 0.0, 15.0, 0.0, 0.0]);
+        assert_eq!(apu.mixer.output(apu.levels()), triangle);
+
+        apu.set_muted(Channel::Triangle, true);
+        assert_eq!(apu.mixer.output(apu.levels()), 0.0);
+        apu.set_muted(Channel::Triangle, false);
+
+        apu.set_solo(Channel::Noise, true);
+        assert!(!apu.audible(Channel::Triangle));
+        assert_eq!(apu.mixer.output(apu.levels()), 0.0);
+        apu.set_solo(Channel::Triangle, true);
+        assert_eq!(apu.mixer.output(apu.levels()), triangle);
+        apu.set_solo(Channel::Noise, false);
+        apu.set_solo(Channel::Triangle, false);
+
+        apu.set_gain(Channel::Triangle, 2.0);
+        let louder = Mixer::mix([0.0, 0.0, 30.0, 0.0, 0.0]);
+        assert_eq!(apu.mixer.output(apu.levels()), louder);
+    }
+
+    #[test]
+    fn test_samples() {
+        let mut apu = Apu::new();
+        run(&mut apu, 100);
+        assert_eq!(apu.take_samples().len(), 100);
+        assert!(apu.take_samples().is_empty());
+        assert!(apu.take_channel_samples().is_none());
+        assert_eq!(apu.take_peaks(), [0, 0, 15, 0, 0]);
+        assert_eq!(apu.take_peaks(), [0; 5]);
+    }
+
+    #[test]
+    fn test_expansion_output() {
+        let mut apu = Apu::new();
+        apu.set_muted(Channel::Triangle, true);
+        apu.set_expansion_output(0.25);
+        run(&mut apu, 1);
+        apu.set_solo(Channel::Noise, true);
+        run(&mut apu, 1);
+        assert_eq!(apu.take_samples(), [0.25, 0.0]);
+    }
+
+    #[test]
+    fn test_channel_samples() {
+        let mut apu = Apu::new();
+        apu.record_channels(true);
+        run(&mut apu, 10);
+        let channels = apu.take_channel_samples().unwrap();
+        assert!(channels.iter().all(|samples| samples.len() == 10));
+        assert_eq!(channels[2][0], Mixer::mix([0.0, 0.0, 15.0, 0.0, 0.0]));
+        assert_eq!(channels[0][0], 0.0);
+    }
+}
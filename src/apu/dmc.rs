@@ -0,0 +1,229 @@
+use log::debug;
+
+/// timer periods in CPU cycles indexed by the low 4 bits of $4010
+#[rustfmt::skip]
+const RATE_TABLE: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+
+/// Delta modulation channel at $4010-$4013
+///
+/// Plays 1-bit delta encoded samples read from $C000-$FFFF, each bit moving a 7-bit output
+/// level up or down by 2. The memory reader cannot access the bus itself, instead the bus asks
+/// for fetch_address() after every cycle, halts the CPU while it reads the byte and hands it
+/// back through load_sample(). When a non-looping sample ends it can raise an IRQ.
+///
+/// - https://www.nesdev.org/wiki/APU_DMC
+pub struct Dmc {
+    irq_enabled: bool,
+    looping: bool,
+    period: u16,
+    timer: u16,
+    level: u8,
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    bytes_remaining: u16,
+    buffer: Option<u8>,
+    shift: u8,
+    bits_remaining: u8,
+    silence: bool,
+    pub irq: bool,
+}
+
+impl Dmc {
+    pub fn new() -> Self {
+        Dmc {
+            irq_enabled: false,
+            looping: false,
+            period: RATE_TABLE[0],
+            timer: 0,
+            level: 0,
+            sample_address: 0xc000,
+            sample_length: 1,
+            current_address: 0xc000,
+            bytes_remaining: 0,
+            buffer: None,
+            shift: 0,
+            bits_remaining: 8,
+            silence: true,
+            irq: false,
+        }
+    }
+
+    /// writes one of the four registers, selected by the low two address bits
+    pub fn write(&mut self, register: u16, data: u8) {
+        match register & 0b11 {
+            0 => {
+                self.irq_enabled = data & 0x80 != 0;
+                self.looping = data & 0x40 != 0;
+                self.period = RATE_TABLE[(data & 0x0f) as usize];
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+            }
+            1 => self.level = data & 0x7f,
+            2 => self.sample_address = 0xc000 + data as u16 * 64,
+            _ => self.sample_length = data as u16 * 16 + 1,
+        }
+    }
+
+    /// enables or disables the channel through $4015
+    ///
+    /// Enabling restarts the sample only if the previous one finished, disabling lets the
+    /// bits already in the buffer play out. Either way the DMC interrupt is acknowledged.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    pub fn active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    /// returns the address the memory reader wants to fetch, if the sample buffer is empty
+    pub fn fetch_address(&self) -> Option<u16> {
+        if self.buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.current_address)
+        } else {
+            None
+        }
+    }
+
+    /// fills the sample buffer with the byte read from fetch_address()
+    pub fn load_sample(&mut self, data: u8) {
+        self.buffer = Some(data);
+        // the address wraps around to $8000 rather than $0000
+        self.current_address = self.current_address.checked_add(1).unwrap_or(0x8000);
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                debug!("dmc irq");
+                self.irq = true;
+            }
+        }
+    }
+
+    /// clocked every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period - 1;
+
+        if !self.silence {
+            if self.shift & 1 != 0 {
+                if self.level <= 125 {
+                    self.level += 2;
+                }
+            } else if self.level >= 2 {
+                self.level -= 2;
+            }
+        }
+        self.shift >>= 1;
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.buffer.take() {
+                Some(data) => {
+                    self.silence = false;
+                    self.shift = data;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    /// returns the current level, 0-127
+    pub fn output(&self) -> u8 {
+        self.level
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// plays a sample through to the end, returning the addresses fetched
+    fn play(dmc: &mut Dmc) -> Vec<u16> {
+        let mut fetched = vec![];
+        while let Some(address) = dmc.fetch_address() {
+            fetched.push(address);
+            dmc.load_sample(0xff);
+            dmc.buffer = None;
+            if fetched.len() > 100 {
+                break;
+            }
+        }
+        fetched
+    }
+
+    #[test]
+    fn test_sample_fetch() {
+        let mut dmc = Dmc::new();
+        dmc.write(0, 0x80);
+        dmc.write(2, 0xff);
+        dmc.write(3, 0x01);
+        dmc.set_enabled(true);
+        assert_eq!(dmc.fetch_address(), Some(0xffc0));
+        dmc.load_sample(0x00);
+        assert_eq!(dmc.fetch_address(), None);
+        dmc.buffer = None;
+        let fetched = play(&mut dmc);
+        assert_eq!(fetched.len(), 16);
+        assert_eq!(fetched[0], 0xffc1);
+        assert_eq!(fetched[15], 0xffd0);
+        assert!(dmc.irq);
+        dmc.set_enabled(false);
+        assert!(!dmc.irq);
+    }
+
+    #[test]
+    fn test_address_wraps_to_8000() {
+        let mut dmc = Dmc::new();
+        dmc.current_address = 0xffff;
+        dmc.bytes_remaining = 2;
+        dmc.load_sample(0);
+        assert_eq!(dmc.current_address, 0x8000);
+    }
+
+    #[test]
+    fn test_loop() {
+        let mut dmc = Dmc::new();
+        dmc.write(0, 0xc0);
+        dmc.set_enabled(true);
+        dmc.load_sample(0);
+        assert!(dmc.active());
+        assert!(!dmc.irq);
+    }
+
+    #[test]
+    fn test_output_level() {
+        let mut dmc = Dmc::new();
+        dmc.write(0, 0x0f);
+        dmc.write(1, 0x40);
+        dmc.set_enabled(true);
+        dmc.load_sample(0b0000_0011);
+        // the first byte is picked up once the empty shift register runs out of bits
+        for _ in 0..8 * 54 {
+            dmc.clock_timer();
+        }
+        assert_eq!(dmc.output(), 0x40);
+        for _ in 0..3 * 54 {
+            dmc.clock_timer();
+        }
+        assert_eq!(dmc.output(), 0x42);
+    }
+}
@@ -0,0 +1,130 @@
+/// lengths loaded into a length counter indexed by the upper 5 bits of $4003/$4007/$400B/$400F
+#[rustfmt::skip]
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+    12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+/// Volume envelope shared by the pulse and noise channels
+///
+/// Either outputs a constant volume or a sawtooth decaying from 15 to 0 at a rate set by the
+/// divider period, optionally looping.
+///
+/// - https://www.nesdev.org/wiki/APU_Envelope
+#[derive(Default)]
+pub struct Envelope {
+    start: bool,
+    looping: bool,
+    constant: bool,
+    volume: u8,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    /// writes the --LC VVVV bits of the channel's first register
+    pub fn write(&mut self, data: u8) {
+        self.looping = data & 0x20 != 0;
+        self.constant = data & 0x10 != 0;
+        self.volume = data & 0x0f;
+    }
+
+    /// restarts the envelope, done when the channel's length is reloaded
+    pub fn restart(&mut self) {
+        self.start = true;
+    }
+
+    /// clocked by the frame counter every quarter frame
+    pub fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.constant {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+}
+
+/// Length counter which silences a channel once it counts down to zero
+///
+/// - https://www.nesdev.org/wiki/APU_Length_Counter
+#[derive(Default)]
+pub struct LengthCounter {
+    pub counter: u8,
+    pub halt: bool,
+    enabled: bool,
+}
+
+impl LengthCounter {
+    /// loads the counter from the 5-bit index written to the channel's last register
+    pub fn load(&mut self, index: u8) {
+        if self.enabled {
+            self.counter = LENGTH_TABLE[(index & 0x1f) as usize];
+        }
+    }
+
+    /// enables or disables the channel through $4015, disabling clears the counter
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    /// clocked by the frame counter every half frame
+    pub fn clock(&mut self) {
+        if self.counter > 0 && !self.halt {
+            self.counter -= 1;
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        self.counter > 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_envelope_decay() {
+        let mut envelope = Envelope::default();
+        envelope.write(0x01);
+        envelope.restart();
+        envelope.clock();
+        assert_eq!(envelope.output(), 15);
+        envelope.clock();
+        envelope.clock();
+        assert_eq!(envelope.output(), 14);
+    }
+
+    #[test]
+    fn test_length_counter() {
+        let mut length = LengthCounter::default();
+        length.load(1);
+        assert!(!length.active());
+        length.set_enabled(true);
+        length.load(3);
+        assert_eq!(length.counter, 2);
+        length.clock();
+        length.clock();
+        assert!(!length.active());
+    }
+}
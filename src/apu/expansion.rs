@@ -0,0 +1,34 @@
+mod fds;
+mod mmc5;
+mod n163;
+mod sunsoft5b;
+mod vrc6;
+
+pub use fds::FdsAudio;
+pub use mmc5::Mmc5Audio;
+pub use n163::N163Audio;
+pub use sunsoft5b::Sunsoft5bAudio;
+pub use vrc6::Vrc6Audio;
+
+/// Sound hardware on a cartridge or the Famicom Disk System
+///
+/// The Famicom routes the audio signal through the cartridge, letting boards mix in their
+/// own channels. Chips see every CPU write to the cartridge and decode the addresses they
+/// care about themselves, so a board can forward writes without knowing the chip's layout.
+/// Outputs are scaled so a channel at full volume is roughly as loud as it is next to the
+/// APU on hardware, where one APU pulse at full volume is about 0.15.
+pub trait ExpansionAudio {
+    /// handles a CPU write anywhere in $4020-$FFFF
+    fn write(&mut self, address: u16, data: u8);
+
+    /// handles a CPU read, returning None for addresses the chip doesn't drive
+    fn read(&mut self, address: u16) -> Option<u8> {
+        None
+    }
+
+    /// clocked once per CPU cycle
+    fn tick(&mut self);
+
+    /// returns the chip's contribution to the mixed output
+    fn output(&self) -> f32;
+}
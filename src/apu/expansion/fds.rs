@@ -0,0 +1,288 @@
+use crate::apu::expansion::ExpansionAudio;
+
+/// output at the largest wave sample and full gain, about 2.4 times an APU pulse
+const SCALE: f32 = 0.36 / (63.0 * 32.0);
+
+/// master volume divisors, selected by the low bits of $4089
+const MASTER_VOLUME: [f32; 4] = [1.0, 2.0 / 3.0, 2.0 / 4.0, 2.0 / 5.0];
+
+/// change to the modulation counter for each modulation table entry, None resets it
+const MOD_ADJUST: [Option<i8>; 8] = [
+    Some(0),
+    Some(1),
+    Some(2),
+    Some(4),
+    None,
+    Some(-4),
+    Some(-2),
+    Some(-1),
+];
+
+/// Volume or modulation gain envelope of the FDS
+#[derive(Default)]
+struct FdsEnvelope {
+    /// sets the gain directly instead of ramping
+    direct: bool,
+    increase: bool,
+    speed: u8,
+    gain: u8,
+    timer: usize,
+}
+
+impl FdsEnvelope {
+    /// writes the MDVV VVVV bits of $4080 or $4084
+    fn write(&mut self, data: u8) {
+        self.direct = data & 0x80 != 0;
+        self.increase = data & 0x40 != 0;
+        self.speed = data & 0x3f;
+        self.timer = 0;
+        if self.direct {
+            self.gain = self.speed;
+        }
+    }
+
+    /// clocked every CPU cycle, the gain moves one step every 8 * (master + 1) * (speed + 1)
+    fn tick(&mut self, master_speed: u8) {
+        if self.direct || master_speed == 0 {
+            return;
+        }
+        self.timer += 1;
+        if self.timer < 8 * (master_speed as usize + 1) * (self.speed as usize + 1) {
+            return;
+        }
+        self.timer = 0;
+        if self.increase && self.gain < 32 {
+            self.gain += 1;
+        } else if !self.increase && self.gain > 0 {
+            self.gain -= 1;
+        }
+    }
+}
+
+/// Famicom Disk System sound, a single 64 step wavetable channel with frequency modulation
+///
+/// The 6-bit wave is written to $4040-$407F while $4089 bit 7 is set. A second table of 3-bit
+/// steps written to $4088 drives a modulation counter that bends the wave's pitch, scaled by
+/// the modulation envelope.
+///
+/// - https://www.nesdev.org/wiki/FDS_audio
+pub struct FdsAudio {
+    wave: [u8; 64],
+    wave_write: bool,
+    wave_halt: bool,
+    wave_accumulator: u32,
+    wave_position: usize,
+    frequency: u16,
+    master_volume: u8,
+    envelope_halt: bool,
+    envelope_speed: u8,
+    volume: FdsEnvelope,
+    mod_envelope: FdsEnvelope,
+    mod_table: [u8; 64],
+    mod_position: usize,
+    mod_halt: bool,
+    mod_accumulator: u32,
+    mod_frequency: u16,
+    /// 7-bit signed
+    mod_counter: i8,
+}
+
+impl FdsAudio {
+    pub fn new() -> Self {
+        FdsAudio {
+            wave: [0; 64],
+            wave_write: false,
+            wave_halt: true,
+            wave_accumulator: 0,
+            wave_position: 0,
+            frequency: 0,
+            master_volume: 0,
+            envelope_halt: false,
+            envelope_speed: 0xe8,
+            volume: FdsEnvelope::default(),
+            mod_envelope: FdsEnvelope::default(),
+            mod_table: [0; 64],
+            mod_position: 0,
+            mod_halt: true,
+            mod_accumulator: 0,
+            mod_frequency: 0,
+            mod_counter: 0,
+        }
+    }
+
+    /// returns the wave frequency bent by the modulation unit
+    fn pitch(&self) -> u32 {
+        let counter = self.mod_counter as i32;
+        let mut temp = counter * self.mod_envelope.gain as i32;
+        let remainder = temp & 0x0f;
+        temp >>= 4;
+        if remainder > 0 && temp & 0x80 == 0 {
+            temp += if counter < 0 { -1 } else { 2 };
+        }
+        if temp >= 192 {
+            temp -= 256;
+        } else if temp < -64 {
+            temp += 256;
+        }
+        let mut temp = self.frequency as i32 * temp;
+        let remainder = temp & 0x3f;
+        temp >>= 6;
+        if remainder >= 32 {
+            temp += 1;
+        }
+        (self.frequency as i32 + temp).max(0) as u32
+    }
+
+    fn clock_modulation(&mut self) {
+        if self.mod_halt {
+            return;
+        }
+        self.mod_accumulator += self.mod_frequency as u32;
+        if self.mod_accumulator < 0x10000 {
+            return;
+        }
+        self.mod_accumulator &= 0xffff;
+        self.mod_counter = match MOD_ADJUST[self.mod_table[self.mod_position] as usize] {
+            Some(adjust) => {
+                // wraps around as a 7-bit value
+                ((self.mod_counter + adjust) << 1) >> 1
+            }
+            None => 0,
+        };
+        self.mod_position = (self.mod_position + 1) % self.mod_table.len();
+    }
+}
+
+impl ExpansionAudio for FdsAudio {
+    fn write(&mut self, address: u16, data: u8) {
+        match address {
+            0x4040..=0x407f if self.wave_write => {
+                self.wave[address as usize - 0x4040] = data & 0x3f;
+            }
+            0x4080 => self.volume.write(data),
+            0x4082 => self.frequency = (self.frequency & 0x0f00) | data as u16,
+            0x4083 => {
+                self.frequency = (self.frequency & 0x00ff) | ((data & 0x0f) as u16) << 8;
+                self.wave_halt = data & 0x80 != 0;
+                self.envelope_halt = data & 0x40 != 0;
+                if self.wave_halt {
+                    self.wave_accumulator = 0;
+                    self.wave_position = 0;
+                }
+            }
+            0x4084 => self.mod_envelope.write(data),
+            0x4085 => self.mod_counter = ((data << 1) as i8) >> 1,
+            0x4086 => self.mod_frequency = (self.mod_frequency & 0x0f00) | data as u16,
+            0x4087 => {
+                self.mod_frequency = (self.mod_frequency & 0x00ff) | ((data & 0x0f) as u16) << 8;
+                self.mod_halt = data & 0x80 != 0;
+                if self.mod_halt {
+                    self.mod_accumulator = 0;
+                }
+            }
+            0x4088 if self.mod_halt => {
+                // each write fills a pair of entries, modulation can stop halfway through one
+                let position = self.mod_position & !1;
+                self.mod_table[position] = data & 0x07;
+                self.mod_table[position + 1] = data & 0x07;
+                self.mod_position = (position + 2) % self.mod_table.len();
+            }
+            0x4089 => {
+                self.wave_write = data & 0x80 != 0;
+                self.master_volume = data & 0x03;
+            }
+            0x408a => self.envelope_speed = data,
+            _ => {}
+        }
+    }
+
+    fn read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            0x4040..=0x407f => Some(self.wave[address as usize - 0x4040]),
+            0x4090 => Some(self.volume.gain),
+            0x4092 => Some(self.mod_envelope.gain),
+            _ => None,
+        }
+    }
+
+    fn tick(&mut self) {
+        if !self.wave_halt && !self.envelope_halt {
+            self.volume.tick(self.envelope_speed);
+            self.mod_envelope.tick(self.envelope_speed);
+        }
+        self.clock_modulation();
+        if self.wave_halt || self.wave_write {
+            return;
+        }
+        self.wave_accumulator = (self.wave_accumulator + self.pitch()) & 0x3f_ffff;
+        self.wave_position = (self.wave_accumulator >> 16) as usize;
+    }
+
+    fn output(&self) -> f32 {
+        let gain = self.volume.gain.min(32) as f32;
+        let level = self.wave[self.wave_position] as f32 * gain;
+        level * MASTER_VOLUME[self.master_volume as usize] * SCALE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wave_playback() {
+        let mut fds = FdsAudio::new();
+        fds.write(0x4089, 0x80);
+        for i in 0..64 {
+            fds.write(0x4040 + i, i as u8);
+        }
+        assert_eq!(fds.read(0x407f), Some(63));
+        fds.write(0x4089, 0x00);
+        fds.write(0x4080, 0x80 | 32);
+        // one wave step every 32 cycles
+        fds.write(0x4082, 0x00);
+        fds.write(0x4083, 0x08);
+        for _ in 0..32 * 10 {
+            fds.tick();
+        }
+        assert_eq!(fds.wave_position, 10);
+        assert!((fds.output() - 10.0 * 32.0 * SCALE).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_modulation() {
+        let mut fds = FdsAudio::new();
+        fds.write(0x4087, 0x80);
+        // the whole table is written, leaving the position back at the start
+        for i in 0..32 {
+            fds.write(0x4088, [1, 1, 7, 4].get(i).copied().unwrap_or(0));
+        }
+        // one modulation step every 32 cycles
+        fds.write(0x4086, 0x00);
+        fds.write(0x4087, 0x08);
+        let counters: Vec<i8> = (0..8)
+            .map(|_| {
+                for _ in 0..32 {
+                    fds.tick();
+                }
+                fds.mod_counter
+            })
+            .collect();
+        assert_eq!(counters, [1, 2, 3, 4, 3, 2, 0, 0]);
+    }
+
+    #[test]
+    fn test_modulation_table_write_after_odd_halt() {
+        let mut fds = FdsAudio::new();
+        fds.write(0x4086, 0x00);
+        fds.write(0x4087, 0x08);
+        for _ in 0..32 * 63 {
+            fds.tick();
+        }
+        fds.write(0x4087, 0x80);
+        assert_eq!(fds.mod_position, 63);
+        fds.write(0x4088, 0x03);
+        assert_eq!(fds.mod_table[62..], [3, 3]);
+        assert_eq!(fds.mod_position, 0);
+    }
+}
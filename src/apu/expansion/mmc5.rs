@@ -0,0 +1,112 @@
+use crate::apu::expansion::ExpansionAudio;
+use crate::apu::pulse::Pulse;
+
+/// CPU cycles between the MMC5's envelope and length counter clocks, a fixed 240Hz
+const FRAME_PERIOD: usize = 7457;
+
+/// output of the PCM channel at full level, about the DMC's range
+const PCM_SCALE: f32 = 0.42 / 255.0;
+
+/// Nintendo MMC5 sound: two pulse channels and an 8-bit PCM channel
+///
+/// The pulses at $5000-$5007 are copies of the APU's without the sweep units, with their
+/// envelopes and length counters clocked together at 240Hz rather than by the APU's frame
+/// counter. The PCM channel is only implemented in write mode, where the level is written to
+/// $5011 directly.
+///
+/// - https://www.nesdev.org/wiki/MMC5_audio
+pub struct Mmc5Audio {
+    pulse: [Pulse; 2],
+    pcm: u8,
+    cycle: usize,
+}
+
+impl Mmc5Audio {
+    pub fn new() -> Self {
+        Mmc5Audio {
+            pulse: [Pulse::without_sweep(), Pulse::without_sweep()],
+            pcm: 0,
+            cycle: 0,
+        }
+    }
+}
+
+impl ExpansionAudio for Mmc5Audio {
+    fn write(&mut self, address: u16, data: u8) {
+        match address {
+            0x5000..=0x5003 => self.pulse[0].write(address, data),
+            0x5004..=0x5007 => self.pulse[1].write(address, data),
+            0x5011 if data != 0 => self.pcm = data,
+            0x5015 => {
+                self.pulse[0].length.set_enabled(data & 0x01 != 0);
+                self.pulse[1].length.set_enabled(data & 0x02 != 0);
+            }
+            _ => {}
+        }
+    }
+
+    fn read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            0x5015 => Some(
+                self.pulse[0].length.active() as u8 | (self.pulse[1].length.active() as u8) << 1,
+            ),
+            _ => None,
+        }
+    }
+
+    fn tick(&mut self) {
+        self.cycle += 1;
+        if self.cycle % 2 == 0 {
+            self.pulse[0].clock_timer();
+            self.pulse[1].clock_timer();
+        }
+        if self.cycle % FRAME_PERIOD == 0 {
+            for pulse in &mut self.pulse {
+                pulse.envelope.clock();
+                pulse.length.clock();
+            }
+        }
+    }
+
+    fn output(&self) -> f32 {
+        let pulse = (self.pulse[0].output() + self.pulse[1].output()) as f32;
+        let pulse = if pulse > 0.0 {
+            95.88 / (8128.0 / pulse + 100.0)
+        } else {
+            0.0
+        };
+        pulse + self.pcm as f32 * PCM_SCALE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_length_status() {
+        let mut mmc5 = Mmc5Audio::new();
+        mmc5.write(0x5015, 0x01);
+        mmc5.write(0x5000, 0xbf);
+        mmc5.write(0x5002, 0x10);
+        // length index 1 loads 254
+        mmc5.write(0x5003, 0x08);
+        assert_eq!(mmc5.read(0x5015), Some(0x01));
+        for _ in 0..16 {
+            mmc5.tick();
+        }
+        assert!(mmc5.output() > 0.0);
+        mmc5.write(0x5015, 0x00);
+        assert_eq!(mmc5.read(0x5015), Some(0x00));
+    }
+
+    #[test]
+    fn test_pcm() {
+        let mut mmc5 = Mmc5Audio::new();
+        mmc5.write(0x5011, 0xff);
+        assert!((mmc5.output() - 0.42).abs() < 1e-6);
+        // zero is ignored in write mode, it ends samples in read mode
+        mmc5.write(0x5011, 0x00);
+        assert!((mmc5.output() - 0.42).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,158 @@
+use crate::apu::expansion::ExpansionAudio;
+
+/// CPU cycles spent updating each channel
+const CHANNEL_CYCLES: usize = 15;
+
+/// output per level step, channels are time multiplexed so the average is used
+const SCALE: f32 = 0.15 / 15.0 / 4.0;
+
+/// Namco 163 wavetable sound
+///
+/// Up to 8 channels play 4-bit waveforms stored in 128 bytes of internal RAM, which also
+/// holds each channel's registers at $40-$7F. The RAM is accessed through an address port at
+/// $F800, with auto increment in bit 7, and a data port at $4800. Only one channel is updated
+/// every 15 CPU cycles and the hardware outputs them in turn; that multiplexing is replaced by
+/// averaging the enabled channels, which avoids the whine it makes with many channels.
+///
+/// - https://www.nesdev.org/wiki/Namco_163_audio
+pub struct N163Audio {
+    ram: [u8; 0x80],
+    address: u8,
+    auto_increment: bool,
+    /// latest output of each channel, -8..7 times the volume
+    outputs: [i16; 8],
+    current: usize,
+    cycle: usize,
+}
+
+impl N163Audio {
+    pub fn new() -> Self {
+        N163Audio {
+            ram: [0; 0x80],
+            address: 0,
+            auto_increment: false,
+            outputs: [0; 8],
+            current: 0,
+            cycle: 0,
+        }
+    }
+
+    /// number of enabled channels, set by bits 4-6 of $7F, counting down from channel 8
+    fn channels(&self) -> usize {
+        ((self.ram[0x7f] >> 4) & 0x07) as usize + 1
+    }
+
+    /// returns the 4-bit sample at a nibble address, low nibble first
+    fn sample(&self, address: usize) -> u8 {
+        let byte = self.ram[(address / 2) % self.ram.len()];
+        if address % 2 == 0 {
+            byte & 0x0f
+        } else {
+            byte >> 4
+        }
+    }
+
+    /// advances the phase of one channel and updates its output
+    fn update_channel(&mut self, channel: usize) {
+        let base = 0x40 + channel * 8;
+        let registers = &self.ram[base..base + 8];
+        let frequency = registers[0] as u32 | (registers[2] as u32) << 8;
+        let frequency = frequency | ((registers[4] & 0x03) as u32) << 16;
+        let phase = registers[1] as u32 | (registers[3] as u32) << 8 | (registers[5] as u32) << 16;
+        let length = 256 - (registers[4] & 0xfc) as u32;
+        let offset = registers[6] as u32;
+        let volume = (registers[7] & 0x0f) as i16;
+
+        let phase = (phase + frequency) % (length << 16);
+        let sample = self.sample(((phase >> 16) + offset) as usize);
+        self.outputs[channel] = (sample as i16 - 8) * volume;
+
+        self.ram[base + 1] = phase as u8;
+        self.ram[base + 3] = (phase >> 8) as u8;
+        self.ram[base + 5] = (phase >> 16) as u8;
+    }
+}
+
+impl ExpansionAudio for N163Audio {
+    fn write(&mut self, address: u16, data: u8) {
+        match address & 0xf800 {
+            0x4800 => {
+                self.ram[self.address as usize] = data;
+                if self.auto_increment {
+                    self.address = (self.address + 1) & 0x7f;
+                }
+            }
+            0xf800 => {
+                self.address = data & 0x7f;
+                self.auto_increment = data & 0x80 != 0;
+            }
+            _ => {}
+        }
+    }
+
+    fn read(&mut self, address: u16) -> Option<u8> {
+        if address & 0xf800 != 0x4800 {
+            return None;
+        }
+        let data = self.ram[self.address as usize];
+        if self.auto_increment {
+            self.address = (self.address + 1) & 0x7f;
+        }
+        Some(data)
+    }
+
+    fn tick(&mut self) {
+        self.cycle += 1;
+        if self.cycle % CHANNEL_CYCLES != 0 {
+            return;
+        }
+        let channels = self.channels();
+        self.current = (self.current + 1) % channels;
+        self.update_channel(7 - self.current);
+    }
+
+    fn output(&self) -> f32 {
+        let channels = self.channels();
+        let sum: i16 = self.outputs[8 - channels..].iter().sum();
+        sum as f32 / channels as f32 * SCALE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ram_ports() {
+        let mut n163 = N163Audio::new();
+        n163.write(0xf800, 0x80 | 0x10);
+        n163.write(0x4800, 0x12);
+        n163.write(0x4800, 0x34);
+        n163.write(0xf800, 0x80 | 0x10);
+        assert_eq!(n163.read(0x4800), Some(0x12));
+        assert_eq!(n163.read(0x4800), Some(0x34));
+        assert_eq!(n163.read(0x5000), None);
+    }
+
+    #[test]
+    fn test_wave_playback() {
+        let mut n163 = N163Audio::new();
+        // a 4 sample wave of 0, 15, 8, 4 played by channel 8, one sample per update
+        n163.write(0xf800, 0x80);
+        n163.write(0x4800, 0xf0);
+        n163.write(0x4800, 0x48);
+        n163.write(0xf800, 0x80 | 0x78);
+        for data in [0x00, 0x00, 0x00, 0x00, 0xfd, 0x00, 0x00, 0x0f] {
+            n163.write(0x4800, data);
+        }
+        let outputs: Vec<i16> = (0..4)
+            .map(|_| {
+                for _ in 0..CHANNEL_CYCLES {
+                    n163.tick();
+                }
+                n163.outputs[7]
+            })
+            .collect();
+        assert_eq!(outputs, [7 * 15, 0, -4 * 15, -8 * 15]);
+    }
+}
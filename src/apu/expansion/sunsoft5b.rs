@@ -0,0 +1,241 @@
+use crate::apu::expansion::ExpansionAudio;
+
+/// CPU cycles per tick of the tone and envelope counters
+const PRESCALER: usize = 16;
+
+/// output of a channel at full volume
+const SCALE: f32 = 0.2;
+
+/// envelope shape bits written to register $0D
+const CONTINUE: u8 = 0x08;
+const ATTACK: u8 = 0x04;
+const ALTERNATE: u8 = 0x02;
+const HOLD: u8 = 0x01;
+
+/// Sunsoft 5B sound, a licensed Yamaha YM2149F which is compatible with the AY-3-8910
+///
+/// Three square wave channels which can each mix in a shared noise generator, with volumes
+/// set directly or by the shared envelope generator. Registers are selected by writing their
+/// number to $C000 and written through $E000. Volumes are logarithmic, 3dB per step of the
+/// 4-bit volume and 1.5dB per step of the 5-bit envelope.
+///
+/// - https://www.nesdev.org/wiki/Sunsoft_5B_audio
+pub struct Sunsoft5bAudio {
+    registers: [u8; 16],
+    selected: u8,
+    /// amplitude for each 5-bit level
+    amplitudes: [f32; 32],
+    prescaler: usize,
+    tone_counters: [u16; 3],
+    tone_outputs: [bool; 3],
+    noise_counter: u8,
+    noise_divider: bool,
+    noise_shift: u32,
+    envelope_counter: u16,
+    envelope_step: u8,
+    envelope_attack: bool,
+    envelope_holding: bool,
+}
+
+impl Sunsoft5bAudio {
+    pub fn new() -> Self {
+        Sunsoft5bAudio {
+            registers: [0; 16],
+            selected: 0,
+            amplitudes: std::array::from_fn(|level| {
+                if level == 0 {
+                    0.0
+                } else {
+                    10f32.powf((level as f32 - 31.0) * 1.5 / 20.0)
+                }
+            }),
+            prescaler: 0,
+            tone_counters: [0; 3],
+            tone_outputs: [false; 3],
+            noise_counter: 0,
+            noise_divider: false,
+            noise_shift: 1,
+            envelope_counter: 0,
+            envelope_step: 0,
+            envelope_attack: false,
+            envelope_holding: false,
+        }
+    }
+
+    fn tone_period(&self, channel: usize) -> u16 {
+        let period = self.registers[channel * 2] as u16
+            | ((self.registers[channel * 2 + 1] & 0x0f) as u16) << 8;
+        period.max(1)
+    }
+
+    fn write_register(&mut self, register: u8, data: u8) {
+        self.registers[register as usize] = data;
+        if register == 0x0d {
+            self.envelope_step = 0;
+            self.envelope_attack = data & ATTACK != 0;
+            self.envelope_holding = false;
+        }
+    }
+
+    fn clock_noise(&mut self) {
+        // the noise runs at half the rate of the tones
+        self.noise_divider = !self.noise_divider;
+        if self.noise_divider {
+            return;
+        }
+        self.noise_counter += 1;
+        if self.noise_counter >= (self.registers[6] & 0x1f).max(1) {
+            self.noise_counter = 0;
+            let feedback = (self.noise_shift ^ (self.noise_shift >> 3)) & 1;
+            self.noise_shift = (self.noise_shift >> 1) | feedback << 16;
+        }
+    }
+
+    fn clock_envelope(&mut self) {
+        let period = (self.registers[0x0b] as u16 | (self.registers[0x0c] as u16) << 8).max(1);
+        self.envelope_counter += 1;
+        if self.envelope_counter < period {
+            return;
+        }
+        self.envelope_counter = 0;
+        if self.envelope_holding {
+            return;
+        }
+        if self.envelope_step < 31 {
+            self.envelope_step += 1;
+            return;
+        }
+        let shape = self.registers[0x0d];
+        if shape & CONTINUE == 0 {
+            // one shot shapes end silent
+            self.envelope_attack = false;
+            self.envelope_holding = true;
+        } else {
+            if shape & ALTERNATE != 0 {
+                self.envelope_attack = !self.envelope_attack;
+            }
+            if shape & HOLD != 0 {
+                self.envelope_holding = true;
+            } else {
+                self.envelope_step = 0;
+            }
+        }
+    }
+
+    fn envelope_level(&self) -> u8 {
+        if self.envelope_attack {
+            self.envelope_step
+        } else {
+            31 - self.envelope_step
+        }
+    }
+
+    /// returns the 5-bit level of a channel, before the tone and noise gate it
+    fn level(&self, channel: usize) -> u8 {
+        let volume = self.registers[8 + channel];
+        if volume & 0x10 != 0 {
+            self.envelope_level()
+        } else if volume & 0x0f == 0 {
+            0
+        } else {
+            (volume & 0x0f) * 2 + 1
+        }
+    }
+}
+
+impl ExpansionAudio for Sunsoft5bAudio {
+    fn write(&mut self, address: u16, data: u8) {
+        match address & 0xe000 {
+            0xc000 => self.selected = data & 0x0f,
+            0xe000 => self.write_register(self.selected, data),
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self) {
+        self.prescaler += 1;
+        if self.prescaler < PRESCALER {
+            return;
+        }
+        self.prescaler = 0;
+        for channel in 0..3 {
+            self.tone_counters[channel] += 1;
+            if self.tone_counters[channel] >= self.tone_period(channel) {
+                self.tone_counters[channel] = 0;
+                self.tone_outputs[channel] = !self.tone_outputs[channel];
+            }
+        }
+        self.clock_noise();
+        self.clock_envelope();
+    }
+
+    fn output(&self) -> f32 {
+        let mixer = self.registers[7];
+        let noise = self.noise_shift & 1 != 0;
+        let sum: f32 = (0..3)
+            .filter(|&channel| {
+                let tone = self.tone_outputs[channel] || mixer & (1 << channel) != 0;
+                let noise = noise || mixer & (8 << channel) != 0;
+                tone && noise
+            })
+            .map(|channel| self.amplitudes[self.level(channel) as usize])
+            .sum();
+        sum * SCALE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn write(chip: &mut Sunsoft5bAudio, register: u8, data: u8) {
+        chip.write(0xc000, register);
+        chip.write(0xe000, data);
+    }
+
+    #[test]
+    fn test_tone() {
+        let mut chip = Sunsoft5bAudio::new();
+        write(&mut chip, 0, 2);
+        write(&mut chip, 7, 0x3e);
+        write(&mut chip, 8, 0x0f);
+        let mut toggles = 0;
+        let mut last = chip.output();
+        let mut peak: f32 = 0.0;
+        for _ in 0..PRESCALER * 8 {
+            chip.tick();
+            let output = chip.output();
+            if output != last {
+                toggles += 1;
+            }
+            last = output;
+            peak = peak.max(output);
+        }
+        // a period of 2 toggles every 32 CPU cycles
+        assert_eq!(toggles, 4);
+        assert!((peak - SCALE).abs() < 1e-6, "{}", peak);
+    }
+
+    #[rstest]
+    #[case(0x08, [31, 0, 31])]
+    #[case(0x0a, [31, 0, 0])]
+    #[case(0x0d, [0, 31, 31])]
+    #[case(0x0e, [0, 31, 31])]
+    #[case(0x0f, [0, 31, 0])]
+    #[case(0x00, [31, 0, 0])]
+    fn test_envelope_shapes(#[case] shape: u8, #[case] expected: [u8; 3]) {
+        let mut chip = Sunsoft5bAudio::new();
+        write(&mut chip, 0x0b, 1);
+        write(&mut chip, 0x0d, shape);
+        // levels at the start, end and just after the end of the first cycle
+        let mut levels = [chip.envelope_level(), 0, 0];
+        for _ in 0..31 {
+            chip.clock_envelope();
+        }
+        levels[1] = chip.envelope_level();
+        chip.clock_envelope();
+        levels[2] = chip.envelope_level();
+        assert_eq!(levels, expected);
+    }
+}
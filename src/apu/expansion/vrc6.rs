@@ -0,0 +1,189 @@
+use crate::apu::expansion::ExpansionAudio;
+
+/// output per volume step, a VRC6 pulse is about as loud as an APU pulse
+const SCALE: f32 = 0.15 / 15.0;
+
+/// VRC6 pulse channel with 8 duty cycles and a 4-bit volume
+#[derive(Default)]
+struct Vrc6Pulse {
+    volume: u8,
+    duty: u8,
+    /// ignores the duty and outputs the volume constantly, used for PCM
+    digitized: bool,
+    enabled: bool,
+    period: u16,
+    timer: u16,
+    step: u8,
+}
+
+impl Vrc6Pulse {
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.digitized = data & 0x80 != 0;
+                self.duty = (data >> 4) & 0x07;
+                self.volume = data & 0x0f;
+            }
+            1 => self.period = (self.period & 0x0f00) | data as u16,
+            _ => {
+                self.period = (self.period & 0x00ff) | ((data as u16 & 0x0f) << 8);
+                self.enabled = data & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 15;
+                }
+            }
+        }
+    }
+
+    fn tick(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = self.step.wrapping_sub(1) & 0x0f;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && (self.digitized || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+}
+
+/// VRC6 sawtooth, an accumulator that adds the rate every other clock and resets after 7 adds
+#[derive(Default)]
+struct Vrc6Saw {
+    rate: u8,
+    enabled: bool,
+    period: u16,
+    timer: u16,
+    step: u8,
+    accumulator: u8,
+}
+
+impl Vrc6Saw {
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => self.rate = data & 0x3f,
+            1 => self.period = (self.period & 0x0f00) | data as u16,
+            _ => {
+                self.period = (self.period & 0x00ff) | ((data as u16 & 0x0f) << 8);
+                self.enabled = data & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            }
+        }
+    }
+
+    fn tick(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period;
+        self.step += 1;
+        if self.step == 14 {
+            self.step = 0;
+            self.accumulator = 0;
+        } else if self.step % 2 == 0 {
+            self.accumulator = self.accumulator.wrapping_add(self.rate);
+        }
+    }
+
+    /// returns the top 5 bits of the accumulator
+    fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+}
+
+/// Konami VRC6 sound: two pulse channels and a sawtooth
+///
+/// Registers live at $9000-$9003, $A000-$A002 and $B000-$B002. Boards wired like mapper 26
+/// swap address lines A0 and A1, which is undone by the board before calling write().
+///
+/// - https://www.nesdev.org/wiki/VRC6_audio
+#[derive(Default)]
+pub struct Vrc6Audio {
+    pulse: [Vrc6Pulse; 2],
+    saw: Vrc6Saw,
+    halt: bool,
+}
+
+impl Vrc6Audio {
+    pub fn new() -> Self {
+        Vrc6Audio::default()
+    }
+}
+
+impl ExpansionAudio for Vrc6Audio {
+    fn write(&mut self, address: u16, data: u8) {
+        let register = address & 0x0003;
+        match address & 0xf003 {
+            0x9003 => self.halt = data & 0x01 != 0,
+            0x9000..=0x9002 => self.pulse[0].write(register, data),
+            0xa000..=0xa002 => self.pulse[1].write(register, data),
+            0xb000..=0xb002 => self.saw.write(register, data),
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self) {
+        if self.halt {
+            return;
+        }
+        self.pulse[0].tick();
+        self.pulse[1].tick();
+        self.saw.tick();
+    }
+
+    fn output(&self) -> f32 {
+        let level = self.pulse[0].output() + self.pulse[1].output() + self.saw.output();
+        level as f32 * SCALE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pulse_duty() {
+        let mut vrc6 = Vrc6Audio::new();
+        // duty 4 is high for 5 of 16 steps
+        vrc6.write(0x9000, 0x4f);
+        vrc6.write(0x9001, 0x00);
+        vrc6.write(0x9002, 0x80);
+        let high = (0..16)
+            .filter(|_| {
+                vrc6.tick();
+                vrc6.output() > 0.0
+            })
+            .count();
+        assert_eq!(high, 5);
+    }
+
+    #[test]
+    fn test_saw() {
+        let mut vrc6 = Vrc6Audio::new();
+        vrc6.write(0xb000, 0x08);
+        vrc6.write(0xb002, 0x80);
+        let levels: Vec<u8> = (0..14)
+            .map(|_| {
+                vrc6.tick();
+                vrc6.saw.output()
+            })
+            .collect();
+        assert_eq!(levels, [0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 0]);
+    }
+}
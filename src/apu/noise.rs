@@ -0,0 +1,105 @@
+use crate::apu::envelope::{Envelope, LengthCounter};
+
+/// timer periods in CPU cycles indexed by the low 4 bits of $400E
+#[rustfmt::skip]
+const PERIOD_TABLE: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+
+/// Noise channel at $400C-$400F
+///
+/// A 15-bit linear feedback shift register clocked by the timer produces pseudo-random bits.
+/// Mode 1 takes feedback from bit 6 instead of bit 1, giving a short 93-step sequence with a
+/// metallic tone.
+///
+/// - https://www.nesdev.org/wiki/APU_Noise
+pub struct Noise {
+    pub envelope: Envelope,
+    pub length: LengthCounter,
+    mode: bool,
+    period: u16,
+    timer: u16,
+    shift: u16,
+}
+
+impl Noise {
+    pub fn new() -> Self {
+        Noise {
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+            mode: false,
+            period: PERIOD_TABLE[0],
+            timer: 0,
+            // the shift register is loaded with 1 on power up
+            shift: 1,
+        }
+    }
+
+    /// writes one of the four registers, selected by the low two address bits
+    pub fn write(&mut self, register: u16, data: u8) {
+        match register & 0b11 {
+            0 => {
+                self.length.halt = data & 0x20 != 0;
+                self.envelope.write(data);
+            }
+            1 => {}
+            2 => {
+                self.mode = data & 0x80 != 0;
+                self.period = PERIOD_TABLE[(data & 0x0f) as usize];
+            }
+            _ => {
+                self.length.load(data >> 3);
+                self.envelope.restart();
+            }
+        }
+    }
+
+    /// clocked every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period - 1;
+            let tap = if self.mode { 6 } else { 1 };
+            let feedback = (self.shift ^ (self.shift >> tap)) & 1;
+            self.shift = (self.shift >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// returns the current volume, 0-15
+    pub fn output(&self) -> u8 {
+        if !self.length.active() || self.shift & 1 != 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    /// returns the number of shifts before the register repeats
+    fn sequence_length(mode: u8) -> usize {
+        let mut noise = Noise::new();
+        noise.write(2, mode);
+        let start = noise.shift;
+        (1..)
+            .find(|_| {
+                for _ in 0..PERIOD_TABLE[0] {
+                    noise.clock_timer();
+                }
+                noise.shift == start
+            })
+            .unwrap()
+    }
+
+    #[rstest]
+    #[case(0x00, 32767)]
+    #[case(0x80, 93)]
+    fn test_lfsr_period(#[case] mode: u8, #[case] expected: usize) {
+        assert_eq!(sequence_length(mode), expected);
+    }
+}
@@ -0,0 +1,176 @@
+use crate::apu::envelope::{Envelope, LengthCounter};
+
+#[rustfmt::skip]
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+/// Pulse (square wave) channel at $4000-$4003 and $4004-$4007
+///
+/// An 11-bit timer steps through one of four duty cycles. The sweep unit can periodically bend
+/// the timer period up or down; the two channels differ only in how they negate the change,
+/// pulse 1 uses one's complement and pulse 2 two's complement.
+///
+/// - https://www.nesdev.org/wiki/APU_Pulse
+/// - https://www.nesdev.org/wiki/APU_Sweep
+pub struct Pulse {
+    ones_complement: bool,
+    /// false for pulses copied without the sweep unit, like the MMC5's
+    has_sweep: bool,
+    pub envelope: Envelope,
+    pub length: LengthCounter,
+    duty: u8,
+    step: u8,
+    period: u16,
+    timer: u16,
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_divider: u8,
+    sweep_reload: bool,
+}
+
+impl Pulse {
+    pub fn new(ones_complement: bool) -> Self {
+        Pulse {
+            ones_complement,
+            has_sweep: true,
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+            duty: 0,
+            step: 0,
+            period: 0,
+            timer: 0,
+            sweep_enabled: false,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_divider: 0,
+            sweep_reload: false,
+        }
+    }
+
+    /// returns a pulse channel with no sweep unit, which never silences the channel
+    pub fn without_sweep() -> Self {
+        Pulse {
+            has_sweep: false,
+            ..Pulse::new(false)
+        }
+    }
+
+    /// writes one of the four registers, selected by the low two address bits
+    pub fn write(&mut self, register: u16, data: u8) {
+        match register & 0b11 {
+            0 => {
+                self.duty = data >> 6;
+                self.length.halt = data & 0x20 != 0;
+                self.envelope.write(data);
+            }
+            1 if !self.has_sweep => {}
+            1 => {
+                self.sweep_enabled = data & 0x80 != 0;
+                self.sweep_period = (data >> 4) & 0b111;
+                self.sweep_negate = data & 0x08 != 0;
+                self.sweep_shift = data & 0b111;
+                self.sweep_reload = true;
+            }
+            2 => self.period = (self.period & 0x0700) | data as u16,
+            _ => {
+                self.period = (self.period & 0x00ff) | ((data as u16 & 0b111) << 8);
+                self.length.load(data >> 3);
+                self.step = 0;
+                self.envelope.restart();
+            }
+        }
+    }
+
+    /// clocked every APU cycle, every other CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1) & 0b111;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// returns the period the sweep unit is moving towards
+    fn target_period(&self) -> u16 {
+        let change = self.period >> self.sweep_shift;
+        if !self.sweep_negate {
+            self.period + change
+        } else if self.ones_complement {
+            self.period.saturating_sub(change + 1)
+        } else {
+            self.period.saturating_sub(change)
+        }
+    }
+
+    /// the sweep unit silences the channel when the period is too low or would overflow,
+    /// even while sweeping is disabled
+    fn muted(&self) -> bool {
+        self.has_sweep && (self.period < 8 || self.target_period() > 0x7ff)
+    }
+
+    /// clocked by the frame counter every half frame
+    pub fn clock_sweep(&mut self) {
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.muted() {
+            self.period = self.target_period();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    /// returns the current volume, 0-15
+    pub fn output(&self) -> u8 {
+        if !self.length.active()
+            || self.muted()
+            || DUTY_TABLE[self.duty as usize][self.step as usize] == 0
+        {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(false, 0x0180)]
+    #[case(true, 0x017f)]
+    fn test_sweep_negate(#[case] ones_complement: bool, #[case] expected: u16) {
+        let mut pulse = Pulse::new(ones_complement);
+        pulse.write(2, 0x00);
+        pulse.write(3, 0x02);
+        // enabled, period 0, negate, shift 2
+        pulse.write(1, 0x8a);
+        pulse.clock_sweep();
+        assert_eq!(pulse.period, expected);
+    }
+
+    #[test]
+    fn test_sweep_mutes_overflow() {
+        let mut pulse = Pulse::new(false);
+        pulse.length.set_enabled(true);
+        // constant volume 15, duty 75%
+        pulse.write(0, 0xdf);
+        pulse.write(2, 0xff);
+        pulse.write(3, 0x07);
+        pulse.write(1, 0x00);
+        assert_eq!(pulse.output(), 0);
+        pulse.write(3, 0x03);
+        assert_eq!(pulse.output(), 15);
+    }
+}
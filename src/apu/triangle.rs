@@ -0,0 +1,112 @@
+use crate::apu::envelope::LengthCounter;
+
+#[rustfmt::skip]
+const SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+/// Triangle channel at $4008-$400B
+///
+/// Steps through a 32-step triangle at the CPU clock rate, so it plays an octave lower than a
+/// pulse channel with the same period. Has no volume control; besides the length counter it is
+/// gated by a linear counter with quarter frame resolution.
+///
+/// - https://www.nesdev.org/wiki/APU_Triangle
+pub struct Triangle {
+    pub length: LengthCounter,
+    control: bool,
+    linear_reload_value: u8,
+    linear_counter: u8,
+    linear_reload: bool,
+    period: u16,
+    timer: u16,
+    step: u8,
+}
+
+impl Triangle {
+    pub fn new() -> Self {
+        Triangle {
+            length: LengthCounter::default(),
+            control: false,
+            linear_reload_value: 0,
+            linear_counter: 0,
+            linear_reload: false,
+            period: 0,
+            timer: 0,
+            step: 0,
+        }
+    }
+
+    /// writes one of the four registers, selected by the low two address bits
+    pub fn write(&mut self, register: u16, data: u8) {
+        match register & 0b11 {
+            0 => {
+                self.control = data & 0x80 != 0;
+                self.length.halt = self.control;
+                self.linear_reload_value = data & 0x7f;
+            }
+            1 => {}
+            2 => self.period = (self.period & 0x0700) | data as u16,
+            _ => {
+                self.period = (self.period & 0x00ff) | ((data as u16 & 0b111) << 8);
+                self.length.load(data >> 3);
+                self.linear_reload = true;
+            }
+        }
+    }
+
+    /// clocked every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.length.active() && self.linear_counter > 0 {
+                self.step = (self.step + 1) & 0x1f;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// clocked by the frame counter every quarter frame
+    pub fn clock_linear(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    /// returns the current level, 0-15
+    ///
+    /// Silencing the channel freezes the sequencer rather than dropping the output to zero,
+    /// which is what avoids pops when games stop a note.
+    pub fn output(&self) -> u8 {
+        SEQUENCE[self.step as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_linear_counter_gates_sequencer() {
+        let mut triangle = Triangle::new();
+        triangle.length.set_enabled(true);
+        triangle.write(0, 0x01);
+        triangle.write(2, 0x00);
+        triangle.write(3, 0x08);
+        triangle.clock_timer();
+        assert_eq!(triangle.output(), 15);
+        triangle.clock_linear();
+        triangle.clock_timer();
+        assert_eq!(triangle.output(), 14);
+        triangle.clock_linear();
+        triangle.clock_timer();
+        assert_eq!(triangle.output(), 14);
+    }
+}
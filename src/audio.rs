@@ -0,0 +1,76 @@
+use log::debug;
+use std::thread;
+use std::time::Duration;
+
+mod output;
+mod resampler;
+
+pub use output::Output;
+pub use resampler::{Resampler, CPU_CLOCK_HZ};
+
+/// seconds of audio the frontend tries to keep queued for the device
+const TARGET_LATENCY: f64 = 0.05;
+/// largest change to the resampling ratio used to keep the queue at the target
+const MAX_RATE_ADJUST: f64 = 0.005;
+
+/// Plays APU samples on the host's audio device
+///
+/// The display and the audio device each run off their own clock and neither matches the
+/// NES exactly, so the resampling ratio is continually nudged to keep the device's queue near
+/// TARGET_LATENCY. If the emulator runs ahead anyway, sync() blocks until the device catches
+/// up, making audio a second timing source next to the display's vsync.
+pub struct Audio {
+    resampler: Resampler,
+    output: Option<Output>,
+    buffer: Vec<f32>,
+}
+
+impl Audio {
+    /// opens the default audio device, on failure the emulator carries on silently
+    pub fn new() -> Self {
+        let output = match Output::open() {
+            Ok(output) => {
+                debug!("audio output at {}Hz", output.rate);
+                Some(output)
+            }
+            Err(e) => {
+                println!("audio disabled: {}", e);
+                None
+            }
+        };
+        let rate = output
+            .as_ref()
+            .map_or(output::REQUESTED_RATE, |output| output.rate);
+        Audio {
+            resampler: Resampler::new(rate),
+            output,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// resamples a block of APU samples and queues them for playback
+    pub fn push(&mut self, samples: &[f32]) {
+        let output = match &self.output {
+            Some(output) => output,
+            None => return,
+        };
+        let target = output.rate as f64 * TARGET_LATENCY;
+        let error = (output.queued() as f64 - target) / target;
+        self.resampler
+            .set_adjust(1.0 + MAX_RATE_ADJUST * error.clamp(-1.0, 1.0));
+
+        self.buffer.clear();
+        self.resampler.process(samples, &mut self.buffer);
+        output.push(&self.buffer);
+    }
+
+    /// blocks while the queue holds more than twice the target latency
+    pub fn sync(&self) {
+        if let Some(output) = &self.output {
+            let limit = (output.rate as f64 * TARGET_LATENCY * 2.0) as usize;
+            while output.queued() > limit {
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// sample rate requested from the host, the device may pick something close to it
+pub const REQUESTED_RATE: u32 = 48_000;
+
+/// Connection to the host's audio device
+///
+/// Samples are pushed into a queue shared with a thread that feeds the device. The device
+/// consumes them at its own pace, so the length of the queue tells the frontend whether the
+/// emulator is running ahead of or behind real time.
+///
+/// Only ALSA on Linux is implemented. Elsewhere open() fails and the emulator runs without
+/// sound, paced by the display alone.
+pub struct Output {
+    pub rate: u32,
+    queue: Arc<Mutex<VecDeque<f32>>>,
+}
+
+impl Output {
+    /// queues samples for playback
+    pub fn push(&self, samples: &[f32]) {
+        self.queue.lock().unwrap().extend(samples);
+    }
+
+    /// returns the number of samples waiting to be played
+    pub fn queued(&self) -> usize {
+        self.queue.lock().unwrap().len()
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod alsa {
+    use super::{Output, REQUESTED_RATE};
+    use quad_alsa_sys as sys;
+    use std::collections::VecDeque;
+    use std::os::raw::c_ulong;
+    use std::sync::{mpsc, Arc, Mutex};
+
+    /// frames written to the device at a time
+    const PERIOD_FRAMES: usize = 512;
+    /// frames of buffering inside the device
+    const BUFFER_FRAMES: c_ulong = 2048;
+
+    struct Pcm(*mut sys::snd_pcm_t);
+
+    impl Drop for Pcm {
+        fn drop(&mut self) {
+            // SAFETY: the handle came from a successful snd_pcm_open and is closed only here
+            unsafe { sys::snd_pcm_close(self.0) };
+        }
+    }
+
+    /// opens the default ALSA device for mono 32-bit float playback, returning the rate
+    ///
+    /// The handle isn't thread safe, callers must keep it on the thread that opened it.
+    unsafe fn open_pcm() -> Result<(Pcm, u32), String> {
+        let mut handle = std::ptr::null_mut();
+        // the device name is a nul terminated static string
+        if sys::snd_pcm_open(
+            &mut handle,
+            "default\0".as_ptr() as _,
+            sys::SND_PCM_STREAM_PLAYBACK,
+            0,
+        ) < 0
+        {
+            return Err("unable to open the default audio device".to_string());
+        }
+        let pcm = Pcm(handle);
+
+        let mut params = std::ptr::null_mut();
+        if sys::snd_pcm_hw_params_malloc(&mut params) < 0 {
+            return Err("unable to configure the audio device".to_string());
+        }
+        sys::snd_pcm_hw_params_any(pcm.0, params);
+        let mut rate = REQUESTED_RATE;
+        let mut buffer_frames = BUFFER_FRAMES;
+        let ok =
+            sys::snd_pcm_hw_params_set_access(pcm.0, params, sys::SND_PCM_ACCESS_RW_INTERLEAVED)
+                >= 0
+                && sys::snd_pcm_hw_params_set_format(pcm.0, params, sys::SND_PCM_FORMAT_FLOAT_LE)
+                    >= 0
+                && sys::snd_pcm_hw_params_set_channels(pcm.0, params, 1) >= 0
+                && sys::snd_pcm_hw_params_set_rate_near(
+                    pcm.0,
+                    params,
+                    &mut rate,
+                    std::ptr::null_mut(),
+                ) >= 0
+                && sys::snd_pcm_hw_params_set_buffer_size_near(pcm.0, params, &mut buffer_frames)
+                    >= 0
+                && sys::snd_pcm_hw_params(pcm.0, params) >= 0;
+        sys::snd_pcm_hw_params_free(params);
+        if !ok || sys::snd_pcm_prepare(pcm.0) < 0 {
+            return Err("unable to configure the audio device".to_string());
+        }
+        Ok((pcm, rate))
+    }
+
+    /// feeds the device from the queue until the Output is dropped
+    ///
+    /// pcm must have been opened by open_pcm on the calling thread.
+    unsafe fn play(pcm: Pcm, queue: Arc<Mutex<VecDeque<f32>>>) {
+        let mut buffer = [0.0f32; PERIOD_FRAMES];
+        let mut last = 0.0;
+        while Arc::strong_count(&queue) > 1 {
+            {
+                let mut queue = queue.lock().unwrap();
+                for sample in buffer.iter_mut() {
+                    // on underrun hold the last sample rather than clicking back to zero
+                    *sample = queue.pop_front().unwrap_or(last);
+                    last = *sample;
+                }
+            }
+            // the buffer holds PERIOD_FRAMES mono frames in the format the device was set up with
+            let written = sys::snd_pcm_writei(pcm.0, buffer.as_ptr() as _, PERIOD_FRAMES as _);
+            if written < 0 {
+                sys::snd_pcm_recover(pcm.0, written as _, 1);
+            }
+        }
+    }
+
+    impl Output {
+        /// opens the default audio device, starting the thread that feeds it
+        pub fn open() -> Result<Output, String> {
+            let queue = Arc::new(Mutex::new(VecDeque::new()));
+            let (sender, receiver) = mpsc::channel();
+            let thread_queue = queue.clone();
+            // the device handle isn't Send, so it is opened on the thread that uses it
+            // SAFETY: the handle from open_pcm is only used by play on this same thread
+            std::thread::spawn(move || unsafe {
+                match open_pcm() {
+                    Ok((pcm, rate)) => {
+                        sender.send(Ok(rate)).unwrap();
+                        play(pcm, thread_queue);
+                    }
+                    Err(e) => sender.send(Err(e)).unwrap(),
+                }
+            });
+            let rate = receiver
+                .recv()
+                .map_err(|_| "audio thread exited".to_string())??;
+            Ok(Output { rate, queue })
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl Output {
+    /// there's no audio backend for this platform yet
+    pub fn open() -> Result<Output, String> {
+        Err("audio output is only supported on linux".to_string())
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+/// rate the APU produces samples at, one per NTSC CPU cycle
+pub const CPU_CLOCK_HZ: f64 = 1_789_773.0;
+
+/// the first stage averages this many input samples, bringing the rate down to ~224kHz
+const DECIMATION: usize = 8;
+/// half the width of the windowed sinc kernel in intermediate samples
+const KERNEL_HALF_WIDTH: usize = 32;
+/// kernel table entries per intermediate sample
+const KERNEL_PHASES: usize = 32;
+/// passband of the sinc filter as a fraction of the output rate
+const CUTOFF: f64 = 0.45;
+
+/// First order low-pass filter
+struct LowPass {
+    alpha: f32,
+    previous: f32,
+}
+
+impl LowPass {
+    fn new(rate: f64, cutoff: f64) -> Self {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / rate;
+        LowPass {
+            alpha: (dt / (rc + dt)) as f32,
+            previous: 0.0,
+        }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        self.previous += self.alpha * (input - self.previous);
+        self.previous
+    }
+}
+
+/// First order high-pass filter
+struct HighPass {
+    alpha: f32,
+    previous_input: f32,
+    previous: f32,
+}
+
+impl HighPass {
+    fn new(rate: f64, cutoff: f64) -> Self {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / rate;
+        HighPass {
+            alpha: (rc / (rc + dt)) as f32,
+            previous_input: 0.0,
+            previous: 0.0,
+        }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        self.previous = self.alpha * (self.previous + input - self.previous_input);
+        self.previous_input = input;
+        self.previous
+    }
+}
+
+/// Converts the APU's CPU rate sample stream to the host sample rate
+///
+/// The NES output stage is modelled with a 14kHz low-pass and high-passes at 90Hz and 440Hz,
+/// which also removes the DC offset of the mixer. Decimation happens in two stages: a box
+/// filter averages groups of samples down to an intermediate rate, then a windowed sinc
+/// band-limits the signal to below the output Nyquist frequency while interpolating at
+/// fractional positions. The ratio can be nudged slightly so the frontend can keep its queue
+/// of samples from draining or overflowing.
+pub struct Resampler {
+    output_rate: f64,
+    step: f64,
+    lowpass: LowPass,
+    highpass: [HighPass; 2],
+    box_sum: f32,
+    box_count: usize,
+    history: VecDeque<f32>,
+    position: f64,
+    kernel: Vec<f32>,
+}
+
+impl Resampler {
+    pub fn new(output_rate: u32) -> Self {
+        let output_rate = output_rate as f64;
+        let intermediate_rate = CPU_CLOCK_HZ / DECIMATION as f64;
+        // cutoff in cycles per intermediate sample
+        let cutoff = CUTOFF * output_rate / intermediate_rate;
+        let kernel = (0..=KERNEL_HALF_WIDTH * KERNEL_PHASES)
+            .map(|i| {
+                let t = i as f64 / KERNEL_PHASES as f64;
+                let x = 2.0 * cutoff * t;
+                let sinc = if i == 0 {
+                    1.0
+                } else {
+                    (PI * x).sin() / (PI * x)
+                };
+                // blackman window
+                let n = 0.5 + t / (2.0 * KERNEL_HALF_WIDTH as f64);
+                let window = 0.42 - 0.5 * (2.0 * PI * n).cos() + 0.08 * (4.0 * PI * n).cos();
+                (sinc * window) as f32
+            })
+            .collect();
+
+        Resampler {
+            output_rate,
+            step: intermediate_rate / output_rate,
+            lowpass: LowPass::new(CPU_CLOCK_HZ, 14_000.0),
+            highpass: [
+                HighPass::new(output_rate, 90.0),
+                HighPass::new(output_rate, 440.0),
+            ],
+            box_sum: 0.0,
+            box_count: 0,
+            history: VecDeque::new(),
+            position: KERNEL_HALF_WIDTH as f64,
+            kernel,
+        }
+    }
+
+    /// scales the number of samples produced by 1.0 / adjust, used for rate control
+    pub fn set_adjust(&mut self, adjust: f64) {
+        let intermediate_rate = CPU_CLOCK_HZ / DECIMATION as f64;
+        self.step = intermediate_rate / self.output_rate * adjust;
+    }
+
+    /// resamples a block of APU samples, appending the results to output
+    pub fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        for sample in input {
+            self.box_sum += self.lowpass.process(*sample);
+            self.box_count += 1;
+            if self.box_count == DECIMATION {
+                self.history.push_back(self.box_sum / DECIMATION as f32);
+                self.box_sum = 0.0;
+                self.box_count = 0;
+                self.interpolate(output);
+            }
+        }
+    }
+
+    /// produces every output sample whose kernel is covered by the history
+    fn interpolate(&mut self, output: &mut Vec<f32>) {
+        let half_width = KERNEL_HALF_WIDTH as f64;
+        while self.position + half_width < self.history.len() as f64 {
+            let first = (self.position - half_width).ceil() as usize;
+            let last = (self.position + half_width).floor() as usize;
+            let mut sum = 0.0;
+            let mut weight = 0.0;
+            for k in first..=last {
+                let distance = (self.position - k as f64).abs();
+                let h = self.kernel[(distance * KERNEL_PHASES as f64).round() as usize];
+                sum += self.history[k] * h;
+                weight += h;
+            }
+            let sample = self.highpass[0].process(sum / weight);
+            output.push(self.highpass[1].process(sample));
+            self.position += self.step;
+        }
+
+        // drop history no longer reachable by the kernel
+        let unused = (self.position - half_width).floor() as usize;
+        if unused > 0 {
+            self.history.drain(..unused);
+            self.position -= unused as f64;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    /// resamples one second of a sine wave, returning its RMS amplitude after settling
+    fn rms(frequency: f64) -> f32 {
+        let mut resampler = Resampler::new(44_100);
+        let input: Vec<f32> = (0..CPU_CLOCK_HZ as usize)
+            .map(|i| (2.0 * PI * frequency * i as f64 / CPU_CLOCK_HZ).sin() as f32)
+            .collect();
+        let mut output = vec![];
+        resampler.process(&input, &mut output);
+        let settled = &output[output.len() / 2..];
+        (settled.iter().map(|s| s * s).sum::<f32>() / settled.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn test_output_rate() {
+        let mut resampler = Resampler::new(48_000);
+        let mut output = vec![];
+        resampler.process(&vec![0.5; CPU_CLOCK_HZ as usize], &mut output);
+        assert!((47_900..=48_000).contains(&output.len()));
+        // the high-pass filters remove DC
+        assert!(output.last().unwrap().abs() < 0.01);
+    }
+
+    #[rstest]
+    #[case(1_000.0, 0.6, 0.75)]
+    #[case(40_000.0, 0.0, 0.01)]
+    #[case(100_000.0, 0.0, 0.01)]
+    fn test_band_limited(#[case] frequency: f64, #[case] min: f32, #[case] max: f32) {
+        let rms = rms(frequency);
+        assert!(rms >= min && rms <= max, "rms {} at {}Hz", rms, frequency);
+    }
+}
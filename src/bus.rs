@@ -1,40 +1,154 @@
+use crate::apu::Apu;
 use crate::controller::Controller;
+use crate::mapper::{self, Mapper};
 use crate::ppu::Ppu;
 use crate::rom::Rom;
 use log::debug;
-use std::borrow::Borrow;
+use std::cell::RefCell;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+
+/// CPU cycles lost to each DMC sample fetch, it varies from 1 to 4 depending on what the CPU
+/// was doing but 4 is the common case
+pub const DMC_STALL_CYCLES: usize = 4;
 
 pub struct Bus {
     pub ram: [u8; 2048],
-    rom: Rom,
+    mapper: Rc<RefCell<dyn Mapper>>,
     pub ppu: Ppu,
+    pub apu: Apu,
     pub controller: Controller,
     cycle: usize,
+    battery: bool,
+    saved_ram: Vec<u8>,
+    trainer: Option<Vec<u8>>,
 }
 
 impl Bus {
-    /// Implements the 'bus' connecting PPU, CPU, RAM and controllers
+    /// Implements the 'bus' connecting PPU, CPU, RAM, controllers and the cartridge
     ///
     /// Normally each component in the NES would be independent operating on clocks and various
     /// signals passed via a 'bus'. In this implementation functions act more syncronously and
-    /// timings are guided by cycles and ticks.
-    pub fn new(rom: Rom) -> Self {
-        let ppu = Ppu::new(rom.chr_rom.clone(), rom.mirroring());
+    /// timings are guided by cycles and ticks. The cartridge mapper is shared with the PPU so
+    /// that both sides see the same bank switching state.
+    pub fn new(mut rom: Rom) -> Self {
+        let battery = rom.has_battery();
+        let trainer = rom.trainer.take();
+        let mut bus = Bus::new_with_mapper(mapper::new(rom));
+        bus.battery = battery;
+        bus.trainer = trainer;
+        bus
+    }
+
+    /// creates a bus around an already built mapper, used for sources other than iNES files
+    pub fn new_with_mapper(mapper: Rc<RefCell<dyn Mapper>>) -> Self {
+        let ppu = Ppu::new(mapper.clone());
 
         Bus {
             ram: [0; 2048],
-            rom,
+            mapper,
             ppu,
+            apu: Apu::new(),
             cycle: 0,
             controller: Controller::new(),
+            battery: false,
+            saved_ram: vec![],
+            trainer: None,
         }
     }
 
+    /// performs the cartridge side of a reset
+    ///
+    /// Copier devices loaded the trainer into PRG RAM at $7000-$71FF before starting the game,
+    /// so do the same here.
+    pub fn reset(&mut self) {
+        if let Some(trainer) = &self.trainer {
+            let mut mapper = self.mapper.borrow_mut();
+            let prg_ram = mapper.prg_ram();
+            if prg_ram.is_empty() {
+                debug!("no prg_ram to load trainer into");
+                return;
+            }
+            let len = prg_ram.len();
+            for (i, data) in trainer.iter().enumerate() {
+                prg_ram[(0x1000 + i) % len] = *data;
+            }
+        }
+    }
+
+    /// the reset button silences the APU and turns off PPU rendering and NMI
+    pub fn soft_reset(&mut self) {
+        self.apu.write_register(0x4015, 0);
+        self.ppu.write_ppuctrl(0);
+        self.ppu.write_ppumask(0);
+    }
+
+    /// restores battery backed PRG RAM from a .sav file, a missing file is not an error
+    pub fn load_sav(&mut self, path: &Path) -> io::Result<()> {
+        if !self.battery {
+            return Ok(());
+        }
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let mut mapper = self.mapper.borrow_mut();
+        let prg_ram = mapper.prg_ram();
+        let len = prg_ram.len().min(data.len());
+        prg_ram[..len].copy_from_slice(&data[..len]);
+        self.saved_ram = prg_ram.to_vec();
+        debug!("loaded {} bytes from {}", len, path.display());
+        Ok(())
+    }
+
+    /// writes battery backed PRG RAM to a .sav file if it changed since the last flush
+    pub fn flush_sav(&mut self, path: &Path) -> io::Result<()> {
+        if !self.battery {
+            return Ok(());
+        }
+        let prg_ram = self.mapper.borrow_mut().prg_ram().to_vec();
+        if prg_ram != self.saved_ram {
+            fs::write(path, &prg_ram)?;
+            debug!("saved {} bytes to {}", prg_ram.len(), path.display());
+            self.saved_ram = prg_ram;
+        }
+        Ok(())
+    }
+
+    /// advances the PPU and APU by a number of CPU cycles, returning true when the PPU has
+    /// finished a frame. Games that leave NMI disabled still produce frames.
+    ///
+    /// When the DMC needs a sample byte the CPU is halted while it is read, which shows up as
+    /// extra cycles here.
     pub fn tick(&mut self, cycle: u8) -> bool {
-        self.cycle += cycle as usize;
-        let before = self.ppu.has_nmi.is_some();
-        self.ppu.tick(cycle * 3);
-        !before && self.ppu.has_nmi.is_some()
+        let mut frame_done = false;
+        let mut cycles = cycle as usize;
+        while cycles > 0 {
+            cycles -= 1;
+            self.cycle += 1;
+            frame_done |= self.ppu.tick(3);
+            let expansion = {
+                let mut mapper = self.mapper.borrow_mut();
+                mapper.cpu_tick();
+                mapper.audio_output()
+            };
+            self.apu.set_expansion_output(expansion);
+            self.apu.tick();
+            if let Some(address) = self.apu.dmc_fetch_address() {
+                let data = self.read_u8(address);
+                self.apu.dmc_load_sample(data);
+                cycles += DMC_STALL_CYCLES;
+            }
+        }
+        frame_done
+    }
+
+    /// CPU cycles run since power on, including DMC stalls
+    pub fn cycle(&self) -> usize {
+        self.cycle
     }
 
     /// actively poll for new keys and update internal data
@@ -46,6 +160,11 @@ impl Bus {
         self.ppu.has_nmi.take() == Some(true)
     }
 
+    /// returns true while any device is holding the IRQ line low
+    pub fn irq(&self) -> bool {
+        self.mapper.borrow().irq() || self.apu.irq()
+    }
+
     /// reads a byte matches the address to the correct component on the bus
     pub fn read_u8(&mut self, address: u16) -> u8 {
         debug!("reading @ {:04x}", address);
@@ -57,10 +176,11 @@ impl Bus {
             0x2002 => self.ppu.read_ppustatus(),
             0x2004 => self.ppu.read_oamdata(),
             0x2007 => self.ppu.read_data(),
+            0x4015 => self.apu.read_status(),
             0x4016 => self.controller.read(),
             0x4017 => 0, // TODO player 2
             0x2008..=0x3fff => self.read_u8(address & 0x2007),
-            0x8000..=0xffff => self.rom.read_byte(address),
+            0x4020..=0xffff => self.mapper.borrow_mut().cpu_read(address),
             _ => panic!("invalid read address {:04X}", address),
         }
     }
@@ -81,7 +201,7 @@ impl Bus {
             0x2008..=0x3fff => {
                 self.write_u8(address & 0x2007, data);
             }
-            0x4000..=0x4013 => {} // TODO implement APU
+            0x4000..=0x4013 | 0x4015 | 0x4017 => self.apu.write_register(address, data),
             0x4014 => {
                 let mut buffer: [u8; 256] = [0; 256];
                 let start = (data as u16) << 8;
@@ -90,13 +210,22 @@ impl Bus {
                 }
                 self.ppu.write_oamdata_dma(&buffer);
             }
-            0x4015 => {}
             0x4016 => self.controller.write(data),
-            0x4017 => {} // TODO player 2
+            0x4020..=0xffff => self.mapper.borrow_mut().cpu_write(address, data),
             _ => panic!("invalid write address {:04X}", address),
         }
     }
 
+    /// reads a byte for debugging without the side effects some reads have. The PPU, APU and
+    /// controller registers read as $FF.
+    pub fn peek_u8(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x1fff => self.ram[address as usize % 0x0800],
+            0x2000..=0x401f => 0xff,
+            _ => self.mapper.borrow_mut().cpu_read(address),
+        }
+    }
+
     /// reads 16 bits by calling read_u8 twice
     pub fn read_u16(&mut self, address: u16) -> u16 {
         (self.read_u8(address.wrapping_add(0)) as u16)
@@ -132,4 +261,35 @@ mod tests {
         let mut bus = setup_bus(vec![0xcd, 0xab]);
         assert_eq!(bus.read_u16(0x8000), 0xabcd);
     }
+
+    #[test]
+    fn test_prg_ram() {
+        let mut bus = setup_bus(vec![0xff]);
+        bus.write_u8(0x6000, 0x5a);
+        bus.write_u8(0x7fff, 0xa5);
+        assert_eq!(bus.read_u8(0x6000), 0x5a);
+        assert_eq!(bus.read_u8(0x7fff), 0xa5);
+    }
+
+    #[test]
+    fn test_dmc_stall() {
+        let mut bus = setup_bus(vec![0xff]);
+        bus.write_u8(0x4015, 0x10);
+        bus.tick(2);
+        assert_eq!(bus.cycle, 2 + DMC_STALL_CYCLES);
+        bus.tick(2);
+        assert_eq!(bus.cycle, 4 + DMC_STALL_CYCLES);
+    }
+
+    #[test]
+    fn test_trainer() {
+        let mut rom = Rom::new_from_vec(vec![0xff]);
+        rom.trainer = Some(vec![0x5a; 512]);
+        let mut bus = Bus::new(rom);
+        bus.reset();
+        assert_eq!(bus.read_u8(0x6fff), 0x00);
+        assert_eq!(bus.read_u8(0x7000), 0x5a);
+        assert_eq!(bus.read_u8(0x71ff), 0x5a);
+        assert_eq!(bus.read_u8(0x7200), 0x00);
+    }
 }
@@ -57,6 +57,11 @@ impl Controller {
         }
     }
 
+    /// sets the buttons held down, for input that doesn't come from the keyboard
+    pub fn set_buttons(&mut self, buttons: ControllerButtons) {
+        self.status = buttons;
+    }
+
     /// read keys directly from macroquad and set the status bits
     pub fn read_keys(&mut self) {
         self.status
@@ -8,11 +8,13 @@ use crate::cpu::AddressingMode::{
     IndirectY, Relative, ZeroPage, ZeroPageX, ZeroPageY,
 };
 use crate::cpu::Flag::Zero;
-use log::debug;
+use log::{debug, log_enabled, Level};
 use std::fmt::{self, Debug, Display, Formatter};
 use std::ops::Add;
 
 const STACK_BYTE_HIGH: u16 = 0x0100;
+/// pushing pc and status and reading the vector takes 7 cycles for NMI, IRQ and reset
+const INTERRUPT_CYCLES: u8 = 7;
 
 enum Flag {
     Carry = 0b0000_0001,
@@ -56,8 +58,12 @@ enum AddressingMode {
 #[derive(Debug)]
 enum Opcode {
     Adc,
+    Alr,
+    Anc,
     And,
+    Arr,
     Asl,
+    Axs,
     Bcc,
     Bcs,
     Beq,
@@ -75,6 +81,7 @@ enum Opcode {
     Cmp,
     Cpx,
     Cpy,
+    Dcp,
     Dec,
     Dex,
     Dey,
@@ -82,9 +89,11 @@ enum Opcode {
     Inc,
     Inx,
     Iny,
+    Isb,
     Jmp,
     Jsr,
     Kil,
+    Lax,
     Lda,
     Ldx,
     Ldy,
@@ -95,14 +104,19 @@ enum Opcode {
     Php,
     Pla,
     Plp,
+    Rla,
     Rol,
     Ror,
+    Rra,
     Rti,
     Rts,
+    Sax,
     Sbc,
     Sec,
     Sed,
     Sei,
+    Slo,
+    Sre,
     Sta,
     Stx,
     Sty,
@@ -141,59 +155,27 @@ impl InstructionBytes<'_> {
         assert!(self.bytes.len() == 2);
         self.bytes[1] as i8
     }
-}
 
-impl Display for InstructionBytes<'_> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        let instruction_string = match self.instruction.mode {
-            Absolute => {
-                format!("${:04X?}", self.get_address())
-            }
-            AbsoluteX => {
-                format!("${:04X?},X", self.get_address())
-            }
-            AbsoluteY => {
-                format!("${:04X?},Y", self.get_address())
-            }
-            Immediate => {
-                format!("#${:02X?}", self.get_immediate())
-            }
-            Indirect => {
-                format!("(${:04X?})", self.get_address())
-            }
-            IndirectX => {
-                format!("(${:02X?},X)", self.get_immediate())
-            }
-            IndirectY => {
-                format!("(${:02X?}),Y", self.get_immediate())
-            }
-            ZeroPage => {
-                format!("${:02X?}", self.get_immediate())
-            }
-            ZeroPageX => {
-                format!("${:02X?},X", self.get_immediate())
-            }
-            ZeroPageY => {
-                format!("${:02X?},Y", self.get_immediate())
-            }
-            Relative => {
-                format!("~{:02X?}", self.get_immediate())
-            }
-            _ => "".to_string(),
-        };
-        let mut bytes_string = String::new();
-        for b in &self.bytes {
-            bytes_string.push_str(format!("{:02X} ", b).as_str())
+    /// whether the opcode is one of the undocumented ones, which traces mark with a *
+    fn unofficial(&self) -> bool {
+        match self.instruction.opcode {
+            Opcode::Nop => self.bytes[0] != 0xea,
+            Opcode::Sbc => self.bytes[0] == 0xeb,
+            Opcode::Alr
+            | Opcode::Anc
+            | Opcode::Arr
+            | Opcode::Axs
+            | Opcode::Dcp
+            | Opcode::Isb
+            | Opcode::Kil
+            | Opcode::Lax
+            | Opcode::Rla
+            | Opcode::Rra
+            | Opcode::Sax
+            | Opcode::Slo
+            | Opcode::Sre => true,
+            _ => false,
         }
-        // TODO fix this hack
-        write!(
-            f,
-            "{:<9} {:<3} {:<8}",
-            bytes_string.replace('[', "").replace(']', ""),
-            self.instruction.opcode.to_string().to_ascii_uppercase(),
-            instruction_string
-        )?;
-        Ok(())
     }
 }
 
@@ -223,28 +205,59 @@ impl Cpu {
         }
     }
 
-    pub fn nmi(&mut self) -> u16 {
-        debug!("nmi interrupt");
+    /// pushes pc and status then jumps through the given interrupt vector
+    fn interrupt(&mut self, vector: u16) -> u16 {
         self.stack_push_u16(self.pc);
         self.clear_flag(Flag::Break);
         self.set_flag(Flag::Unused);
         self.stack_push_u8(self.p);
         self.set_flag(Flag::IntDisable);
-        let new_pc = self.bus.read_u16(0xfffa);
+        let new_pc = self.bus.read_u16(vector);
         self.pc = new_pc;
         new_pc
     }
 
+    pub fn nmi(&mut self) -> u16 {
+        debug!("nmi interrupt");
+        self.interrupt(0xfffa)
+    }
+
+    /// services a maskable interrupt, callers should check interrupts_disabled first
+    pub fn irq(&mut self) -> u16 {
+        debug!("irq interrupt");
+        self.interrupt(0xfffe)
+    }
+
     pub fn brk(&mut self) -> u16 {
         debug!("brk interrupt");
-        self.stack_push_u16(self.pc);
-        self.clear_flag(Flag::Break);
-        self.set_flag(Flag::Unused);
-        self.stack_push_u8(self.p);
-        self.set_flag(Flag::IntDisable);
-        let new_pc = self.bus.read_u16(0xfffe);
-        self.pc = new_pc;
-        new_pc
+        self.interrupt(0xfffe)
+    }
+
+    /// calls a subroutine the way a JSR at return_address - 3 would, with A and X loaded
+    ///
+    /// Used to run code without a reset vector, such as NSF INIT and PLAY routines. The
+    /// subroutine has finished once pc() reaches return_address.
+    pub fn call(&mut self, address: u16, return_address: u16, a: u8, x: u8) {
+        self.a = a;
+        self.x = x;
+        self.y = 0;
+        self.p = 0x24;
+        self.sp = 0xfd;
+        self.stack_push_u16(return_address.wrapping_sub(1));
+        self.pc = address;
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    /// continues execution from an address, such as nestest's automatic mode at $C000
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    pub fn interrupts_disabled(&self) -> bool {
+        self.is_flag_set(Flag::IntDisable)
     }
 
     fn set_zero_negative_flags(&mut self, value: u8) {
@@ -276,13 +289,41 @@ impl Cpu {
         self.p & flag as u8 == 0
     }
 
-    fn branch(&mut self, b: &InstructionBytes, condition: bool) -> u16 {
+    /// returns the next pc and the extra cycles taken, one for taking the branch and another
+    /// if it lands on a different page
+    fn branch(&mut self, b: &InstructionBytes, condition: bool) -> (u16, u8) {
         let new_pc = self.pc.wrapping_add(b.bytes.len() as u16);
-        if condition {
-            new_pc.wrapping_add(b.get_offset() as u16)
-        } else {
-            new_pc
+        if !condition {
+            return (new_pc, 0);
         }
+        let target = new_pc.wrapping_add(b.get_offset() as u16);
+        (target, 1 + (target & 0xff00 != new_pc & 0xff00) as u8)
+    }
+
+    /// returns the extra cycle a read takes when indexing carries into the next page. Writes
+    /// and read-modify-write instructions always spend it, so it's in their base cycles.
+    fn page_cross_cycles(&mut self, b: &InstructionBytes) -> u8 {
+        let reads = matches!(
+            b.instruction.opcode,
+            Opcode::Adc
+                | Opcode::And
+                | Opcode::Cmp
+                | Opcode::Eor
+                | Opcode::Lax
+                | Opcode::Lda
+                | Opcode::Ldx
+                | Opcode::Ldy
+                | Opcode::Nop
+                | Opcode::Ora
+                | Opcode::Sbc
+        );
+        let (base, index) = match b.instruction.mode {
+            AbsoluteX if reads => (b.get_address(), self.x),
+            AbsoluteY if reads => (b.get_address(), self.y),
+            IndirectY if reads => (self.read_zero_page_u16(b.get_immediate()), self.y),
+            _ => return 0,
+        };
+        (base & 0xff00 != base.wrapping_add(index as u16) & 0xff00) as u8
     }
 
     fn shift_left(&mut self, input: u8) -> (u8, bool) {
@@ -291,6 +332,10 @@ impl Cpu {
 
     fn compare(&mut self, b: &InstructionBytes, reg: u8) {
         let op = self.get_operand(b) as u8;
+        self.compare_value(reg, op);
+    }
+
+    fn compare_value(&mut self, reg: u8, op: u8) {
         debug!("compare op {:02X} reg {:02X}", op, reg);
         let result = reg.wrapping_sub(op);
         self.change_flag(Flag::Carry, reg >= op);
@@ -334,21 +379,30 @@ impl Cpu {
         self.set_zero_negative_flags(self.a);
     }
 
-    fn set_result(&mut self, b: &InstructionBytes, result: u8) {
+    fn set_result(&mut self, b: &InstructionBytes, value: u8, result: u8) {
         match b.instruction.mode {
             Accumulator => {
                 self.a = result;
             }
             _ => {
                 let addr = self.get_operand_address(b);
-                self.bus.write_u8(addr, result);
+                self.write_modified(addr, value, result);
             }
         }
     }
 
-    /// given InstructionBytes execute and modify the CPU state
-    fn execute(&mut self, b: &InstructionBytes) {
+    /// writes the result of a read-modify-write instruction. The CPU writes the unmodified
+    /// value back on the cycle before, which boards such as MMC1 can see.
+    fn write_modified(&mut self, addr: u16, value: u8, result: u8) {
+        self.bus.write_u8(addr, value);
+        self.bus.write_u8(addr, result);
+    }
+
+    /// given InstructionBytes execute and modify the CPU state, returning any cycles taken
+    /// beyond the instruction's base count
+    fn execute(&mut self, b: &InstructionBytes) -> u8 {
         let mut new_pc = self.pc.wrapping_add((b.instruction.length) as u16);
+        let mut extra_cycles = 0;
         match b.instruction.opcode {
             Opcode::Nop => {}
 
@@ -367,39 +421,31 @@ impl Cpu {
                 self.change_flag(Flag::Carry, op & 0x80 != 0);
                 let result = op.rotate_left(1) as u8 & 0xfe;
                 self.set_zero_negative_flags(result);
-                match b.instruction.mode {
-                    Accumulator => {
-                        self.a = result;
-                    }
-                    _ => {
-                        let addr = self.get_operand_address(b);
-                        self.bus.write_u8(addr, result);
-                    }
-                }
+                self.set_result(b, op as u8, result);
             }
             Opcode::Bcc => {
-                new_pc = self.branch(b, self.is_flag_clear(Flag::Carry));
+                (new_pc, extra_cycles) = self.branch(b, self.is_flag_clear(Flag::Carry));
             }
             Opcode::Bcs => {
-                new_pc = self.branch(b, self.is_flag_set(Flag::Carry));
+                (new_pc, extra_cycles) = self.branch(b, self.is_flag_set(Flag::Carry));
             }
             Opcode::Bvc => {
-                new_pc = self.branch(b, self.is_flag_clear(Flag::Overflow));
+                (new_pc, extra_cycles) = self.branch(b, self.is_flag_clear(Flag::Overflow));
             }
             Opcode::Bvs => {
-                new_pc = self.branch(b, self.is_flag_set(Flag::Overflow));
+                (new_pc, extra_cycles) = self.branch(b, self.is_flag_set(Flag::Overflow));
             }
             Opcode::Bne => {
-                new_pc = self.branch(b, self.is_flag_clear(Flag::Zero));
+                (new_pc, extra_cycles) = self.branch(b, self.is_flag_clear(Flag::Zero));
             }
             Opcode::Beq => {
-                new_pc = self.branch(b, self.is_flag_set(Flag::Zero));
+                (new_pc, extra_cycles) = self.branch(b, self.is_flag_set(Flag::Zero));
             }
             Opcode::Bpl => {
-                new_pc = self.branch(b, self.is_flag_clear(Flag::Negative));
+                (new_pc, extra_cycles) = self.branch(b, self.is_flag_clear(Flag::Negative));
             }
             Opcode::Bmi => {
-                new_pc = self.branch(b, self.is_flag_set(Flag::Negative));
+                (new_pc, extra_cycles) = self.branch(b, self.is_flag_set(Flag::Negative));
             }
             Opcode::Clc => {
                 self.clear_flag(Flag::Carry);
@@ -424,9 +470,10 @@ impl Cpu {
             }
             Opcode::Inc => {
                 let addr = self.get_operand_address(b);
-                let value = self.bus.read_u8(addr).wrapping_add(1);
-                self.bus.write_u8(addr, value);
-                self.set_zero_negative_flags(value);
+                let value = self.bus.read_u8(addr);
+                let result = value.wrapping_add(1);
+                self.write_modified(addr, value, result);
+                self.set_zero_negative_flags(result);
             }
             Opcode::Inx => {
                 self.x = self.x.wrapping_add(1);
@@ -476,9 +523,10 @@ impl Cpu {
             }
             Opcode::Dec => {
                 let addr = self.get_operand_address(b);
-                let value = self.bus.read_u8(addr).wrapping_sub(1);
-                self.bus.write_u8(addr, value);
-                self.set_zero_negative_flags(value);
+                let value = self.bus.read_u8(addr);
+                let result = value.wrapping_sub(1);
+                self.write_modified(addr, value, result);
+                self.set_zero_negative_flags(result);
             }
             Opcode::Dex => {
                 self.x = self.x.wrapping_sub(1);
@@ -521,7 +569,7 @@ impl Cpu {
                 self.change_flag(Flag::Carry, op & 0x80 != 0);
                 let result = op.rotate_left(1) as u8 | carry_in;
                 self.set_zero_negative_flags(result);
-                self.set_result(b, result);
+                self.set_result(b, op as u8, result);
             }
             Opcode::Ror => {
                 let carry_in = if self.p & (Flag::Carry as u8) != 0 {
@@ -533,7 +581,7 @@ impl Cpu {
                 self.change_flag(Flag::Carry, op & 0x01 != 0);
                 let result = op.rotate_right(1) as u8 | carry_in;
                 self.set_zero_negative_flags(result);
-                self.set_result(b, result);
+                self.set_result(b, op as u8, result);
             }
 
             Opcode::Sbc => {
@@ -585,7 +633,7 @@ impl Cpu {
                         let addr = self.get_operand_address(b);
                         let value = self.bus.read_u8(addr);
                         let (result, carry_bit) = self.shift_left(value);
-                        self.bus.write_u8(addr, result);
+                        self.write_modified(addr, value, result);
                         (result, carry_bit)
                     }
                 };
@@ -625,9 +673,99 @@ impl Cpu {
             Opcode::Kil => {
                 todo!("KIL")
             }
+
+            // unofficial opcodes, mostly a read-modify-write followed by an ALU operation on
+            // the result
+            // - https://www.nesdev.org/wiki/Programming_with_unofficial_opcodes
+            Opcode::Lax => {
+                self.a = self.get_operand(b) as u8;
+                self.x = self.a;
+                self.set_zero_negative_flags(self.a);
+            }
+            Opcode::Sax => {
+                let addr = self.get_operand_address(b);
+                self.bus.write_u8(addr, self.a & self.x);
+            }
+            Opcode::Slo => {
+                let addr = self.get_operand_address(b);
+                let value = self.bus.read_u8(addr);
+                self.change_flag(Flag::Carry, value & 0x80 != 0);
+                let result = value << 1;
+                self.write_modified(addr, value, result);
+                self.a |= result;
+                self.set_zero_negative_flags(self.a);
+            }
+            Opcode::Rla => {
+                let carry_in = self.is_flag_set(Flag::Carry) as u8;
+                let addr = self.get_operand_address(b);
+                let value = self.bus.read_u8(addr);
+                self.change_flag(Flag::Carry, value & 0x80 != 0);
+                let result = value << 1 | carry_in;
+                self.write_modified(addr, value, result);
+                self.a &= result;
+                self.set_zero_negative_flags(self.a);
+            }
+            Opcode::Sre => {
+                let addr = self.get_operand_address(b);
+                let value = self.bus.read_u8(addr);
+                self.change_flag(Flag::Carry, value & 0x01 != 0);
+                let result = value >> 1;
+                self.write_modified(addr, value, result);
+                self.a ^= result;
+                self.set_zero_negative_flags(self.a);
+            }
+            Opcode::Rra => {
+                let carry_in = (self.is_flag_set(Flag::Carry) as u8) << 7;
+                let addr = self.get_operand_address(b);
+                let value = self.bus.read_u8(addr);
+                self.change_flag(Flag::Carry, value & 0x01 != 0);
+                let result = value >> 1 | carry_in;
+                self.write_modified(addr, value, result);
+                self.add_to_a(result);
+            }
+            Opcode::Dcp => {
+                let addr = self.get_operand_address(b);
+                let value = self.bus.read_u8(addr);
+                let result = value.wrapping_sub(1);
+                self.write_modified(addr, value, result);
+                self.compare_value(self.a, result);
+            }
+            Opcode::Isb => {
+                let addr = self.get_operand_address(b);
+                let value = self.bus.read_u8(addr);
+                let result = value.wrapping_add(1);
+                self.write_modified(addr, value, result);
+                self.add_to_a(!result);
+            }
+            Opcode::Anc => {
+                self.a &= b.get_immediate();
+                self.set_zero_negative_flags(self.a);
+                self.change_flag(Flag::Carry, self.a & 0x80 != 0);
+            }
+            Opcode::Alr => {
+                let value = self.a & b.get_immediate();
+                self.change_flag(Flag::Carry, value & 0x01 != 0);
+                self.a = value >> 1;
+                self.set_zero_negative_flags(self.a);
+            }
+            Opcode::Arr => {
+                let carry_in = (self.is_flag_set(Flag::Carry) as u8) << 7;
+                self.a = (self.a & b.get_immediate()) >> 1 | carry_in;
+                self.set_zero_negative_flags(self.a);
+                self.change_flag(Flag::Carry, self.a & 0x40 != 0);
+                self.change_flag(Flag::Overflow, (self.a ^ self.a << 1) & 0x40 != 0);
+            }
+            Opcode::Axs => {
+                let value = self.a & self.x;
+                let operand = b.get_immediate();
+                self.change_flag(Flag::Carry, value >= operand);
+                self.x = value.wrapping_sub(operand);
+                self.set_zero_negative_flags(self.x);
+            }
         }
 
         self.pc = new_pc;
+        extra_cycles
     }
 
     fn get_operand(&mut self, b: &InstructionBytes) -> u16 {
@@ -667,23 +805,21 @@ impl Cpu {
                     self.bus.read_u16(addr)
                 }
             }
-            IndirectX => {
-                let addr = b.get_immediate().wrapping_add(self.x) as u8;
-                let lsb = self.bus.read_u8(addr as u16);
-                let msb = self.bus.read_u8(addr.wrapping_add(1) as u16);
-                lsb as u16 | (msb as u16) << 8
-            }
-            IndirectY => {
-                let addr = b.get_immediate();
-                let lsb = self.bus.read_u8(addr as u16);
-                let msb = self.bus.read_u8(addr.wrapping_add(1) as u16);
-                let target = lsb as u16 | (msb as u16) << 8;
-                target.wrapping_add(self.y as u16)
-            }
+            IndirectX => self.read_zero_page_u16(b.get_immediate().wrapping_add(self.x)),
+            IndirectY => self
+                .read_zero_page_u16(b.get_immediate())
+                .wrapping_add(self.y as u16),
             _ => panic!("get_operand not supported for {:?}", b.instruction.mode),
         }
     }
 
+    /// reads a pointer from the zero page, wrapping from $FF to $00 for the high byte
+    fn read_zero_page_u16(&mut self, addr: u8) -> u16 {
+        let lsb = self.bus.read_u8(addr as u16);
+        let msb = self.bus.read_u8(addr.wrapping_add(1) as u16);
+        lsb as u16 | (msb as u16) << 8
+    }
+
     /// decode takes in an opcode and outputs an instruction structure
     /// reference:
     /// - https://www.nesdev.org/obelisk-6502-guide/reference.html
@@ -812,13 +948,13 @@ impl Cpu {
                 opcode: Opcode::Asl,
                 mode: Absolute,
                 length: 3,
-                cycles: 2,
+                cycles: 6,
             },
             0x1e => Instruction {
                 opcode: Opcode::Asl,
                 mode: AbsoluteX,
                 length: 3,
-                cycles: 4,
+                cycles: 7,
             },
 
             // Branch instructions
@@ -1352,13 +1488,13 @@ impl Cpu {
                 opcode: Opcode::Pla,
                 mode: Implied,
                 length: 1,
-                cycles: 3,
+                cycles: 4,
             },
             0x28 => Instruction {
                 opcode: Opcode::Plp,
                 mode: Implied,
                 length: 1,
-                cycles: 3,
+                cycles: 4,
             },
 
             // Rotates
@@ -1720,70 +1856,730 @@ impl Cpu {
             0x1a => Instruction {
                 opcode: Opcode::Nop,
                 mode: Implied,
-                length: 2,
-                cycles: 1,
+                length: 1,
+                cycles: 2,
             },
             0x3a => Instruction {
                 opcode: Opcode::Nop,
                 mode: Implied,
-                length: 2,
-                cycles: 1,
+                length: 1,
+                cycles: 2,
             },
             0x5a => Instruction {
                 opcode: Opcode::Nop,
                 mode: Implied,
-                length: 2,
-                cycles: 1,
+                length: 1,
+                cycles: 2,
             },
             0x7a => Instruction {
                 opcode: Opcode::Nop,
                 mode: Implied,
-                length: 2,
-                cycles: 1,
+                length: 1,
+                cycles: 2,
             },
             0xda => Instruction {
                 opcode: Opcode::Nop,
                 mode: Implied,
-                length: 2,
-                cycles: 1,
+                length: 1,
+                cycles: 2,
             },
             0xfa => Instruction {
                 opcode: Opcode::Nop,
                 mode: Implied,
-                length: 2,
-                cycles: 1,
+                length: 1,
+                cycles: 2,
             },
-            _ => Instruction {
+
+            // NOP, the official one and the unofficial ones which read operands
+            0xea => Instruction {
                 opcode: Opcode::Nop,
                 mode: Implied,
                 length: 1,
-                cycles: 1,
+                cycles: 2,
+            },
+            0x04 => Instruction {
+                opcode: Opcode::Nop,
+                mode: ZeroPage,
+                length: 2,
+                cycles: 3,
+            },
+            0x0c => Instruction {
+                opcode: Opcode::Nop,
+                mode: Absolute,
+                length: 3,
+                cycles: 4,
+            },
+            0x14 => Instruction {
+                opcode: Opcode::Nop,
+                mode: ZeroPageX,
+                length: 2,
+                cycles: 4,
+            },
+            0x1c => Instruction {
+                opcode: Opcode::Nop,
+                mode: AbsoluteX,
+                length: 3,
+                cycles: 4,
+            },
+            0x34 => Instruction {
+                opcode: Opcode::Nop,
+                mode: ZeroPageX,
+                length: 2,
+                cycles: 4,
+            },
+            0x3c => Instruction {
+                opcode: Opcode::Nop,
+                mode: AbsoluteX,
+                length: 3,
+                cycles: 4,
+            },
+            0x44 => Instruction {
+                opcode: Opcode::Nop,
+                mode: ZeroPage,
+                length: 2,
+                cycles: 3,
+            },
+            0x54 => Instruction {
+                opcode: Opcode::Nop,
+                mode: ZeroPageX,
+                length: 2,
+                cycles: 4,
+            },
+            0x5c => Instruction {
+                opcode: Opcode::Nop,
+                mode: AbsoluteX,
+                length: 3,
+                cycles: 4,
+            },
+            0x64 => Instruction {
+                opcode: Opcode::Nop,
+                mode: ZeroPage,
+                length: 2,
+                cycles: 3,
+            },
+            0x74 => Instruction {
+                opcode: Opcode::Nop,
+                mode: ZeroPageX,
+                length: 2,
+                cycles: 4,
+            },
+            0x7c => Instruction {
+                opcode: Opcode::Nop,
+                mode: AbsoluteX,
+                length: 3,
+                cycles: 4,
+            },
+            0x80 => Instruction {
+                opcode: Opcode::Nop,
+                mode: Immediate,
+                length: 2,
+                cycles: 2,
+            },
+            0x82 => Instruction {
+                opcode: Opcode::Nop,
+                mode: Immediate,
+                length: 2,
+                cycles: 2,
+            },
+            0x89 => Instruction {
+                opcode: Opcode::Nop,
+                mode: Immediate,
+                length: 2,
+                cycles: 2,
+            },
+            0xc2 => Instruction {
+                opcode: Opcode::Nop,
+                mode: Immediate,
+                length: 2,
+                cycles: 2,
+            },
+            0xd4 => Instruction {
+                opcode: Opcode::Nop,
+                mode: ZeroPageX,
+                length: 2,
+                cycles: 4,
+            },
+            0xdc => Instruction {
+                opcode: Opcode::Nop,
+                mode: AbsoluteX,
+                length: 3,
+                cycles: 4,
+            },
+            0xe2 => Instruction {
+                opcode: Opcode::Nop,
+                mode: Immediate,
+                length: 2,
+                cycles: 2,
+            },
+            0xf4 => Instruction {
+                opcode: Opcode::Nop,
+                mode: ZeroPageX,
+                length: 2,
+                cycles: 4,
+            },
+            0xfc => Instruction {
+                opcode: Opcode::Nop,
+                mode: AbsoluteX,
+                length: 3,
+                cycles: 4,
             },
-        }
-    }
-
-    pub fn reset(&mut self) {
-        // https://www.nesdev.org/wiki/CPU_power_up_state
-        self.a = 0;
-        self.x = 0;
-        self.p = 0x24;
-        self.pc = self.bus.read_u16(0xfffc);
-        self.sp = 0xfd;
-    }
 
-    pub fn step(&mut self) -> u8 {
-        let op = self.bus.read_u8(self.pc);
-        let instruction = self.decode(op);
+            // LAX (load A and X), unofficial
+            0xa3 => Instruction {
+                opcode: Opcode::Lax,
+                mode: IndirectX,
+                length: 2,
+                cycles: 6,
+            },
+            0xa7 => Instruction {
+                opcode: Opcode::Lax,
+                mode: ZeroPage,
+                length: 2,
+                cycles: 3,
+            },
+            0xaf => Instruction {
+                opcode: Opcode::Lax,
+                mode: Absolute,
+                length: 3,
+                cycles: 4,
+            },
+            0xb3 => Instruction {
+                opcode: Opcode::Lax,
+                mode: IndirectY,
+                length: 2,
+                cycles: 5,
+            },
+            0xb7 => Instruction {
+                opcode: Opcode::Lax,
+                mode: ZeroPageY,
+                length: 2,
+                cycles: 4,
+            },
+            0xbf => Instruction {
+                opcode: Opcode::Lax,
+                mode: AbsoluteY,
+                length: 3,
+                cycles: 4,
+            },
+
+            // SAX (store A AND X), unofficial
+            0x83 => Instruction {
+                opcode: Opcode::Sax,
+                mode: IndirectX,
+                length: 2,
+                cycles: 6,
+            },
+            0x87 => Instruction {
+                opcode: Opcode::Sax,
+                mode: ZeroPage,
+                length: 2,
+                cycles: 3,
+            },
+            0x8f => Instruction {
+                opcode: Opcode::Sax,
+                mode: Absolute,
+                length: 3,
+                cycles: 4,
+            },
+            0x97 => Instruction {
+                opcode: Opcode::Sax,
+                mode: ZeroPageY,
+                length: 2,
+                cycles: 4,
+            },
+
+            // SBC, unofficial duplicate of $E9
+            0xeb => Instruction {
+                opcode: Opcode::Sbc,
+                mode: Immediate,
+                length: 2,
+                cycles: 2,
+            },
+
+            // SLO (ASL then ORA), unofficial
+            0x03 => Instruction {
+                opcode: Opcode::Slo,
+                mode: IndirectX,
+                length: 2,
+                cycles: 8,
+            },
+            0x07 => Instruction {
+                opcode: Opcode::Slo,
+                mode: ZeroPage,
+                length: 2,
+                cycles: 5,
+            },
+            0x0f => Instruction {
+                opcode: Opcode::Slo,
+                mode: Absolute,
+                length: 3,
+                cycles: 6,
+            },
+            0x13 => Instruction {
+                opcode: Opcode::Slo,
+                mode: IndirectY,
+                length: 2,
+                cycles: 8,
+            },
+            0x17 => Instruction {
+                opcode: Opcode::Slo,
+                mode: ZeroPageX,
+                length: 2,
+                cycles: 6,
+            },
+            0x1b => Instruction {
+                opcode: Opcode::Slo,
+                mode: AbsoluteY,
+                length: 3,
+                cycles: 7,
+            },
+            0x1f => Instruction {
+                opcode: Opcode::Slo,
+                mode: AbsoluteX,
+                length: 3,
+                cycles: 7,
+            },
+
+            // RLA (ROL then AND), unofficial
+            0x23 => Instruction {
+                opcode: Opcode::Rla,
+                mode: IndirectX,
+                length: 2,
+                cycles: 8,
+            },
+            0x27 => Instruction {
+                opcode: Opcode::Rla,
+                mode: ZeroPage,
+                length: 2,
+                cycles: 5,
+            },
+            0x2f => Instruction {
+                opcode: Opcode::Rla,
+                mode: Absolute,
+                length: 3,
+                cycles: 6,
+            },
+            0x33 => Instruction {
+                opcode: Opcode::Rla,
+                mode: IndirectY,
+                length: 2,
+                cycles: 8,
+            },
+            0x37 => Instruction {
+                opcode: Opcode::Rla,
+                mode: ZeroPageX,
+                length: 2,
+                cycles: 6,
+            },
+            0x3b => Instruction {
+                opcode: Opcode::Rla,
+                mode: AbsoluteY,
+                length: 3,
+                cycles: 7,
+            },
+            0x3f => Instruction {
+                opcode: Opcode::Rla,
+                mode: AbsoluteX,
+                length: 3,
+                cycles: 7,
+            },
+
+            // SRE (LSR then EOR), unofficial
+            0x43 => Instruction {
+                opcode: Opcode::Sre,
+                mode: IndirectX,
+                length: 2,
+                cycles: 8,
+            },
+            0x47 => Instruction {
+                opcode: Opcode::Sre,
+                mode: ZeroPage,
+                length: 2,
+                cycles: 5,
+            },
+            0x4f => Instruction {
+                opcode: Opcode::Sre,
+                mode: Absolute,
+                length: 3,
+                cycles: 6,
+            },
+            0x53 => Instruction {
+                opcode: Opcode::Sre,
+                mode: IndirectY,
+                length: 2,
+                cycles: 8,
+            },
+            0x57 => Instruction {
+                opcode: Opcode::Sre,
+                mode: ZeroPageX,
+                length: 2,
+                cycles: 6,
+            },
+            0x5b => Instruction {
+                opcode: Opcode::Sre,
+                mode: AbsoluteY,
+                length: 3,
+                cycles: 7,
+            },
+            0x5f => Instruction {
+                opcode: Opcode::Sre,
+                mode: AbsoluteX,
+                length: 3,
+                cycles: 7,
+            },
+
+            // RRA (ROR then ADC), unofficial
+            0x63 => Instruction {
+                opcode: Opcode::Rra,
+                mode: IndirectX,
+                length: 2,
+                cycles: 8,
+            },
+            0x67 => Instruction {
+                opcode: Opcode::Rra,
+                mode: ZeroPage,
+                length: 2,
+                cycles: 5,
+            },
+            0x6f => Instruction {
+                opcode: Opcode::Rra,
+                mode: Absolute,
+                length: 3,
+                cycles: 6,
+            },
+            0x73 => Instruction {
+                opcode: Opcode::Rra,
+                mode: IndirectY,
+                length: 2,
+                cycles: 8,
+            },
+            0x77 => Instruction {
+                opcode: Opcode::Rra,
+                mode: ZeroPageX,
+                length: 2,
+                cycles: 6,
+            },
+            0x7b => Instruction {
+                opcode: Opcode::Rra,
+                mode: AbsoluteY,
+                length: 3,
+                cycles: 7,
+            },
+            0x7f => Instruction {
+                opcode: Opcode::Rra,
+                mode: AbsoluteX,
+                length: 3,
+                cycles: 7,
+            },
+
+            // DCP (DEC then CMP), unofficial
+            0xc3 => Instruction {
+                opcode: Opcode::Dcp,
+                mode: IndirectX,
+                length: 2,
+                cycles: 8,
+            },
+            0xc7 => Instruction {
+                opcode: Opcode::Dcp,
+                mode: ZeroPage,
+                length: 2,
+                cycles: 5,
+            },
+            0xcf => Instruction {
+                opcode: Opcode::Dcp,
+                mode: Absolute,
+                length: 3,
+                cycles: 6,
+            },
+            0xd3 => Instruction {
+                opcode: Opcode::Dcp,
+                mode: IndirectY,
+                length: 2,
+                cycles: 8,
+            },
+            0xd7 => Instruction {
+                opcode: Opcode::Dcp,
+                mode: ZeroPageX,
+                length: 2,
+                cycles: 6,
+            },
+            0xdb => Instruction {
+                opcode: Opcode::Dcp,
+                mode: AbsoluteY,
+                length: 3,
+                cycles: 7,
+            },
+            0xdf => Instruction {
+                opcode: Opcode::Dcp,
+                mode: AbsoluteX,
+                length: 3,
+                cycles: 7,
+            },
+
+            // ISB (INC then SBC), unofficial
+            0xe3 => Instruction {
+                opcode: Opcode::Isb,
+                mode: IndirectX,
+                length: 2,
+                cycles: 8,
+            },
+            0xe7 => Instruction {
+                opcode: Opcode::Isb,
+                mode: ZeroPage,
+                length: 2,
+                cycles: 5,
+            },
+            0xef => Instruction {
+                opcode: Opcode::Isb,
+                mode: Absolute,
+                length: 3,
+                cycles: 6,
+            },
+            0xf3 => Instruction {
+                opcode: Opcode::Isb,
+                mode: IndirectY,
+                length: 2,
+                cycles: 8,
+            },
+            0xf7 => Instruction {
+                opcode: Opcode::Isb,
+                mode: ZeroPageX,
+                length: 2,
+                cycles: 6,
+            },
+            0xfb => Instruction {
+                opcode: Opcode::Isb,
+                mode: AbsoluteY,
+                length: 3,
+                cycles: 7,
+            },
+            0xff => Instruction {
+                opcode: Opcode::Isb,
+                mode: AbsoluteX,
+                length: 3,
+                cycles: 7,
+            },
+
+            // immediate combinations, unofficial
+            0x0b => Instruction {
+                opcode: Opcode::Anc,
+                mode: Immediate,
+                length: 2,
+                cycles: 2,
+            },
+            0x2b => Instruction {
+                opcode: Opcode::Anc,
+                mode: Immediate,
+                length: 2,
+                cycles: 2,
+            },
+            0x4b => Instruction {
+                opcode: Opcode::Alr,
+                mode: Immediate,
+                length: 2,
+                cycles: 2,
+            },
+            0x6b => Instruction {
+                opcode: Opcode::Arr,
+                mode: Immediate,
+                length: 2,
+                cycles: 2,
+            },
+            0xcb => Instruction {
+                opcode: Opcode::Axs,
+                mode: Immediate,
+                length: 2,
+                cycles: 2,
+            },
+            _ => Instruction {
+                opcode: Opcode::Nop,
+                mode: Implied,
+                length: 1,
+                cycles: 1,
+            },
+        }
+    }
+
+    pub fn reset(&mut self) {
+        // https://www.nesdev.org/wiki/CPU_power_up_state
+        self.bus.reset();
+        self.a = 0;
+        self.x = 0;
+        self.p = 0x24;
+        self.pc = self.bus.read_u16(0xfffc);
+        self.sp = 0xfd;
+        // like an interrupt the reset sequence takes 7 cycles
+        self.cycles = INTERRUPT_CYCLES as u64;
+        self.bus.tick(INTERRUPT_CYCLES);
+    }
+
+    /// presses the reset button, which unlike power on leaves memory and A, X and Y alone
+    ///
+    /// - https://www.nesdev.org/wiki/CPU_power_up_state#At_reset
+    pub fn soft_reset(&mut self) {
+        self.bus.soft_reset();
+        self.sp = self.sp.wrapping_sub(3);
+        self.set_flag(Flag::IntDisable);
+        self.pc = self.bus.read_u16(0xfffc);
+    }
+
+    pub fn step(&mut self) -> u8 {
+        if log_enabled!(Level::Debug) {
+            let trace = self.trace();
+            debug!("{}", trace);
+        }
+        let op = self.bus.read_u8(self.pc);
+        let instruction = self.decode(op);
         let instruction_bytes = InstructionBytes {
             instruction: &instruction,
             bytes: self.bus.read_bytes(self.pc, instruction.length),
         };
 
-        self.cycles = self.cycles.wrapping_add(instruction.cycles as u64);
+        let cycles = instruction.cycles
+            + self.page_cross_cycles(&instruction_bytes)
+            + self.execute(&instruction_bytes);
+        self.cycles = self.cycles.wrapping_add(cycles as u64);
+        cycles
+    }
+
+    /// formats the next instruction, registers, PPU position and cycle count the way
+    /// nestest.log does, e.g.
+    ///
+    /// C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+    ///
+    /// Memory is peeked so tracing doesn't disturb registers with read side effects.
+    pub fn trace(&mut self) -> String {
+        let instruction = self.decode(self.bus.peek_u8(self.pc));
+        let bytes: Vec<u8> = (0..instruction.length)
+            .map(|i| self.bus.peek_u8(self.pc.wrapping_add(i as u16)))
+            .collect();
+        let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        let b = InstructionBytes {
+            instruction: &instruction,
+            bytes,
+        };
+        let marker = if b.unofficial() { '*' } else { ' ' };
+        let (scanline, dot) = self.bus.ppu.position();
+        format!(
+            "{:04X}  {:<8} {}{:<32}{} PPU:{:>3},{:>3} CYC:{}",
+            self.pc,
+            hex.join(" "),
+            marker,
+            self.disassemble(&b),
+            self,
+            scanline,
+            dot,
+            self.cycles
+        )
+    }
+
+    /// disassembles an instruction, showing the address it will use and the value there
+    fn disassemble(&self, b: &InstructionBytes) -> String {
+        let peek = |addr: u16| self.bus.peek_u8(addr);
+        let peek_zero_page_u16 =
+            |addr: u8| peek(addr as u16) as u16 | (peek(addr.wrapping_add(1) as u16) as u16) << 8;
+        let operand = match b.instruction.mode {
+            Implied => String::new(),
+            Accumulator => "A".to_string(),
+            Immediate => format!("#${:02X}", b.get_immediate()),
+            ZeroPage => {
+                let addr = b.get_immediate();
+                format!("${:02X} = {:02X}", addr, peek(addr as u16))
+            }
+            ZeroPageX | ZeroPageY => {
+                let (index, name) = match b.instruction.mode {
+                    ZeroPageX => (self.x, 'X'),
+                    _ => (self.y, 'Y'),
+                };
+                let addr = b.get_immediate().wrapping_add(index);
+                format!(
+                    "${:02X},{} @ {:02X} = {:02X}",
+                    b.get_immediate(),
+                    name,
+                    addr,
+                    peek(addr as u16)
+                )
+            }
+            Absolute => match b.instruction.opcode {
+                Opcode::Jmp | Opcode::Jsr => format!("${:04X}", b.get_address()),
+                _ => format!("${:04X} = {:02X}", b.get_address(), peek(b.get_address())),
+            },
+            AbsoluteX | AbsoluteY => {
+                let (index, name) = match b.instruction.mode {
+                    AbsoluteX => (self.x, 'X'),
+                    _ => (self.y, 'Y'),
+                };
+                let addr = b.get_address().wrapping_add(index as u16);
+                format!(
+                    "${:04X},{} @ {:04X} = {:02X}",
+                    b.get_address(),
+                    name,
+                    addr,
+                    peek(addr)
+                )
+            }
+            Indirect => {
+                // the high byte wraps within the page, like the JMP it's used by
+                let addr = b.get_address();
+                let msb_addr = addr & 0xff00 | addr.wrapping_add(1) & 0x00ff;
+                let target = peek(addr) as u16 | (peek(msb_addr) as u16) << 8;
+                format!("(${:04X}) = {:04X}", addr, target)
+            }
+            IndirectX => {
+                let pointer = b.get_immediate().wrapping_add(self.x);
+                let addr = peek_zero_page_u16(pointer);
+                format!(
+                    "(${:02X},X) @ {:02X} = {:04X} = {:02X}",
+                    b.get_immediate(),
+                    pointer,
+                    addr,
+                    peek(addr)
+                )
+            }
+            IndirectY => {
+                let base = peek_zero_page_u16(b.get_immediate());
+                let addr = base.wrapping_add(self.y as u16);
+                format!(
+                    "(${:02X}),Y = {:04X} @ {:04X} = {:02X}",
+                    b.get_immediate(),
+                    base,
+                    addr,
+                    peek(addr)
+                )
+            }
+            Relative => {
+                let next = self.pc.wrapping_add(b.bytes.len() as u16);
+                format!("${:04X}", next.wrapping_add(b.get_offset() as u16))
+            }
+        };
+        let name = b.instruction.opcode.to_string().to_ascii_uppercase();
+        if operand.is_empty() {
+            name
+        } else {
+            format!("{} {}", name, operand)
+        }
+    }
 
-        debug!("{:04X}  {}   {}", self.pc, instruction_bytes, self);
-        self.execute(&instruction_bytes);
-        instruction.cycles
+    /// services a pending interrupt or runs the next instruction, then clocks the rest of the
+    /// console for the cycles it took. returns true when the PPU has finished a frame
+    pub fn tick(&mut self) -> bool {
+        let cycles = if self.bus.take_nmi() {
+            self.nmi();
+            self.cycles = self.cycles.wrapping_add(INTERRUPT_CYCLES as u64);
+            INTERRUPT_CYCLES
+        } else if self.bus.irq() && !self.interrupts_disabled() {
+            self.irq();
+            self.cycles = self.cycles.wrapping_add(INTERRUPT_CYCLES as u64);
+            INTERRUPT_CYCLES
+        } else {
+            self.step()
+        };
+        let start = self.bus.cycle();
+        let frame_done = self.bus.tick(cycles);
+        // the CPU is halted while the DMC fetches a sample
+        let stalled = self.bus.cycle() - start - cycles as usize;
+        self.cycles = self.cycles.wrapping_add(stalled as u64);
+        frame_done
     }
 }
 
@@ -1800,8 +2596,12 @@ impl Display for Cpu {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::rom::Rom;
+    use crate::bus::DMC_STALL_CYCLES;
+    use crate::mapper::Mapper;
+    use crate::rom::{Mirroring, Rom};
     use rstest::rstest;
+    use std::cell::RefCell;
+    use std::rc::Rc;
 
     fn setup_cpu(prg_rom: Vec<u8>) -> Cpu {
         let rom = Rom::new_from_vec(prg_rom);
@@ -1809,6 +2609,32 @@ mod tests {
         Cpu::new(bus)
     }
 
+    /// a cartridge serving a program at $8000 and recording the writes it sees
+    struct WriteLog {
+        prg_rom: Vec<u8>,
+        writes: Vec<(u16, u8)>,
+    }
+
+    impl Mapper for WriteLog {
+        fn cpu_read(&mut self, address: u16) -> u8 {
+            self.prg_rom[address as usize - 0x8000]
+        }
+
+        fn cpu_write(&mut self, address: u16, data: u8) {
+            self.writes.push((address, data));
+        }
+
+        fn ppu_read(&self, address: u16) -> u8 {
+            0
+        }
+
+        fn ppu_write(&mut self, address: u16, data: u8) {}
+
+        fn mirroring(&self) -> Mirroring {
+            Mirroring::Horizontal
+        }
+    }
+
     fn test_program(instructions: Vec<u8>) -> Vec<u8> {
         let mut prog: Vec<u8> = instructions.to_vec();
         prog.append(&mut vec![0; 0x7ffc - instructions.len()]);
@@ -1923,34 +2749,22 @@ mod tests {
     }
 
     #[rstest]
-    #[case(vec![0xaa], 0xfa, 0, 0, 0, 0xfa, 0xfa, 0, 0)]
-    #[case(vec![0xa8], 0xfa, 0, 0, 0, 0xfa, 0, 0xfa, 0)]
-    #[case(vec![0xba], 0, 0, 0, 0xba, 0, 0xba, 0, 0xba)]
-    #[case(vec![0x8a], 0, 0x8a, 0, 0, 0x8a, 0x8a, 0, 0)]
-    #[case(vec![0x9a], 0, 0x9a, 0, 0, 0, 0x9a, 0, 0x9a)]
-    #[case(vec![0x98], 0, 0, 0x98, 0, 0x98, 0, 0x98, 0)]
+    #[case(vec![0xaa], (0xfa, 0, 0, 0), (0xfa, 0xfa, 0, 0))]
+    #[case(vec![0xa8], (0xfa, 0, 0, 0), (0xfa, 0, 0xfa, 0))]
+    #[case(vec![0xba], (0, 0, 0, 0xba), (0, 0xba, 0, 0xba))]
+    #[case(vec![0x8a], (0, 0x8a, 0, 0), (0x8a, 0x8a, 0, 0))]
+    #[case(vec![0x9a], (0, 0x9a, 0, 0), (0, 0x9a, 0, 0x9a))]
+    #[case(vec![0x98], (0, 0, 0x98, 0), (0x98, 0, 0x98, 0))]
     fn test_transfers(
         #[case] in_prg: Vec<u8>,
-        #[case] in_a: u8,
-        #[case] in_x: u8,
-        #[case] in_y: u8,
-        #[case] in_sp: u8,
-        #[case] ex_a: u8,
-        #[case] ex_x: u8,
-        #[case] ex_y: u8,
-        #[case] ex_sp: u8,
+        #[case] in_axys: (u8, u8, u8, u8),
+        #[case] ex_axys: (u8, u8, u8, u8),
     ) {
         let mut cpu = setup_cpu(test_program(in_prg));
         cpu.reset();
-        cpu.a = in_a;
-        cpu.x = in_x;
-        cpu.y = in_y;
-        cpu.sp = in_sp;
+        (cpu.a, cpu.x, cpu.y, cpu.sp) = in_axys;
         cpu.step();
-        assert_eq!(cpu.a, ex_a);
-        assert_eq!(cpu.x, ex_x);
-        assert_eq!(cpu.y, ex_y);
-        assert_eq!(cpu.sp, ex_sp);
+        assert_eq!((cpu.a, cpu.x, cpu.y, cpu.sp), ex_axys);
     }
 
     #[rstest]
@@ -2104,6 +2918,36 @@ mod tests {
         assert_eq!(cpu.p, ex_flags);
     }
 
+    #[rstest]
+    #[case::inc(0xee, 0x82)]
+    #[case::dec(0xce, 0x80)]
+    #[case::asl(0x0e, 0x02)]
+    #[case::lsr(0x4e, 0x40)]
+    #[case::rol(0x2e, 0x02)]
+    #[case::ror(0x6e, 0x40)]
+    #[case::slo(0x0f, 0x02)]
+    #[case::rla(0x2f, 0x02)]
+    #[case::sre(0x4f, 0x40)]
+    #[case::rra(0x6f, 0x40)]
+    #[case::dcp(0xcf, 0x80)]
+    #[case::isb(0xef, 0x82)]
+    fn test_read_modify_write_dummy_write(#[case] opcode: u8, #[case] ex_result: u8) {
+        let mut prg_rom = test_program(vec![opcode, 0x00, 0x90]);
+        prg_rom[0x1000] = 0x81;
+        let mapper = Rc::new(RefCell::new(WriteLog {
+            prg_rom,
+            writes: vec![],
+        }));
+        let mut cpu = Cpu::new(Bus::new_with_mapper(mapper.clone()));
+        cpu.reset();
+        cpu.step();
+        // the unmodified value is written back before the result
+        assert_eq!(
+            mapper.borrow().writes,
+            [(0x9000, 0x81), (0x9000, ex_result)]
+        );
+    }
+
     #[test]
     fn test_brk() {
         let mut prog: Vec<u8> = vec![0x00];
@@ -2114,4 +2958,104 @@ mod tests {
         let mut cpu = setup_cpu(prog);
         assert_eq!(cpu.brk(), 0xaa00);
     }
+
+    #[test]
+    fn test_irq() {
+        let mut prog: Vec<u8> = vec![0x58];
+        prog.append(&mut vec![0; 0x3ffb]);
+        prog.append(&mut vec![0x00, 0x80]);
+        prog.append(&mut vec![0x34, 0x12]);
+        let mut cpu = setup_cpu(prog);
+        cpu.reset();
+        assert!(cpu.interrupts_disabled());
+        cpu.step();
+        assert!(!cpu.interrupts_disabled());
+        assert_eq!(cpu.irq(), 0x1234);
+        assert!(cpu.interrupts_disabled());
+        assert_eq!(cpu.stack_pop_u8() & Flag::IntDisable as u8, 0);
+        assert_eq!(cpu.stack_pop_u16(), 0x8001);
+    }
+
+    #[rstest]
+    #[case::lax(vec![0xa7, 0x10], 0x80, (0x00, 0x00), (0x80, 0x80), 0x80, 0xa4)]
+    #[case::sax(vec![0x87, 0x10], 0x00, (0xf0, 0x3c), (0xf0, 0x3c), 0x30, 0x24)]
+    #[case::dcp(vec![0xc7, 0x10], 0x41, (0x40, 0x00), (0x40, 0x00), 0x40, 0x27)]
+    #[case::isb(vec![0xe7, 0x10], 0x0f, (0x20, 0x00), (0x0f, 0x00), 0x10, 0x25)]
+    #[case::slo(vec![0x07, 0x10], 0x81, (0x01, 0x00), (0x03, 0x00), 0x02, 0x25)]
+    #[case::rra(vec![0x67, 0x10], 0x02, (0x10, 0x00), (0x11, 0x00), 0x01, 0x24)]
+    #[case::axs(vec![0xcb, 0x05], 0x00, (0x0f, 0xfc), (0x0f, 0x07), 0x00, 0x25)]
+    #[case::arr(vec![0x6b, 0xff], 0x00, (0xc0, 0x00), (0x60, 0x00), 0x00, 0x25)]
+    fn test_unofficial(
+        #[case] in_prg: Vec<u8>,
+        #[case] in_mem: u8,
+        #[case] in_ax: (u8, u8),
+        #[case] ex_ax: (u8, u8),
+        #[case] ex_mem: u8,
+        #[case] ex_flags: u8,
+    ) {
+        let mut cpu = setup_cpu(test_program(in_prg));
+        cpu.reset();
+        cpu.bus.write_u8(0x10, in_mem);
+        (cpu.a, cpu.x) = in_ax;
+        cpu.step();
+        assert_eq!((cpu.a, cpu.x), ex_ax);
+        assert_eq!(cpu.bus.read_u8(0x10), ex_mem);
+        assert_eq!(cpu.p, ex_flags);
+    }
+
+    #[rstest]
+    #[case(vec![0xbd, 0xff, 0x00], 0x00, 0x24, 4)]
+    #[case(vec![0xbd, 0xff, 0x00], 0x01, 0x24, 5)]
+    #[case(vec![0x9d, 0xff, 0x00], 0x01, 0x24, 5)]
+    #[case(vec![0x9d, 0x00, 0x00], 0x01, 0x24, 5)]
+    #[case(vec![0xd0, 0x10], 0x00, 0x26, 2)]
+    #[case(vec![0xd0, 0x10], 0x00, 0x24, 3)]
+    #[case(vec![0xd0, 0xf0], 0x00, 0x24, 4)]
+    fn test_extra_cycles(
+        #[case] in_prg: Vec<u8>,
+        #[case] in_x: u8,
+        #[case] in_flags: u8,
+        #[case] ex_cycles: u8,
+    ) {
+        let mut cpu = setup_cpu(test_program(in_prg));
+        cpu.reset();
+        cpu.x = in_x;
+        cpu.p = in_flags;
+        assert_eq!(cpu.step(), ex_cycles);
+        assert_eq!(cpu.cycles, 7 + ex_cycles as u64);
+    }
+
+    #[test]
+    fn test_trace() {
+        let mut cpu = setup_cpu(test_program(vec![0xb5, 0x0f, 0xa7, 0x10, 0x4c, 0xf5, 0xc5]));
+        cpu.reset();
+        cpu.x = 0x01;
+        cpu.bus.write_u8(0x10, 0x80);
+        let mut trace = vec![];
+        for _ in 0..3 {
+            trace.push(cpu.trace());
+            cpu.tick();
+        }
+        assert_eq!(
+            trace,
+            [
+                "8000  B5 0F     LDA $0F,X @ 10 = 80             A:00 X:01 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7",
+                "8002  A7 10    *LAX $10 = 80                    A:80 X:01 Y:00 P:A4 SP:FD PPU:  0, 33 CYC:11",
+                "8004  4C F5 C5  JMP $C5F5                       A:80 X:80 Y:00 P:A4 SP:FD PPU:  0, 42 CYC:14",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tick_counts_interrupts_and_dmc_stalls() {
+        let mut cpu = setup_cpu(test_program(vec![0xea, 0xea]));
+        cpu.reset();
+        cpu.bus.ppu.has_nmi = Some(true);
+        cpu.tick();
+        assert_eq!(cpu.cycles, 7 + 7);
+        cpu.set_pc(0x8000);
+        cpu.bus.write_u8(0x4015, 0x10);
+        cpu.tick();
+        assert_eq!(cpu.cycles, 7 + 7 + 2 + DMC_STALL_CYCLES as u64);
+    }
 }
@@ -0,0 +1,93 @@
+/// visible pixels per scanline
+pub const WIDTH: usize = 256;
+/// visible scanlines
+pub const HEIGHT: usize = 240;
+
+#[rustfmt::skip]
+pub static DEFAULT_PALETTE: [(u8,u8,u8); 64] = [
+   (0x80, 0x80, 0x80), (0x00, 0x3D, 0xA6), (0x00, 0x12, 0xB0), (0x44, 0x00, 0x96), (0xA1, 0x00, 0x5E),
+   (0xC7, 0x00, 0x28), (0xBA, 0x06, 0x00), (0x8C, 0x17, 0x00), (0x5C, 0x2F, 0x00), (0x10, 0x45, 0x00),
+   (0x05, 0x4A, 0x00), (0x00, 0x47, 0x2E), (0x00, 0x41, 0x66), (0x00, 0x00, 0x00), (0x05, 0x05, 0x05),
+   (0x05, 0x05, 0x05), (0xC7, 0xC7, 0xC7), (0x00, 0x77, 0xFF), (0x21, 0x55, 0xFF), (0x82, 0x37, 0xFA),
+   (0xEB, 0x2F, 0xB5), (0xFF, 0x29, 0x50), (0xFF, 0x22, 0x00), (0xD6, 0x32, 0x00), (0xC4, 0x62, 0x00),
+   (0x35, 0x80, 0x00), (0x05, 0x8F, 0x00), (0x00, 0x8A, 0x55), (0x00, 0x99, 0xCC), (0x21, 0x21, 0x21),
+   (0x09, 0x09, 0x09), (0x09, 0x09, 0x09), (0xFF, 0xFF, 0xFF), (0x0F, 0xD7, 0xFF), (0x69, 0xA2, 0xFF),
+   (0xD4, 0x80, 0xFF), (0xFF, 0x45, 0xF3), (0xFF, 0x61, 0x8B), (0xFF, 0x88, 0x33), (0xFF, 0x9C, 0x12),
+   (0xFA, 0xBC, 0x20), (0x9F, 0xE3, 0x0E), (0x2B, 0xF0, 0x35), (0x0C, 0xF0, 0xA4), (0x05, 0xFB, 0xFF),
+   (0x5E, 0x5E, 0x5E), (0x0D, 0x0D, 0x0D), (0x0D, 0x0D, 0x0D), (0xFF, 0xFF, 0xFF), (0xA6, 0xFC, 0xFF),
+   (0xB3, 0xEC, 0xFF), (0xDA, 0xAB, 0xEB), (0xFF, 0xA8, 0xF9), (0xFF, 0xAB, 0xB3), (0xFF, 0xD2, 0xB0),
+   (0xFF, 0xEF, 0xA6), (0xFF, 0xF7, 0x9C), (0xD7, 0xE8, 0x95), (0xA6, 0xED, 0xAF), (0xA2, 0xF2, 0xDA),
+   (0x99, 0xFF, 0xFC), (0xDD, 0xDD, 0xDD), (0x11, 0x11, 0x11), (0x11, 0x11, 0x11)
+];
+
+/// how much an emphasis bit dims the other two channels
+const EMPHASIS_ATTENUATION: f32 = 0.816;
+
+/// returns the colour of a pixel from the PPU's frame
+///
+/// Each emphasis bit (red, green, blue from bit 6) darkens the other two channels. The greys
+/// in columns $xE and $xF are black on the video signal and aren't affected.
+///
+/// - https://www.nesdev.org/wiki/NTSC_video#Color_Tint_Bits
+pub fn rgb(pixel: u16) -> (u8, u8, u8) {
+    let colour = (pixel & 0x3f) as u8;
+    let (r, g, b) = DEFAULT_PALETTE[colour as usize];
+    let emphasis = (pixel >> 6) & 0x07;
+    if emphasis == 0 || colour & 0x0f >= 0x0e {
+        return (r, g, b);
+    }
+    let attenuate = |value: u8, bit: u16| {
+        if emphasis & !bit != 0 {
+            (value as f32 * EMPHASIS_ATTENUATION) as u8
+        } else {
+            value
+        }
+    };
+    (
+        attenuate(r, 0b001),
+        attenuate(g, 0b010),
+        attenuate(b, 0b100),
+    )
+}
+
+/// returns a new frame of WIDTH x HEIGHT pixels showing colour $00
+///
+/// Frames are plain NES colour indices rather than a windowing library's image, so they can
+/// be rendered headless, compared in tests or uploaded to a texture by the frontend.
+pub fn new() -> Vec<u16> {
+    vec![0; WIDTH * HEIGHT]
+}
+
+/// converts a frame of NES colour indices to RGBA bytes, row by row
+pub fn to_rgba(frame: &[u16]) -> Vec<u8> {
+    frame
+        .iter()
+        .flat_map(|&pixel| {
+            let (r, g, b) = rgb(pixel);
+            [r, g, b, 0xff]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_emphasis() {
+        assert_eq!(rgb(0x30), (0xff, 0xff, 0xff));
+        // red emphasis dims green and blue, all three dim everything
+        assert_eq!(rgb(0x01 << 6 | 0x30), (0xff, 0xd0, 0xd0));
+        assert_eq!(rgb(0x07 << 6 | 0x30), (0xd0, 0xd0, 0xd0));
+        assert_eq!(rgb(0x07 << 6 | 0x0f), DEFAULT_PALETTE[0x0f]);
+    }
+
+    #[test]
+    fn test_to_rgba() {
+        let mut frame = new();
+        frame[1] = 0x30;
+        let rgba = to_rgba(&frame);
+        assert_eq!(rgba.len(), WIDTH * HEIGHT * 4);
+        assert_eq!(rgba[..8], [0x80, 0x80, 0x80, 0xff, 0xff, 0xff, 0xff, 0xff]);
+    }
+}
@@ -0,0 +1,436 @@
+use crate::controller::ControllerButtons;
+use crate::cpu::Cpu;
+use crate::wav::WavRecorder;
+use log::debug;
+
+/// exit status when the run ended as expected
+pub const EXIT_SUCCESS: i32 = 0;
+/// exit status when the condition wasn't met within the frame limit
+pub const EXIT_TIMEOUT: i32 = 1;
+/// exit status when the rom couldn't be loaded or the recording couldn't be created
+pub const EXIT_ERROR: i32 = 2;
+/// exit status when a test rom reported a failure
+pub const EXIT_FAILED: i32 = 3;
+
+/// frames a run is limited to when no other limit is given, a minute of emulated time
+pub const DEFAULT_FRAMES: u32 = 3600;
+
+/// test roms keep this many frames, about 100ms, between asking for a reset and expecting it
+const RESET_DELAY_FRAMES: u32 = 6;
+
+/// where test roms following blargg's protocol report their progress
+const TEST_STATUS: u16 = 0x6000;
+const TEST_SIGNATURE: [u8; 3] = [0xde, 0xb0, 0x61];
+const TEST_MESSAGE: u16 = 0x6004;
+const TEST_RUNNING: u8 = 0x80;
+const TEST_NEEDS_RESET: u8 = 0x81;
+
+/// Controller state held from a frame until the next input in the script
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Input {
+    pub frame: u32,
+    pub buttons: ControllerButtons,
+}
+
+/// parses an input script of `frame:buttons` entries separated by commas, with buttons
+/// joined by `+` and an empty list releasing everything, e.g. "60:start,64:,90:a+right"
+pub fn parse_script(script: &str) -> Option<Vec<Input>> {
+    let mut inputs: Vec<Input> = Vec::new();
+    for entry in script.split(',').filter(|entry| !entry.is_empty()) {
+        let (frame, names) = entry.split_once(':')?;
+        let frame = frame.parse().ok()?;
+        if inputs.last().map_or(false, |input| input.frame >= frame) {
+            return None;
+        }
+        let mut buttons = ControllerButtons::empty();
+        for name in names.split('+').filter(|name| !name.is_empty()) {
+            buttons |= button(name)?;
+        }
+        inputs.push(Input { frame, buttons });
+    }
+    Some(inputs)
+}
+
+fn button(name: &str) -> Option<ControllerButtons> {
+    Some(match name {
+        "a" => ControllerButtons::A_BUTTON,
+        "b" => ControllerButtons::B_BUTTON,
+        "select" => ControllerButtons::SELECT,
+        "start" => ControllerButtons::START,
+        "up" => ControllerButtons::UP,
+        "down" => ControllerButtons::DOWN,
+        "left" => ControllerButtons::LEFT,
+        "right" => ControllerButtons::RIGHT,
+        _ => return None,
+    })
+}
+
+/// What ends a run before its frame limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    /// a byte of CPU memory holds a value
+    Memory { address: u16, value: u8 },
+    /// a test rom reporting through $6000 has finished
+    TestStatus,
+}
+
+impl Condition {
+    /// parses `address=value` in hex, e.g. "6000=80". Only RAM and cartridge space can be
+    /// watched, memory is peeked so the PPU and APU registers would always read $FF.
+    pub fn parse(condition: &str) -> Option<Self> {
+        let (address, value) = condition.split_once('=')?;
+        let address = u16::from_str_radix(address.trim_start_matches('$'), 16).ok()?;
+        let value = u8::from_str_radix(value.trim_start_matches('$'), 16).ok()?;
+        if (0x2000..0x4020).contains(&address) {
+            return None;
+        }
+        Some(Condition::Memory { address, value })
+    }
+}
+
+/// returns the status byte of a test rom, once it has written the signature after it
+///
+/// $80 means the test is running and $81 that it wants the reset button pressed, anything
+/// else is the final result with 0 for a pass.
+///
+/// - https://github.com/christopherpow/nes-test-roms/blob/master/README.md
+pub fn test_status(cpu: &Cpu) -> Option<u8> {
+    let signature = [
+        cpu.bus.peek_u8(TEST_STATUS + 1),
+        cpu.bus.peek_u8(TEST_STATUS + 2),
+        cpu.bus.peek_u8(TEST_STATUS + 3),
+    ];
+    if signature != TEST_SIGNATURE {
+        return None;
+    }
+    Some(cpu.bus.peek_u8(TEST_STATUS))
+}
+
+/// returns the zero terminated text a test rom wrote from $6004
+pub fn test_message(cpu: &Cpu) -> String {
+    let mut message = Vec::new();
+    for address in TEST_MESSAGE..0x8000 {
+        match cpu.bus.peek_u8(address) {
+            0 => break,
+            byte => message.push(byte),
+        }
+    }
+    String::from_utf8_lossy(&message).trim_end().to_string()
+}
+
+/// How a headless run ended and after how many frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// ran every frame without a condition to wait for
+    Completed(u32),
+    /// the condition was met
+    Matched(u32),
+    /// the frame limit was reached before the condition was met
+    TimedOut(u32),
+    /// a test rom finished with a result code of 0
+    Passed(u32),
+    /// a test rom finished with a failing result code
+    Failed(u32, u8),
+}
+
+impl Outcome {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Outcome::Completed(_) | Outcome::Matched(_) | Outcome::Passed(_) => EXIT_SUCCESS,
+            Outcome::TimedOut(_) => EXIT_TIMEOUT,
+            Outcome::Failed(..) => EXIT_FAILED,
+        }
+    }
+}
+
+/// runs the console without a window for up to `frames` frames, feeding the controller from
+/// the script and checking the condition after each frame
+///
+/// The cpu should already be reset. Audio is written to the recorder if there is one. Test roms
+/// asking for a reset get one after the delay they expect.
+pub fn run(
+    cpu: &mut Cpu,
+    frames: u32,
+    condition: Option<Condition>,
+    script: &[Input],
+    mut recorder: Option<&mut WavRecorder>,
+) -> Outcome {
+    let mut script = script.iter().peekable();
+    let mut reset_frame = None;
+    let mut last_status = None;
+    for frame in 0..frames {
+        while let Some(input) = script.next_if(|input| input.frame <= frame) {
+            debug!("frame {}: pressing {:?}", frame, input.buttons);
+            cpu.bus.controller.set_buttons(input.buttons);
+        }
+        if reset_frame == Some(frame) {
+            debug!("frame {}: pressing reset", frame);
+            cpu.soft_reset();
+            reset_frame = None;
+        }
+        while !cpu.tick() {}
+        let samples = cpu.bus.apu.take_samples();
+        if let Some(wav) = &mut recorder {
+            if let Err(e) = wav.write(&samples, cpu.bus.apu.take_channel_samples()) {
+                println!("unable to record audio: {}", e);
+                recorder = None;
+            }
+        }
+
+        match condition {
+            Some(Condition::Memory { address, value }) if cpu.bus.peek_u8(address) == value => {
+                return Outcome::Matched(frame + 1);
+            }
+            Some(Condition::TestStatus) => {
+                let status = test_status(cpu);
+                match status {
+                    None | Some(TEST_RUNNING) => {}
+                    // the status stays $81 until the rom restarts, so only reset once per request
+                    Some(TEST_NEEDS_RESET) if last_status != status => {
+                        reset_frame = Some(frame + RESET_DELAY_FRAMES);
+                    }
+                    Some(TEST_NEEDS_RESET) => {}
+                    Some(0) => return Outcome::Passed(frame + 1),
+                    Some(code) => return Outcome::Failed(frame + 1, code),
+                }
+                last_status = status;
+            }
+            _ => {}
+        }
+    }
+    match condition {
+        Some(_) => Outcome::TimedOut(frames),
+        None => Outcome::Completed(frames),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::rom::Rom;
+    use rstest::rstest;
+    use std::path::PathBuf;
+    use std::{env, fs, panic};
+
+    /// blargg's test roms that report through $6000, relative to the directory in
+    /// CRABBINESS_TEST_ROMS and laid out as in https://github.com/christopherpow/nes-test-roms
+    const TEST_ROM_SUITE: [&str; 9] = [
+        "instr_test-v5/official_only.nes",
+        "instr_misc/instr_misc.nes",
+        "instr_timing/instr_timing.nes",
+        "cpu_interrupts_v2/cpu_interrupts.nes",
+        "apu_test/apu_test.nes",
+        "ppu_vbl_nmi/ppu_vbl_nmi.nes",
+        "ppu_open_bus/ppu_open_bus.nes",
+        "oam_read/oam_read.nes",
+        "mmc3_test_2/rom_singles/1-clocking.nes",
+    ];
+
+    /// returns a reset cpu running a program at $8000
+    fn setup_cpu(program: &[u8]) -> Cpu {
+        let mut prg_rom = vec![0; 0x8000];
+        prg_rom[..program.len()].copy_from_slice(program);
+        prg_rom[0x7ffc..0x7ffe].copy_from_slice(&[0x00, 0x80]);
+        let mut cpu = Cpu::new(Bus::new(Rom::new_from_vec(prg_rom)));
+        cpu.reset();
+        cpu
+    }
+
+    /// copies the A button into $10
+    #[rustfmt::skip]
+    const READ_CONTROLLER: [u8; 20] = [
+        0xa9, 0x01, 0x8d, 0x16, 0x40, // LDA #1, STA $4016
+        0xa9, 0x00, 0x8d, 0x16, 0x40, // LDA #0, STA $4016
+        0xad, 0x16, 0x40, 0x29, 0x01, // LDA $4016, AND #1
+        0x85, 0x10, 0x4c, 0x00, 0x80, // STA $10, JMP $8000
+    ];
+
+    /// a test rom which asks to be reset once, then reports "ok" and a result code
+    #[rustfmt::skip]
+    fn reset_test(code: u8) -> Vec<u8> {
+        vec![
+            0xa9, 0xde, 0x8d, 0x01, 0x60, // signature
+            0xa9, 0xb0, 0x8d, 0x02, 0x60,
+            0xa9, 0x61, 0x8d, 0x03, 0x60,
+            0xee, 0x10, 0x60,             // INC $6010, counting runs
+            0xad, 0x10, 0x60, 0xc9, 0x02, // LDA $6010, CMP #2
+            0xf0, 0x08,                   // BEQ done
+            0xa9, 0x81, 0x8d, 0x00, 0x60, // status $81
+            0x4c, 0x1e, 0x80,             // JMP *
+            0xa9, b'o', 0x8d, 0x04, 0x60, // done: message
+            0xa9, b'k', 0x8d, 0x05, 0x60,
+            0xa9, 0x00, 0x8d, 0x06, 0x60,
+            0xa9, code, 0x8d, 0x00, 0x60, // result
+            0x4c, 0x35, 0x80,             // JMP *
+        ]
+    }
+
+    #[test]
+    fn test_parse_script() {
+        assert_eq!(
+            parse_script("60:start,64:,90:a+right"),
+            Some(vec![
+                Input {
+                    frame: 60,
+                    buttons: ControllerButtons::START,
+                },
+                Input {
+                    frame: 64,
+                    buttons: ControllerButtons::empty(),
+                },
+                Input {
+                    frame: 90,
+                    buttons: ControllerButtons::A_BUTTON | ControllerButtons::RIGHT,
+                },
+            ])
+        );
+        assert_eq!(parse_script(""), Some(vec![]));
+    }
+
+    #[rstest]
+    #[case("60")]
+    #[case("x:a")]
+    #[case("60:turbo")]
+    #[case("60:a,30:b")]
+    fn test_parse_script_invalid(#[case] script: &str) {
+        assert_eq!(parse_script(script), None);
+    }
+
+    #[test]
+    fn test_parse_condition() {
+        assert_eq!(
+            Condition::parse("6000=80"),
+            Some(Condition::Memory {
+                address: 0x6000,
+                value: 0x80,
+            })
+        );
+        assert_eq!(
+            Condition::parse("$10=$ff"),
+            Some(Condition::Memory {
+                address: 0x10,
+                value: 0xff,
+            })
+        );
+        assert_eq!(Condition::parse("2002=80"), None);
+        assert_eq!(Condition::parse("6000"), None);
+    }
+
+    #[test]
+    fn test_run() {
+        let a_pressed = Condition::parse("10=01");
+        let script = parse_script("3:a").unwrap();
+        assert_eq!(
+            run(
+                &mut setup_cpu(&READ_CONTROLLER),
+                10,
+                a_pressed,
+                &script,
+                None
+            ),
+            Outcome::Matched(4)
+        );
+        assert_eq!(
+            run(&mut setup_cpu(&READ_CONTROLLER), 10, a_pressed, &[], None),
+            Outcome::TimedOut(10)
+        );
+        assert_eq!(
+            run(&mut setup_cpu(&READ_CONTROLLER), 5, None, &script, None),
+            Outcome::Completed(5)
+        );
+        assert_eq!(Outcome::TimedOut(10).exit_code(), EXIT_TIMEOUT);
+    }
+
+    #[test]
+    fn test_run_records_audio() {
+        let dir = env::temp_dir().join(format!("crabbiness-headless-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("run.wav");
+        let mut cpu = setup_cpu(&READ_CONTROLLER);
+        cpu.bus.apu.record_channels(true);
+        let mut recorder = WavRecorder::create(&path, true).unwrap();
+        run(&mut cpu, 6, None, &[], Some(&mut recorder));
+        recorder.finalize().unwrap();
+
+        // 6 frames is a tenth of a second
+        let reader = hound::WavReader::open(&path).unwrap();
+        let expected = reader.spec().sample_rate as i64 / 10;
+        assert!((reader.duration() as i64 - expected).abs() < 100);
+        let reader = hound::WavReader::open(dir.join("run-noise.wav")).unwrap();
+        assert!((reader.duration() as i64 - expected).abs() < 100);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[rstest]
+    #[case(0x00, Outcome::Passed(7))]
+    #[case(0x03, Outcome::Failed(7, 0x03))]
+    fn test_status_protocol(#[case] code: u8, #[case] expected: Outcome) {
+        // $81 is seen after the first frame and the reset happens 6 frames later
+        let mut cpu = setup_cpu(&reset_test(code));
+        assert_eq!(
+            run(&mut cpu, 20, Some(Condition::TestStatus), &[], None),
+            expected
+        );
+        assert_eq!(test_message(&cpu), "ok");
+        assert_eq!(expected.exit_code() == EXIT_SUCCESS, code == 0);
+
+        // without the reset it never finishes
+        let mut cpu = setup_cpu(&reset_test(code));
+        assert_eq!(
+            run(&mut cpu, 6, Some(Condition::TestStatus), &[], None),
+            Outcome::TimedOut(6)
+        );
+    }
+
+    #[test]
+    #[ignore = "needs the test roms, run with CRABBINESS_TEST_ROMS=<dir> cargo test -- --ignored"]
+    fn test_rom_suite() {
+        let dir = PathBuf::from(
+            env::var("CRABBINESS_TEST_ROMS").expect("CRABBINESS_TEST_ROMS should be set"),
+        );
+        if let Err(e) = fs::read_dir(&dir) {
+            panic!("unable to read {}: {}", dir.display(), e);
+        }
+        let mut failures = Vec::new();
+        let mut ran = 0;
+        for name in TEST_ROM_SUITE {
+            let data = match fs::read(dir.join(name)) {
+                Ok(data) => data,
+                Err(e) => {
+                    println!("{}: missing, {}", name, e);
+                    failures.push(name);
+                    continue;
+                }
+            };
+            ran += 1;
+            // unimplemented corners of the hardware panic, which counts as a failure
+            let result = panic::catch_unwind(|| {
+                let rom = Rom::new_from_ines(&data).unwrap();
+                let mut cpu = Cpu::new(Bus::new(rom));
+                cpu.reset();
+                let outcome = run(
+                    &mut cpu,
+                    DEFAULT_FRAMES,
+                    Some(Condition::TestStatus),
+                    &[],
+                    None,
+                );
+                (outcome, test_message(&cpu))
+            });
+            match result {
+                Ok((Outcome::Passed(_), _)) => println!("{}: passed", name),
+                Ok((outcome, message)) => {
+                    println!("{}: {:?}\n{}", name, outcome, message);
+                    failures.push(name);
+                }
+                Err(_) => {
+                    println!("{}: panicked", name);
+                    failures.push(name);
+                }
+            }
+        }
+        assert!(ran > 0, "no test roms found in {}", dir.display());
+        assert!(failures.is_empty(), "failed: {:?}", failures);
+    }
+}
@@ -5,63 +5,435 @@
 extern crate core;
 
 use std::env;
-use std::io::Read;
+use std::path::{Path, PathBuf};
 use std::{fs, thread};
 
 use macroquad::prelude::*;
 
+mod apu;
+mod audio;
 mod bus;
 mod controller;
 mod cpu;
+mod frame;
+mod headless;
+mod mapper;
+#[cfg(test)]
+mod nestest;
+mod nsf;
 mod ppu;
 mod render;
 mod rom;
+mod wav;
 
-#[macroquad::main("crabbiness")]
-async fn main() {
+/// how often battery backed RAM is flushed to disk while running
+const SAV_FLUSH_FRAMES: u32 = 300;
+
+const USAGE: &str = "usage: [--wav <file>] [--wav-channels] [--no-sprite-limit] <nes or nsf file>
+       --headless [--frames <n>] [--until <address>=<value> | --test-rom] [--input <script>]
+                  [--wav <file>] [--wav-channels] <nes file>
+
+headless runs exit with 0 when the frames ran or the condition was met, 1 when the
+condition wasn't met in time, 2 when the rom couldn't be loaded or the recording couldn't
+be created and 3 when a test rom failed. --test-rom waits for a result from a test rom
+reporting through $6000. the input script lists frame:buttons entries, e.g.
+60:start,64:,90:a+right";
+
+/// command line options
+#[derive(Debug, Default, PartialEq)]
+struct Options {
+    rom: String,
+    /// record the mixed audio to this file
+    wav: Option<PathBuf>,
+    /// also record each APU channel next to the --wav file
+    wav_channels: bool,
+    /// draw every sprite on a scanline instead of the first 8
+    no_sprite_limit: bool,
+    /// run without a window, for automated tests
+    headless: bool,
+    /// frames to run for in headless mode
+    frames: Option<u32>,
+    /// stop a headless run once memory holds a value or a test rom has finished
+    until: Option<headless::Condition>,
+    /// controller input for a headless run
+    input: Vec<headless::Input>,
+}
+
+/// parses the command line, returning None if it doesn't make sense
+fn parse_args(args: &[String]) -> Option<Options> {
+    let mut options = Options::default();
+    let mut rom = None;
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--wav" => options.wav = Some(PathBuf::from(args.next()?)),
+            "--wav-channels" => options.wav_channels = true,
+            "--no-sprite-limit" => options.no_sprite_limit = true,
+            "--headless" => options.headless = true,
+            "--frames" => options.frames = Some(args.next()?.parse().ok()?),
+            "--until" if options.until.is_none() => {
+                options.until = Some(headless::Condition::parse(args.next()?)?)
+            }
+            "--test-rom" if options.until.is_none() => {
+                options.until = Some(headless::Condition::TestStatus)
+            }
+            "--input" => options.input = headless::parse_script(args.next()?)?,
+            _ if arg.starts_with("--") || rom.is_some() => return None,
+            _ => rom = Some(arg.clone()),
+        }
+    }
+    if options.wav_channels && options.wav.is_none() {
+        return None;
+    }
+    let headless_only =
+        options.frames.is_some() || options.until.is_some() || !options.input.is_empty();
+    if headless_only && !options.headless {
+        return None;
+    }
+    options.rom = rom?;
+    Some(options)
+}
+
+/// keys toggling each APU channel, in apu::Channel::ALL order
+const CHANNEL_KEYS: [KeyCode; 5] = [
+    KeyCode::F1,
+    KeyCode::F2,
+    KeyCode::F3,
+    KeyCode::F4,
+    KeyCode::F5,
+];
+
+/// how much shift or alt with F1-F5 raises or lowers a channel's gain
+const GAIN_STEP: f32 = 0.25;
+const MAX_GAIN: f32 = 4.0;
+
+/// F1-F5 mute a channel, with control held they solo it instead and with shift or alt held
+/// they raise or lower its gain. F6 restores everything.
+fn read_audio_keys(apu: &mut apu::Apu) {
+    let control = is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl);
+    let shift = is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift);
+    let alt = is_key_down(KeyCode::LeftAlt) || is_key_down(KeyCode::RightAlt);
+    for (channel, key) in apu::Channel::ALL.iter().zip(CHANNEL_KEYS) {
+        if !is_key_pressed(key) {
+            continue;
+        }
+        if control {
+            apu.set_solo(*channel, !apu.solo(*channel));
+        } else if shift {
+            apu.set_gain(*channel, (apu.gain(*channel) + GAIN_STEP).min(MAX_GAIN));
+        } else if alt {
+            apu.set_gain(*channel, apu.gain(*channel) - GAIN_STEP);
+        } else {
+            apu.set_muted(*channel, !apu.muted(*channel));
+        }
+    }
+    if is_key_pressed(KeyCode::F6) {
+        for channel in apu::Channel::ALL {
+            apu.set_muted(channel, false);
+            apu.set_solo(channel, false);
+            apu.set_gain(channel, 1.0);
+        }
+    }
+}
+
+/// lists the channels that can be heard with any gain that was changed, e.g.
+/// "pulse1 pulse2 -- noise:1.50 dmc"
+fn audio_status(apu: &apu::Apu) -> String {
+    apu::Channel::ALL
+        .iter()
+        .map(|channel| {
+            if !apu.audible(*channel) {
+                "--".to_string()
+            } else if apu.gain(*channel) != 1.0 {
+                format!("{}:{:.2}", channel.name(), apu.gain(*channel))
+            } else {
+                channel.name().to_string()
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// opens the --wav recording if one was asked for
+fn open_recorder(
+    options: &Options,
+    apu: &mut apu::Apu,
+) -> Result<Option<wav::WavRecorder>, String> {
+    let path = match &options.wav {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    apu.record_channels(options.wav_channels);
+    wav::WavRecorder::create(path, options.wav_channels)
+        .map(Some)
+        .map_err(|e| format!("unable to record {}: {}", path.display(), e))
+}
+
+/// hands the samples produced since the last call to the recorder and the audio device
+fn output_audio(
+    apu: &mut apu::Apu,
+    audio: &mut audio::Audio,
+    recorder: &mut Option<wav::WavRecorder>,
+) {
+    let samples = apu.take_samples();
+    if let Some(recorder) = recorder {
+        if let Err(e) = recorder.write(&samples, apu.take_channel_samples()) {
+            println!("unable to record audio: {}", e);
+        }
+    }
+    audio.push(&samples);
+    audio.sync();
+}
+
+fn close_recorder(recorder: Option<wav::WavRecorder>) {
+    if let Some(recorder) = recorder {
+        if let Err(e) = recorder.finalize() {
+            println!("unable to record audio: {}", e);
+        }
+    }
+}
+
+/// plays an NSF file showing the track and channel activity, left and right change track
+async fn play_nsf(data: &[u8], options: &Options) {
+    let nsf = match nsf::Nsf::new(data) {
+        Ok(nsf) => nsf,
+        Err(e) => {
+            println!("unable to load {}: {}", options.rom, e);
+            return;
+        }
+    };
+    let mut player = nsf::Player::new(nsf);
+    let mut audio = audio::Audio::new();
+    let mut recorder = match open_recorder(options, &mut player.cpu.bus.apu) {
+        Ok(recorder) => recorder,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    // run as many PLAY calls as fit in a 60Hz display frame
+    let frame_cycles = (audio::CPU_CLOCK_HZ / 60.0) as usize;
+    let mut cycles = 0;
+    prevent_quit();
+    while !is_quit_requested() {
+        if is_key_pressed(KeyCode::Right) {
+            player.next_track();
+        }
+        if is_key_pressed(KeyCode::Left) {
+            player.prev_track();
+        }
+        read_audio_keys(&mut player.cpu.bus.apu);
+
+        while cycles < frame_cycles {
+            cycles += player.run_frame();
+        }
+        cycles -= frame_cycles;
+        let peaks = player.cpu.bus.apu.take_peaks();
+        output_audio(&mut player.cpu.bus.apu, &mut audio, &mut recorder);
+
+        clear_background(BLACK);
+        let nsf = &player.nsf;
+        let track = player.track();
+        let lines = [
+            nsf.title.clone(),
+            nsf.artist.clone(),
+            nsf.copyright.clone(),
+            String::new(),
+            format!(
+                "track {}/{} {}",
+                track + 1,
+                nsf.songs,
+                nsf.track_title(track).unwrap_or_default()
+            ),
+        ];
+        for (i, line) in lines.iter().enumerate() {
+            draw_text(line, 20.0, 40.0 + 30.0 * i as f32, 30.0, WHITE);
+        }
+        for (i, channel) in apu::Channel::ALL.iter().enumerate() {
+            let y = 220.0 + 30.0 * i as f32;
+            let max = if *channel == apu::Channel::Dmc {
+                127.0
+            } else {
+                15.0
+            };
+            let color = if player.cpu.bus.apu.audible(*channel) {
+                GREEN
+            } else {
+                GRAY
+            };
+            draw_text(channel.name(), 20.0, y, 30.0, color);
+            draw_rectangle(150.0, y - 18.0, 300.0 * peaks[i] as f32 / max, 18.0, color);
+        }
+        draw_text(
+            "left/right: track  F1-F5: mute  ctrl: solo  shift/alt: gain  F6: reset",
+            20.0,
+            screen_height() - 20.0,
+            20.0,
+            GRAY,
+        );
+        next_frame().await
+    }
+    close_recorder(recorder);
+}
+
+/// reads a rom or NSF file, printing why it couldn't be
+fn read_file(filename: &str) -> Option<Vec<u8>> {
+    match fs::read(filename) {
+        Ok(data) => Some(data),
+        Err(e) => {
+            println!("unable to read {}: {}", filename, e);
+            None
+        }
+    }
+}
+
+/// loads a rom, printing why it couldn't be
+fn load_rom(filename: &str) -> Option<rom::Rom> {
+    parse_rom(filename, &read_file(filename)?)
+}
+
+/// parses a rom read from filename, printing why it couldn't be
+fn parse_rom(filename: &str, data: &[u8]) -> Option<rom::Rom> {
+    match rom::Rom::new_from_ines(data) {
+        Ok(rom) => Some(rom),
+        Err(e) => {
+            println!("unable to load {}: {}", filename, e);
+            None
+        }
+    }
+}
+
+/// runs a rom without opening a window and returns the exit status
+fn run_headless(options: &Options) -> i32 {
+    let rom = match load_rom(&options.rom) {
+        Some(rom) => rom,
+        None => return headless::EXIT_ERROR,
+    };
+    let mut bus = bus::Bus::new(rom);
+    bus.ppu.set_sprite_limit(!options.no_sprite_limit);
+    let mut cpu = cpu::Cpu::new(bus);
+    cpu.reset();
+
+    let mut recorder = match open_recorder(options, &mut cpu.bus.apu) {
+        Ok(recorder) => recorder,
+        Err(e) => {
+            println!("{}", e);
+            return headless::EXIT_ERROR;
+        }
+    };
+
+    let frames = options.frames.unwrap_or(headless::DEFAULT_FRAMES);
+    let outcome = headless::run(
+        &mut cpu,
+        frames,
+        options.until,
+        &options.input,
+        recorder.as_mut(),
+    );
+    close_recorder(recorder);
+    if options.until == Some(headless::Condition::TestStatus) {
+        println!("{}", headless::test_message(&cpu));
+    }
+    match outcome {
+        headless::Outcome::Completed(frames) => println!("ran {} frames", frames),
+        headless::Outcome::Matched(frames) => println!("condition met after {} frames", frames),
+        headless::Outcome::TimedOut(frames) => {
+            println!("condition not met after {} frames", frames)
+        }
+        headless::Outcome::Passed(frames) => println!("passed after {} frames", frames),
+        headless::Outcome::Failed(frames, code) => {
+            println!("failed with code {} after {} frames", code, frames)
+        }
+    }
+    outcome.exit_code()
+}
+
+fn main() {
     // setup logger
     env_logger::init();
 
     // parse command line args
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        println!("usage: <nes file>");
-        return;
+    let options = match parse_args(&args) {
+        Some(options) => options,
+        None => {
+            println!("{}", USAGE);
+            std::process::exit(headless::EXIT_ERROR);
+        }
+    };
+    if options.headless {
+        std::process::exit(run_headless(&options));
     }
-    let filename = &args[1];
+    macroquad::Window::new("crabbiness", run(options));
+}
+
+/// runs a rom or NSF file in a window until it's closed
+async fn run(options: Options) {
+    let filename = &options.rom;
 
     // load rom from disk
-    let mut file = fs::File::open(filename).unwrap();
-    let mut data: Vec<u8> = Vec::new();
-    file.read_to_end(&mut data).unwrap();
-    let r = rom::Rom::new_from_ines(&data);
+    let data = match read_file(filename) {
+        Some(data) => data,
+        None => return,
+    };
+    if nsf::is_nsf(&data) {
+        play_nsf(&data, &options).await;
+        return;
+    }
+    let r = match parse_rom(filename, &data) {
+        Some(r) => r,
+        None => return,
+    };
 
     // setup bus, cpu
-    let bus = bus::Bus::new(r);
+    let mut bus = bus::Bus::new(r);
+    bus.ppu.set_sprite_limit(!options.no_sprite_limit);
+    let sav_path = Path::new(filename).with_extension("sav");
+    if let Err(e) = bus.load_sav(&sav_path) {
+        println!("unable to load {}: {}", sav_path.display(), e);
+    }
     let mut cpu = cpu::Cpu::new(bus);
 
     // setup graphics
-    let mut image = Image::gen_image_color(320 as u16, 320 as u16, BLACK);
+    let mut audio = audio::Audio::new();
+    let mut recorder = match open_recorder(&options, &mut cpu.bus.apu) {
+        Ok(recorder) => recorder,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    let mut image = Image::gen_image_color(320, 320, BLACK);
 
     let mut counter: u32 = 0;
+    let mut frames: u32 = 0;
 
     // run cpu
     cpu.reset();
     clear_background(BLUE);
+    prevent_quit();
     loop {
-        let cycles = if cpu.bus.take_nmi() {
-            cpu.nmi();
-            2
-        } else {
-            cpu.step()
-        };
-        let render = cpu.bus.tick(cycles);
+        let render = cpu.tick();
 
         // get controller input from keyboard
         cpu.bus.read_keys();
         counter = counter.wrapping_add(1);
 
         if render {
+            frames = frames.wrapping_add(1);
+            if frames % SAV_FLUSH_FRAMES == 0 || is_quit_requested() {
+                if let Err(e) = cpu.bus.flush_sav(&sav_path) {
+                    println!("unable to save {}: {}", sav_path.display(), e);
+                }
+            }
+            if is_quit_requested() {
+                break;
+            }
+
+            output_audio(&mut cpu.bus.apu, &mut audio, &mut recorder);
+            read_audio_keys(&mut cpu.bus.apu);
+
             render::draw(&cpu.bus.ppu, &mut image);
             let tex_params = DrawTextureParams {
                 dest_size: Some(vec2(screen_width(), screen_height())),
@@ -87,6 +459,14 @@ async fn main() {
                 GRAY,
             );
 
+            draw_text(
+                audio_status(&cpu.bus.apu).as_str(),
+                0.0,
+                screen_height() - 120.0,
+                30.0,
+                GRAY,
+            );
+
             draw_text(
                 format!("{} {}", counter, get_fps()).trim(),
                 screen_width() - 150.0,
@@ -98,4 +478,72 @@ async fn main() {
             next_frame().await
         }
     }
+
+    close_recorder(recorder);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(
+            parse_args(&args(
+                "crabbiness --wav out.wav --wav-channels --no-sprite-limit game.nes"
+            )),
+            Some(Options {
+                rom: "game.nes".to_string(),
+                wav: Some(PathBuf::from("out.wav")),
+                wav_channels: true,
+                no_sprite_limit: true,
+                ..Options::default()
+            })
+        );
+        assert_eq!(
+            parse_args(&args(
+                "crabbiness --headless --frames 600 --until 6000=80 --input 60:start test.nes"
+            )),
+            Some(Options {
+                rom: "test.nes".to_string(),
+                headless: true,
+                frames: Some(600),
+                until: headless::Condition::parse("6000=80"),
+                input: headless::parse_script("60:start").unwrap(),
+                ..Options::default()
+            })
+        );
+        assert_eq!(
+            parse_args(&args("crabbiness --headless --test-rom test.nes")).map(|o| o.until),
+            Some(Some(headless::Condition::TestStatus))
+        );
+    }
+
+    #[test]
+    fn test_audio_status() {
+        let mut apu = apu::Apu::new();
+        apu.set_muted(apu::Channel::Triangle, true);
+        assert_eq!(audio_status(&apu), "pulse1 pulse2 -- noise dmc");
+        apu.set_gain(apu::Channel::Noise, 1.5);
+        assert_eq!(audio_status(&apu), "pulse1 pulse2 -- noise:1.50 dmc");
+    }
+
+    #[rstest]
+    #[case("crabbiness")]
+    #[case("crabbiness game.nes other.nes")]
+    #[case("crabbiness --wav")]
+    #[case("crabbiness --wav-channels game.nes")]
+    #[case("crabbiness --bogus game.nes")]
+    #[case("crabbiness --frames 60 game.nes")]
+    #[case("crabbiness --headless --frames soon game.nes")]
+    #[case("crabbiness --headless --until 2002=80 game.nes")]
+    #[case("crabbiness --headless --until 6000=80 --test-rom game.nes")]
+    fn test_parse_args_invalid(#[case] line: &str) {
+        assert_eq!(parse_args(&args(line)), None);
+    }
 }
@@ -0,0 +1,213 @@
+use crate::rom::{Mirroring, Rom};
+use log::debug;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+const CHR_RAM_MIN_SIZE: usize = 0x2000;
+
+mod discrete;
+mod mmc1;
+mod mmc3;
+mod nrom;
+mod nsf;
+mod vrc6;
+
+pub use discrete::{Board, Discrete};
+pub use mmc1::Mmc1;
+pub use mmc3::Mmc3;
+pub use nrom::Nrom;
+pub use nsf::NsfMapper;
+pub use vrc6::Vrc6;
+
+/// Implements the cartridge side of the CPU and PPU buses
+///
+/// The cartridge decides what lives in $4020-$FFFF on the CPU bus and in the pattern tables
+/// ($0000-$1FFF) on the PPU bus. Boards with bank switching intercept writes to these regions
+/// to remap memory, change the nametable mirroring and raise interrupts.
+pub trait Mapper {
+    /// reads a byte from the cartridge at a CPU address in $4020-$FFFF
+    fn cpu_read(&mut self, address: u16) -> u8;
+
+    /// writes a byte to the cartridge at a CPU address in $4020-$FFFF
+    fn cpu_write(&mut self, address: u16, data: u8);
+
+    /// reads a byte from the pattern tables at a PPU address in $0000-$1FFF
+    fn ppu_read(&self, address: u16) -> u8;
+
+    /// writes a byte to the pattern tables at a PPU address in $0000-$1FFF
+    fn ppu_write(&mut self, address: u16, data: u8);
+
+    /// observes an address the PPU placed on its bus while fetching pattern data, letting
+    /// boards such as MMC3 clock counters from address line A12
+    fn ppu_address(&mut self, address: u16) {}
+
+    /// returns the work RAM mapped at $6000-$7FFF, empty for boards without any
+    fn prg_ram(&mut self) -> &mut [u8] {
+        &mut []
+    }
+
+    /// returns the current nametable mirroring mode
+    fn mirroring(&self) -> Mirroring;
+
+    /// returns true while the cartridge is asserting the IRQ line
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// clocked once per CPU cycle, for boards with their own timers or sound hardware
+    fn cpu_tick(&mut self) {}
+
+    /// returns the output of the board's sound hardware, mixed in with the APU
+    fn audio_output(&self) -> f32 {
+        0.0
+    }
+}
+
+type Constructor = fn(Rom) -> Rc<RefCell<dyn Mapper>>;
+
+/// returns how to build the given mapper number, None for boards that aren't supported
+fn constructor(number: u16) -> Option<Constructor> {
+    let constructor: Constructor = match number {
+        0 => |rom| Rc::new(RefCell::new(Nrom::new(rom))),
+        1 => |rom| Rc::new(RefCell::new(Mmc1::new(rom))),
+        2 => |rom| Rc::new(RefCell::new(Discrete::new(rom, Board::Uxrom))),
+        3 => |rom| Rc::new(RefCell::new(Discrete::new(rom, Board::Cnrom))),
+        4 => |rom| Rc::new(RefCell::new(Mmc3::new(rom))),
+        7 => |rom| Rc::new(RefCell::new(Discrete::new(rom, Board::Axrom))),
+        11 => |rom| Rc::new(RefCell::new(Discrete::new(rom, Board::ColorDreams))),
+        24 | 26 => |rom| Rc::new(RefCell::new(Vrc6::new(rom))),
+        // mapper 34 covers two boards, told apart by the amount of CHR ROM
+        34 => |rom| {
+            if rom.chr_rom.len() > 0x2000 {
+                Rc::new(RefCell::new(Discrete::new(rom, Board::Nina001)))
+            } else {
+                Rc::new(RefCell::new(Discrete::new(rom, Board::Bnrom)))
+            }
+        },
+        66 => |rom| Rc::new(RefCell::new(Discrete::new(rom, Board::Gxrom))),
+        _ => return None,
+    };
+    Some(constructor)
+}
+
+/// returns true if new() knows how to build the given mapper number
+pub fn is_supported(number: u16) -> bool {
+    constructor(number).is_some()
+}
+
+/// returns the least PRG ROM a board needs, smaller roms would leave its fixed banks empty
+pub fn min_prg_rom_bytes(number: u16) -> usize {
+    match number {
+        // the last two 8K banks are fixed
+        4 => 0x8000,
+        _ => 0x4000,
+    }
+}
+
+/// creates the mapper described by the rom header
+pub fn new(rom: Rom) -> Rc<RefCell<dyn Mapper>> {
+    match constructor(rom.mapper()) {
+        Some(constructor) => constructor(rom),
+        None => panic!("unsupported mapper {}", rom.mapper()),
+    }
+}
+
+/// returns the offset into a block of memory for an address within a switchable bank
+///
+/// Bank numbers wrap around the amount of memory present, matching boards that simply
+/// ignore the upper bank bits when fewer banks are populated.
+fn bank_offset(len: usize, bank: usize, bank_size: usize, address: u16) -> usize {
+    (bank * bank_size) % len + address as usize % bank_size
+}
+
+/// Pattern table memory on the cartridge
+///
+/// Most boards carry CHR ROM, but boards without any (a CHR ROM size of zero in the header)
+/// carry CHR RAM instead which games fill at runtime through PPUDATA.
+pub struct ChrMemory {
+    data: Vec<u8>,
+    writable: bool,
+}
+
+impl ChrMemory {
+    pub fn new(rom: &mut Rom) -> Self {
+        if rom.chr_rom.is_empty() {
+            let header = rom.header();
+            let size = (header.chr_ram_bytes + header.chr_nvram_bytes).max(CHR_RAM_MIN_SIZE);
+            debug!("allocating {} bytes of chr_ram", size);
+            ChrMemory {
+                data: vec![0; size],
+                writable: true,
+            }
+        } else {
+            ChrMemory {
+                data: std::mem::take(&mut rom.chr_rom),
+                writable: false,
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn read(&self, offset: usize) -> u8 {
+        self.data[offset]
+    }
+
+    /// writes to chr_ram, writes to chr_rom are ignored like on real hardware
+    pub fn write(&mut self, offset: usize, data: u8) {
+        if self.writable {
+            self.data[offset] = data;
+        } else {
+            debug!("ignoring chr_rom write {:02X} @ {:04X}", data, offset);
+        }
+    }
+}
+
+/// Work RAM on the cartridge at $6000-$7FFF
+///
+/// Sized from the header, which for iNES files defaults to 8K. Cartridges with a battery keep
+/// the contents between power cycles which is how games save progress.
+#[derive(Default)]
+pub struct PrgRam {
+    data: Vec<u8>,
+}
+
+impl PrgRam {
+    pub fn new(rom: &Rom) -> Self {
+        let header = rom.header();
+        PrgRam {
+            data: vec![0; header.prg_ram_bytes + header.prg_nvram_bytes],
+        }
+    }
+
+    pub fn with_size(size: usize) -> Self {
+        PrgRam {
+            data: vec![0; size],
+        }
+    }
+
+    /// reads from an address in $6000-$7FFF, smaller RAMs are mirrored
+    pub fn read(&self, address: u16) -> u8 {
+        if self.data.is_empty() {
+            debug!("unmapped cartridge read @ {:04X}", address);
+            return 0;
+        }
+        self.data[(address as usize - 0x6000) % self.data.len()]
+    }
+
+    /// writes to an address in $6000-$7FFF, smaller RAMs are mirrored
+    pub fn write(&mut self, address: u16, data: u8) {
+        if self.data.is_empty() {
+            debug!("ignoring cartridge write {:02X} @ {:04X}", data, address);
+            return;
+        }
+        let len = self.data.len();
+        self.data[(address as usize - 0x6000) % len] = data;
+    }
+
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+}
@@ -0,0 +1,238 @@
+use crate::mapper::{bank_offset, ChrMemory, Mapper, PrgRam};
+use crate::rom::{Mirroring, Rom};
+use log::debug;
+
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x1000;
+
+/// boards built from discrete logic, a latch and a few gates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Board {
+    /// mapper 2: 16K switchable at $8000, last bank fixed at $C000
+    Uxrom,
+    /// mapper 3: 8K switchable CHR
+    Cnrom,
+    /// mapper 7: 32K switchable PRG and single screen mirroring
+    Axrom,
+    /// mapper 11: 32K PRG in the low bits, 8K CHR in the high bits
+    ColorDreams,
+    /// mapper 34 without CHR ROM: 32K switchable PRG
+    Bnrom,
+    /// mapper 34 with CHR ROM: 32K PRG and two 4K CHR banks latched at $7FFD-$7FFF
+    Nina001,
+    /// mapper 66: 32K PRG in bits 4-5, 8K CHR in the low bits
+    Gxrom,
+}
+
+/// Latch based discrete mappers
+///
+/// All of these boards latch the value written to $8000-$FFFF into a register whose bits select
+/// the PRG and CHR banks directly. Most do not disconnect the ROM during writes, so the ROM
+/// and the CPU both drive the data bus and the latch sees the AND of the two values. Games
+/// avoid this by writing to a ROM location holding the same value, which is emulated here.
+///
+/// - https://www.nesdev.org/wiki/Bus_conflict
+pub struct Discrete {
+    board: Board,
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    prg_ram: PrgRam,
+    mirroring: Mirroring,
+    bus_conflicts: bool,
+    prg_bank: usize,
+    chr_banks: [usize; 2],
+}
+
+impl Discrete {
+    pub fn new(mut rom: Rom, board: Board) -> Self {
+        let mirroring = match board {
+            Board::Axrom => Mirroring::SingleScreenLower,
+            _ => rom.mirroring(),
+        };
+        // NES 2.0 submapper 1 marks UxROM, CNROM and AxROM boards without bus conflicts
+        let bus_conflicts = board != Board::Nina001 && rom.header().submapper != 1;
+        let chr = ChrMemory::new(&mut rom);
+        // of these boards only NINA-001 carries work RAM
+        let prg_ram = if board == Board::Nina001 {
+            PrgRam::new(&rom)
+        } else {
+            PrgRam::default()
+        };
+        Discrete {
+            board,
+            prg_rom: rom.prg_rom,
+            chr,
+            prg_ram,
+            mirroring,
+            bus_conflicts,
+            prg_bank: 0,
+            chr_banks: [0, 1],
+        }
+    }
+
+    /// selects an 8K CHR bank as a pair of 4K banks
+    fn select_chr_8k(&mut self, bank: u8) {
+        self.chr_banks = [bank as usize * 2, bank as usize * 2 + 1];
+    }
+
+    /// latches a value written to $8000-$FFFF
+    fn write_latch(&mut self, data: u8) {
+        match self.board {
+            Board::Uxrom => self.prg_bank = data as usize,
+            Board::Cnrom => self.select_chr_8k(data),
+            Board::Axrom => {
+                self.prg_bank = (data & 0x07) as usize;
+                self.mirroring = if data & 0x10 == 0 {
+                    Mirroring::SingleScreenLower
+                } else {
+                    Mirroring::SingleScreenUpper
+                };
+            }
+            Board::ColorDreams => {
+                self.prg_bank = (data & 0x03) as usize;
+                self.select_chr_8k(data >> 4);
+            }
+            Board::Bnrom => self.prg_bank = data as usize,
+            Board::Nina001 => {}
+            Board::Gxrom => {
+                self.prg_bank = ((data >> 4) & 0x03) as usize;
+                self.select_chr_8k(data & 0x03);
+            }
+        }
+    }
+
+    /// returns the offset into prg_rom for an address in $8000-$FFFF
+    fn prg_offset(&self, address: u16) -> usize {
+        let half = (address as usize - 0x8000) / PRG_BANK_SIZE;
+        let bank = match self.board {
+            Board::Uxrom if half == 0 => self.prg_bank,
+            Board::Uxrom => self.prg_rom.len() / PRG_BANK_SIZE - 1,
+            _ => self.prg_bank * 2 + half,
+        };
+        bank_offset(self.prg_rom.len(), bank, PRG_BANK_SIZE, address)
+    }
+
+    /// returns the offset into chr for an address in $0000-$1FFF
+    fn chr_offset(&self, address: u16) -> usize {
+        let bank = self.chr_banks[address as usize / CHR_BANK_SIZE];
+        bank_offset(self.chr.len(), bank, CHR_BANK_SIZE, address)
+    }
+}
+
+impl Mapper for Discrete {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x6000..=0x7fff => self.prg_ram.read(address),
+            0x8000..=0xffff => self.prg_rom[self.prg_offset(address)],
+            _ => {
+                debug!("unmapped cartridge read @ {:04X}", address);
+                0
+            }
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        if let 0x6000..=0x7fff = address {
+            self.prg_ram.write(address, data);
+        }
+        match (self.board, address) {
+            (Board::Nina001, 0x7ffd) => self.prg_bank = (data & 0x01) as usize,
+            (Board::Nina001, 0x7ffe) => self.chr_banks[0] = (data & 0x0f) as usize,
+            (Board::Nina001, 0x7fff) => self.chr_banks[1] = (data & 0x0f) as usize,
+            (_, 0x8000..=0xffff) => {
+                let data = if self.bus_conflicts {
+                    data & self.prg_rom[self.prg_offset(address)]
+                } else {
+                    data
+                };
+                debug!("{:?} latch <- {:02X}", self.board, data);
+                self.write_latch(data);
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&self, address: u16) -> u8 {
+        self.chr.read(self.chr_offset(address))
+    }
+
+    fn ppu_write(&mut self, address: u16, data: u8) {
+        self.chr.write(self.chr_offset(address), data);
+    }
+
+    fn prg_ram(&mut self) -> &mut [u8] {
+        self.prg_ram.data_mut()
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup_board(board: Board) -> Discrete {
+        // 8 PRG banks and 8 CHR banks, each tagged with their bank number. The last byte of
+        // every PRG bank is $FF so latch writes there avoid bus conflicts.
+        let mut rom = Rom::new_from_vec(
+            (0..8)
+                .flat_map(|bank| {
+                    let mut data = vec![bank as u8; PRG_BANK_SIZE];
+                    data[PRG_BANK_SIZE - 1] = 0xff;
+                    data
+                })
+                .collect(),
+        );
+        rom.chr_rom = (0..8)
+            .flat_map(|bank| vec![bank as u8; CHR_BANK_SIZE])
+            .collect();
+        Discrete::new(rom, board)
+    }
+
+    #[test]
+    fn test_uxrom() {
+        let mut uxrom = setup_board(Board::Uxrom);
+        uxrom.cpu_write(0xffff, 3);
+        assert_eq!(uxrom.cpu_read(0x8000), 3);
+        assert_eq!(uxrom.cpu_read(0xc000), 7);
+    }
+
+    #[test]
+    fn test_bus_conflict() {
+        let mut uxrom = setup_board(Board::Uxrom);
+        // bank 7 holds the value 7 at $C000, so 0xfe & 0x07 latches bank 6
+        uxrom.cpu_write(0xc000, 0xfe);
+        assert_eq!(uxrom.cpu_read(0x8000), 6);
+    }
+
+    #[test]
+    fn test_axrom_mirroring() {
+        let mut axrom = setup_board(Board::Axrom);
+        axrom.cpu_write(0xbfff, 0x12);
+        assert_eq!(axrom.cpu_read(0x8000), 4);
+        assert_eq!(axrom.cpu_read(0xc000), 5);
+        assert_eq!(axrom.mirroring(), Mirroring::SingleScreenUpper);
+    }
+
+    #[test]
+    fn test_gxrom() {
+        let mut gxrom = setup_board(Board::Gxrom);
+        gxrom.cpu_write(0xffff, 0x21);
+        assert_eq!(gxrom.cpu_read(0x8000), 4);
+        assert_eq!(gxrom.ppu_read(0x0000), 2);
+        assert_eq!(gxrom.ppu_read(0x1000), 3);
+    }
+
+    #[test]
+    fn test_nina001() {
+        let mut nina = setup_board(Board::Nina001);
+        nina.cpu_write(0x7ffd, 1);
+        nina.cpu_write(0x7ffe, 5);
+        nina.cpu_write(0x7fff, 2);
+        assert_eq!(nina.cpu_read(0x8000), 2);
+        assert_eq!(nina.ppu_read(0x0000), 5);
+        assert_eq!(nina.ppu_read(0x1000), 2);
+    }
+}
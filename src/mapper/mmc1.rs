@@ -0,0 +1,260 @@
+use crate::mapper::{bank_offset, ChrMemory, Mapper, PrgRam};
+use crate::rom::{Mirroring, Rom};
+use log::debug;
+
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x1000;
+
+/// MMC1 (mapper 1)
+///
+/// Registers are loaded one bit at a time through a 5-bit serial shift register: writing with
+/// bit 7 set resets it, otherwise bit 0 is shifted in and the fifth write copies the value into
+/// the register selected by address bits 13-14. A write on the cycle after another is ignored,
+/// so read-modify-write instructions only shift in the unmodified value they write first.
+///
+/// - https://www.nesdev.org/wiki/MMC1
+pub struct Mmc1 {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    prg_ram: PrgRam,
+    shift: u8,
+    shift_count: u8,
+    control: u8,
+    chr_bank0: u8,
+    chr_bank1: u8,
+    prg_bank: u8,
+    /// CPU cycles seen, and the count at the last write to the shift register
+    cycle: u64,
+    last_write: Option<u64>,
+}
+
+impl Mmc1 {
+    pub fn new(mut rom: Rom) -> Self {
+        let chr = ChrMemory::new(&mut rom);
+        let prg_ram = PrgRam::new(&rom);
+        Mmc1 {
+            prg_rom: rom.prg_rom,
+            chr,
+            prg_ram,
+            shift: 0,
+            shift_count: 0,
+            // power on in PRG mode 3 so the reset vector is found in the last bank
+            control: 0x0c,
+            chr_bank0: 0,
+            chr_bank1: 0,
+            prg_bank: 0,
+            cycle: 0,
+            last_write: None,
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.prg_bank & 0x10 == 0
+    }
+
+    /// shifts one bit into the load register and commits it on the fifth write
+    fn write_shift(&mut self, address: u16, data: u8) {
+        // the CPU is ticked after each instruction, so both writes of a read-modify-write
+        // land on the same count here
+        let consecutive = matches!(self.last_write, Some(last) if self.cycle - last <= 1);
+        self.last_write = Some(self.cycle);
+        if consecutive {
+            debug!(
+                "mmc1 ignoring consecutive write {:02X} @ {:04X}",
+                data, address
+            );
+            return;
+        }
+        if data & 0x80 != 0 {
+            self.shift = 0;
+            self.shift_count = 0;
+            self.control |= 0x0c;
+            return;
+        }
+
+        self.shift |= (data & 1) << self.shift_count;
+        self.shift_count += 1;
+        if self.shift_count < 5 {
+            return;
+        }
+
+        let value = self.shift;
+        debug!("mmc1 register {:04X} <- {:02X}", address & 0xe000, value);
+        match address {
+            0x8000..=0x9fff => self.control = value,
+            0xa000..=0xbfff => self.chr_bank0 = value,
+            0xc000..=0xdfff => self.chr_bank1 = value,
+            _ => self.prg_bank = value,
+        }
+        self.shift = 0;
+        self.shift_count = 0;
+    }
+
+    /// returns the offset into prg_rom for an address in $8000-$FFFF
+    fn prg_offset(&self, address: u16) -> usize {
+        let bank = (self.prg_bank & 0x0f) as usize;
+        let last = self.prg_rom.len() / PRG_BANK_SIZE - 1;
+        let bank = match (self.control >> 2) & 0b11 {
+            // 32K mode ignores the low bit of the bank number
+            0 | 1 => (bank & !1) + (address as usize - 0x8000) / PRG_BANK_SIZE,
+            2 if address < 0xc000 => 0,
+            2 => bank,
+            _ if address < 0xc000 => bank,
+            _ => last,
+        };
+        bank_offset(self.prg_rom.len(), bank, PRG_BANK_SIZE, address)
+    }
+
+    /// returns the offset into chr for an address in $0000-$1FFF
+    fn chr_offset(&self, address: u16) -> usize {
+        let bank = if self.control & 0x10 == 0 {
+            // 8K mode ignores the low bit of the bank number
+            (self.chr_bank0 & !1) as usize + address as usize / CHR_BANK_SIZE
+        } else if address < 0x1000 {
+            self.chr_bank0 as usize
+        } else {
+            self.chr_bank1 as usize
+        };
+        bank_offset(self.chr.len(), bank, CHR_BANK_SIZE, address)
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x6000..=0x7fff if self.prg_ram_enabled() => self.prg_ram.read(address),
+            0x8000..=0xffff => self.prg_rom[self.prg_offset(address)],
+            _ => {
+                debug!("unmapped cartridge read @ {:04X}", address);
+                0
+            }
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        match address {
+            0x6000..=0x7fff if self.prg_ram_enabled() => self.prg_ram.write(address, data),
+            0x8000..=0xffff => self.write_shift(address, data),
+            _ => debug!("ignoring cartridge write {:02X} @ {:04X}", data, address),
+        }
+    }
+
+    fn ppu_read(&self, address: u16) -> u8 {
+        self.chr.read(self.chr_offset(address))
+    }
+
+    fn ppu_write(&mut self, address: u16, data: u8) {
+        self.chr.write(self.chr_offset(address), data);
+    }
+
+    fn prg_ram(&mut self) -> &mut [u8] {
+        self.prg_ram.data_mut()
+    }
+
+    fn cpu_tick(&mut self) {
+        self.cycle += 1;
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0b11 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup_mmc1() -> Mmc1 {
+        // 8 PRG banks and 4 CHR banks, each tagged with their bank number
+        let mut rom = Rom::new_from_vec(
+            (0..8)
+                .flat_map(|bank| vec![bank as u8; PRG_BANK_SIZE])
+                .collect(),
+        );
+        rom.chr_rom = (0..4)
+            .flat_map(|bank| vec![bank as u8; CHR_BANK_SIZE])
+            .collect();
+        Mmc1::new(rom)
+    }
+
+    fn write_register(mmc1: &mut Mmc1, address: u16, value: u8) {
+        for i in 0..5 {
+            mmc1.cpu_write(address, (value >> i) & 1);
+            mmc1.cpu_tick();
+            mmc1.cpu_tick();
+        }
+    }
+
+    #[test]
+    fn test_power_on_fixes_last_bank() {
+        let mut mmc1 = setup_mmc1();
+        assert_eq!(mmc1.cpu_read(0x8000), 0);
+        assert_eq!(mmc1.cpu_read(0xc000), 7);
+    }
+
+    #[test]
+    fn test_shift_register_reset() {
+        let mut mmc1 = setup_mmc1();
+        for data in [1, 1, 0x80] {
+            mmc1.cpu_write(0xe000, data);
+            mmc1.cpu_tick();
+            mmc1.cpu_tick();
+        }
+        write_register(&mut mmc1, 0xe000, 3);
+        assert_eq!(mmc1.cpu_read(0x8000), 3);
+    }
+
+    #[test]
+    fn test_prg_modes() {
+        let mut mmc1 = setup_mmc1();
+        write_register(&mut mmc1, 0xe000, 5);
+        write_register(&mut mmc1, 0x8000, 0b01000);
+        assert_eq!(mmc1.cpu_read(0x8000), 0);
+        assert_eq!(mmc1.cpu_read(0xc000), 5);
+        write_register(&mut mmc1, 0x8000, 0b00000);
+        assert_eq!(mmc1.cpu_read(0x8000), 4);
+        assert_eq!(mmc1.cpu_read(0xc000), 5);
+    }
+
+    #[test]
+    fn test_chr_modes() {
+        let mut mmc1 = setup_mmc1();
+        write_register(&mut mmc1, 0xa000, 3);
+        write_register(&mut mmc1, 0xc000, 1);
+        assert_eq!(mmc1.ppu_read(0x0000), 2);
+        assert_eq!(mmc1.ppu_read(0x1000), 3);
+        write_register(&mut mmc1, 0x8000, 0b10000);
+        assert_eq!(mmc1.ppu_read(0x0000), 3);
+        assert_eq!(mmc1.ppu_read(0x1000), 1);
+    }
+
+    #[test]
+    fn test_mirroring() {
+        let mut mmc1 = setup_mmc1();
+        write_register(&mut mmc1, 0x8000, 0b00001);
+        assert_eq!(mmc1.mirroring(), Mirroring::SingleScreenUpper);
+        write_register(&mut mmc1, 0x8000, 0b00010);
+        assert_eq!(mmc1.mirroring(), Mirroring::Vertical);
+    }
+
+    #[test]
+    fn test_consecutive_writes_ignored() {
+        let mut mmc1 = setup_mmc1();
+        write_register(&mut mmc1, 0xe000, 3);
+        // INC $FFFF on the $FF there writes $FF then $00 on the next cycle, the reset
+        // goes through and the $00 is ignored
+        mmc1.cpu_write(0xffff, 0xff);
+        mmc1.cpu_tick();
+        mmc1.cpu_write(0xffff, 0x00);
+        mmc1.cpu_tick();
+        mmc1.cpu_tick();
+        assert_eq!(mmc1.shift_count, 0);
+        write_register(&mut mmc1, 0xe000, 5);
+        assert_eq!(mmc1.cpu_read(0x8000), 5);
+    }
+}
@@ -0,0 +1,259 @@
+use crate::mapper::{bank_offset, ChrMemory, Mapper, PrgRam};
+use crate::rom::{Mirroring, Rom};
+use log::debug;
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+/// MMC3 (mapper 4)
+///
+/// Eight bank registers are written indirectly through $8000 (select) and $8001 (data). PRG is
+/// switched in 8K banks and CHR in 2K and 1K banks, with mode bits that swap which halves of
+/// each address space are fixed. A scanline counter clocked by rising edges of PPU address line
+/// A12 raises an IRQ when it reaches zero.
+///
+/// - https://www.nesdev.org/wiki/MMC3
+pub struct Mmc3 {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    prg_ram: PrgRam,
+    bank_select: u8,
+    banks: [u8; 8],
+    mirroring: Mirroring,
+    prg_ram_enabled: bool,
+    prg_ram_write_protect: bool,
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+    a12: bool,
+}
+
+impl Mmc3 {
+    pub fn new(mut rom: Rom) -> Self {
+        let mirroring = rom.mirroring();
+        let chr = ChrMemory::new(&mut rom);
+        let prg_ram = PrgRam::new(&rom);
+        Mmc3 {
+            prg_rom: rom.prg_rom,
+            chr,
+            prg_ram,
+            bank_select: 0,
+            banks: [0; 8],
+            mirroring,
+            prg_ram_enabled: true,
+            prg_ram_write_protect: false,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            a12: false,
+        }
+    }
+
+    /// returns the offset into prg_rom for an address in $8000-$FFFF
+    fn prg_offset(&self, address: u16) -> usize {
+        let second_last = self.prg_rom.len() / PRG_BANK_SIZE - 2;
+        let swapped = self.bank_select & 0x40 != 0;
+        let bank = match (address, swapped) {
+            (0x8000..=0x9fff, false) | (0xc000..=0xdfff, true) => self.banks[6] as usize,
+            (0x8000..=0x9fff, true) | (0xc000..=0xdfff, false) => second_last,
+            (0xa000..=0xbfff, _) => self.banks[7] as usize,
+            _ => second_last + 1,
+        };
+        bank_offset(self.prg_rom.len(), bank, PRG_BANK_SIZE, address)
+    }
+
+    /// returns the offset into chr for an address in $0000-$1FFF
+    fn chr_offset(&self, address: u16) -> usize {
+        // CHR A12 inversion swaps the 2K and 1K halves of the pattern tables
+        let address = if self.bank_select & 0x80 != 0 {
+            address ^ 0x1000
+        } else {
+            address
+        };
+        let bank = match address {
+            0x0000..=0x07ff => (self.banks[0] & !1) as usize + (address as usize >> 10 & 1),
+            0x0800..=0x0fff => (self.banks[1] & !1) as usize + (address as usize >> 10 & 1),
+            _ => self.banks[2 + (address as usize - 0x1000) / CHR_BANK_SIZE] as usize,
+        };
+        bank_offset(self.chr.len(), bank, CHR_BANK_SIZE, address)
+    }
+
+    /// clocks the scanline counter, raising an IRQ when it reaches zero
+    fn clock_irq_counter(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+        if self.irq_counter == 0 && self.irq_enabled {
+            debug!("mmc3 irq");
+            self.irq_pending = true;
+        }
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x6000..=0x7fff if self.prg_ram_enabled => self.prg_ram.read(address),
+            0x8000..=0xffff => self.prg_rom[self.prg_offset(address)],
+            _ => {
+                debug!("unmapped cartridge read @ {:04X}", address);
+                0
+            }
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        let even = address & 1 == 0;
+        match address {
+            0x6000..=0x7fff if self.prg_ram_enabled && !self.prg_ram_write_protect => {
+                self.prg_ram.write(address, data)
+            }
+            0x8000..=0x9fff if even => self.bank_select = data,
+            0x8000..=0x9fff => self.banks[(self.bank_select & 0b111) as usize] = data,
+            // four screen boards wire the nametables themselves and ignore the mirroring bit
+            0xa000..=0xbfff if even && self.mirroring == Mirroring::FourScreen => {}
+            0xa000..=0xbfff if even => {
+                self.mirroring = if data & 1 == 0 {
+                    Mirroring::Vertical
+                } else {
+                    Mirroring::Horizontal
+                }
+            }
+            0xa000..=0xbfff => {
+                self.prg_ram_enabled = data & 0x80 != 0;
+                self.prg_ram_write_protect = data & 0x40 != 0;
+            }
+            0xc000..=0xdfff if even => self.irq_latch = data,
+            0xc000..=0xdfff => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            0xe000..=0xffff if even => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            0xe000..=0xffff => self.irq_enabled = true,
+            _ => debug!("ignoring cartridge write {:02X} @ {:04X}", data, address),
+        }
+    }
+
+    fn ppu_read(&self, address: u16) -> u8 {
+        self.chr.read(self.chr_offset(address))
+    }
+
+    fn ppu_write(&mut self, address: u16, data: u8) {
+        self.chr.write(self.chr_offset(address), data);
+    }
+
+    fn ppu_address(&mut self, address: u16) {
+        let a12 = address & 0x1000 != 0;
+        if a12 && !self.a12 {
+            self.clock_irq_counter();
+        }
+        self.a12 = a12;
+    }
+
+    fn prg_ram(&mut self) -> &mut [u8] {
+        self.prg_ram.data_mut()
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup_mmc3() -> Mmc3 {
+        // 8 PRG banks and 16 CHR banks, each tagged with their bank number
+        let mut rom = Rom::new_from_vec(
+            (0..8)
+                .flat_map(|bank| vec![bank as u8; PRG_BANK_SIZE])
+                .collect(),
+        );
+        rom.chr_rom = (0..16)
+            .flat_map(|bank| vec![bank as u8; CHR_BANK_SIZE])
+            .collect();
+        Mmc3::new(rom)
+    }
+
+    fn scanline(mmc3: &mut Mmc3) {
+        mmc3.ppu_address(0x0000);
+        mmc3.ppu_address(0x1000);
+    }
+
+    #[test]
+    fn test_prg_banks() {
+        let mut mmc3 = setup_mmc3();
+        mmc3.cpu_write(0x8000, 6);
+        mmc3.cpu_write(0x8001, 2);
+        mmc3.cpu_write(0x8000, 7);
+        mmc3.cpu_write(0x8001, 3);
+        assert_eq!(mmc3.cpu_read(0x8000), 2);
+        assert_eq!(mmc3.cpu_read(0xa000), 3);
+        assert_eq!(mmc3.cpu_read(0xc000), 6);
+        assert_eq!(mmc3.cpu_read(0xe000), 7);
+        mmc3.cpu_write(0x8000, 0x40);
+        assert_eq!(mmc3.cpu_read(0x8000), 6);
+        assert_eq!(mmc3.cpu_read(0xc000), 2);
+    }
+
+    #[test]
+    fn test_chr_banks() {
+        let mut mmc3 = setup_mmc3();
+        for (register, bank) in [(0, 5), (1, 8), (2, 12), (5, 15)] {
+            mmc3.cpu_write(0x8000, register);
+            mmc3.cpu_write(0x8001, bank);
+        }
+        assert_eq!(mmc3.ppu_read(0x0000), 4);
+        assert_eq!(mmc3.ppu_read(0x0400), 5);
+        assert_eq!(mmc3.ppu_read(0x0800), 8);
+        assert_eq!(mmc3.ppu_read(0x1000), 12);
+        assert_eq!(mmc3.ppu_read(0x1c00), 15);
+        mmc3.cpu_write(0x8000, 0x80);
+        assert_eq!(mmc3.ppu_read(0x0000), 12);
+        assert_eq!(mmc3.ppu_read(0x1400), 5);
+    }
+
+    #[test]
+    fn test_mirroring() {
+        let mut mmc3 = setup_mmc3();
+        mmc3.cpu_write(0xa000, 0x01);
+        assert_eq!(mmc3.mirroring(), Mirroring::Horizontal);
+        mmc3.cpu_write(0xa000, 0x00);
+        assert_eq!(mmc3.mirroring(), Mirroring::Vertical);
+
+        let rom = Rom::new_from_vec(vec![0; 0x8000]).with_mirroring(Mirroring::FourScreen);
+        let mut mmc3 = Mmc3::new(rom);
+        mmc3.cpu_write(0xa000, 0x01);
+        assert_eq!(mmc3.mirroring(), Mirroring::FourScreen);
+    }
+
+    #[test]
+    fn test_scanline_irq() {
+        let mut mmc3 = setup_mmc3();
+        mmc3.cpu_write(0xc000, 2);
+        mmc3.cpu_write(0xc001, 0);
+        mmc3.cpu_write(0xe001, 0);
+        scanline(&mut mmc3);
+        scanline(&mut mmc3);
+        assert!(!mmc3.irq());
+        scanline(&mut mmc3);
+        assert!(mmc3.irq());
+        mmc3.cpu_write(0xe000, 0);
+        assert!(!mmc3.irq());
+    }
+}
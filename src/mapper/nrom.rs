@@ -0,0 +1,78 @@
+use crate::mapper::{ChrMemory, Mapper, PrgRam};
+use crate::rom::{Mirroring, Rom};
+use log::debug;
+
+/// NROM (mapper 0)
+///
+/// No bank switching at all: 16K or 32K of PRG ROM at $8000 (16K carts are mirrored into
+/// $C000) and 8K of CHR. Mirroring is hard wired on the board.
+pub struct Nrom {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    prg_ram: PrgRam,
+    mirroring: Mirroring,
+}
+
+impl Nrom {
+    pub fn new(mut rom: Rom) -> Self {
+        let mirroring = rom.mirroring();
+        let chr = ChrMemory::new(&mut rom);
+        let prg_ram = PrgRam::new(&rom);
+        Nrom {
+            prg_rom: rom.prg_rom,
+            chr,
+            prg_ram,
+            mirroring,
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x6000..=0x7fff => self.prg_ram.read(address),
+            0x8000..=0xffff => self.prg_rom[(address - 0x8000) as usize % self.prg_rom.len()],
+            _ => {
+                debug!("unmapped cartridge read @ {:04X}", address);
+                0
+            }
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        match address {
+            0x6000..=0x7fff => self.prg_ram.write(address, data),
+            _ => debug!("ignoring cartridge write {:02X} @ {:04X}", data, address),
+        }
+    }
+
+    fn ppu_read(&self, address: u16) -> u8 {
+        self.chr.read(address as usize % self.chr.len())
+    }
+
+    fn ppu_write(&mut self, address: u16, data: u8) {
+        self.chr.write(address as usize % self.chr.len(), data);
+    }
+
+    fn prg_ram(&mut self) -> &mut [u8] {
+        self.prg_ram.data_mut()
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prg_rom_mirroring() {
+        let mut prg_rom = vec![0; 0x4000];
+        prg_rom[0x0010] = 0x5a;
+        let mut nrom = Nrom::new(Rom::new_from_vec(prg_rom));
+        assert_eq!(nrom.cpu_read(0x8010), 0x5a);
+        assert_eq!(nrom.cpu_read(0xc010), 0x5a);
+    }
+}
@@ -0,0 +1,180 @@
+use crate::apu::expansion::{
+    ExpansionAudio, FdsAudio, Mmc5Audio, N163Audio, Sunsoft5bAudio, Vrc6Audio,
+};
+use crate::mapper::{Mapper, PrgRam};
+use crate::nsf::Nsf;
+use crate::rom::Mirroring;
+use log::debug;
+
+const BANK_SIZE: usize = 0x1000;
+
+/// expansion audio bits in the NSF header
+const VRC6: u8 = 0x01;
+const VRC7: u8 = 0x02;
+const FDS: u8 = 0x04;
+const MMC5: u8 = 0x08;
+const N163: u8 = 0x10;
+const SUNSOFT_5B: u8 = 0x20;
+
+/// Cartridge side of an NSF player
+///
+/// Tune data is split into 4K banks mapped at $8000-$FFFF through the registers at
+/// $5FF8-$5FFF. Tunes that don't bankswitch are placed at their load address with the banks
+/// fixed in order, which works out the same. There is always 8K of work RAM at $6000 and
+/// nothing on the PPU side.
+///
+/// The expansion audio chips named in the header see every write to the cartridge. VRC7 isn't
+/// emulated, and FDS tunes get the sound hardware but not the writable RAM at $8000-$DFFF.
+///
+/// - https://www.nesdev.org/wiki/NSF#Bankswitching
+pub struct NsfMapper {
+    data: Vec<u8>,
+    prg_ram: PrgRam,
+    banks: [u8; 8],
+    audio: Vec<Box<dyn ExpansionAudio>>,
+}
+
+impl NsfMapper {
+    pub fn new(nsf: &mut Nsf) -> Self {
+        let tune = std::mem::take(&mut nsf.data);
+        // the data is padded so that it starts at the load address within its bank
+        let padding = if nsf.is_bankswitched() {
+            (nsf.load_address & 0x0fff) as usize
+        } else {
+            nsf.load_address as usize - 0x8000
+        };
+        let len = ((padding + tune.len() + BANK_SIZE - 1) / BANK_SIZE).max(8) * BANK_SIZE;
+        let mut data = vec![0; len];
+        data[padding..padding + tune.len()].copy_from_slice(&tune);
+
+        NsfMapper {
+            data,
+            prg_ram: PrgRam::with_size(0x2000),
+            banks: nsf.initial_banks(),
+            audio: expansion_audio(nsf.expansion),
+        }
+    }
+}
+
+/// creates the sound chips for the expansion bits of an NSF header
+fn expansion_audio(expansion: u8) -> Vec<Box<dyn ExpansionAudio>> {
+    let mut audio: Vec<Box<dyn ExpansionAudio>> = Vec::new();
+    if expansion & VRC6 != 0 {
+        audio.push(Box::new(Vrc6Audio::new()));
+    }
+    if expansion & VRC7 != 0 {
+        debug!("VRC7 audio isn't supported");
+    }
+    if expansion & FDS != 0 {
+        audio.push(Box::new(FdsAudio::new()));
+    }
+    if expansion & MMC5 != 0 {
+        audio.push(Box::new(Mmc5Audio::new()));
+    }
+    if expansion & N163 != 0 {
+        audio.push(Box::new(N163Audio::new()));
+    }
+    if expansion & SUNSOFT_5B != 0 {
+        audio.push(Box::new(Sunsoft5bAudio::new()));
+    }
+    audio
+}
+
+impl Mapper for NsfMapper {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        if let Some(data) = self.audio.iter_mut().find_map(|chip| chip.read(address)) {
+            return data;
+        }
+        match address {
+            0x6000..=0x7fff => self.prg_ram.read(address),
+            0x8000..=0xffff => {
+                let bank = self.banks[(address as usize - 0x8000) / BANK_SIZE] as usize;
+                self.data[(bank * BANK_SIZE) % self.data.len() + address as usize % BANK_SIZE]
+            }
+            _ => {
+                debug!("unmapped cartridge read @ {:04X}", address);
+                0
+            }
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        for chip in &mut self.audio {
+            chip.write(address, data);
+        }
+        match address {
+            0x5ff8..=0x5fff => self.banks[address as usize - 0x5ff8] = data,
+            0x6000..=0x7fff => self.prg_ram.write(address, data),
+            _ if !self.audio.is_empty() => {}
+            _ => debug!("ignoring cartridge write {:02X} @ {:04X}", data, address),
+        }
+    }
+
+    fn ppu_read(&self, address: u16) -> u8 {
+        0
+    }
+
+    fn ppu_write(&mut self, address: u16, data: u8) {}
+
+    fn prg_ram(&mut self) -> &mut [u8] {
+        self.prg_ram.data_mut()
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::Horizontal
+    }
+
+    fn cpu_tick(&mut self) {
+        for chip in &mut self.audio {
+            chip.tick();
+        }
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.iter().map(|chip| chip.output()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_address() {
+        let mut nsf = Nsf::new_from_vec(0x8123, vec![0x5a; 0x10]);
+        let mut mapper = NsfMapper::new(&mut nsf);
+        assert_eq!(mapper.cpu_read(0x8122), 0x00);
+        assert_eq!(mapper.cpu_read(0x8123), 0x5a);
+        assert_eq!(mapper.cpu_read(0x8132), 0x5a);
+        assert_eq!(mapper.cpu_read(0x8133), 0x00);
+    }
+
+    #[test]
+    fn test_bankswitch() {
+        let mut nsf = Nsf::new_from_vec(
+            0x8000,
+            (0..4).flat_map(|bank| vec![bank; BANK_SIZE]).collect(),
+        );
+        nsf.banks = Some([0, 1, 2, 3, 0, 1, 2, 3]);
+        let mut mapper = NsfMapper::new(&mut nsf);
+        assert_eq!(mapper.cpu_read(0x9000), 1);
+        mapper.cpu_write(0x5ff9, 3);
+        assert_eq!(mapper.cpu_read(0x9000), 3);
+        assert_eq!(mapper.cpu_read(0xf000), 3);
+    }
+
+    #[test]
+    fn test_expansion_audio() {
+        let mut nsf = Nsf::new_from_vec(0x8000, vec![0; 0x10]);
+        nsf.expansion = VRC6 | N163;
+        let mut mapper = NsfMapper::new(&mut nsf);
+        mapper.cpu_write(0x9000, 0x8f);
+        mapper.cpu_write(0x9002, 0x80);
+        mapper.cpu_tick();
+        assert!(mapper.audio_output() > 0.0);
+
+        mapper.cpu_write(0xf800, 0x00);
+        mapper.cpu_write(0x4800, 0x5a);
+        assert_eq!(mapper.cpu_read(0x4800), 0x5a);
+    }
+}
@@ -0,0 +1,263 @@
+use crate::apu::expansion::{ExpansionAudio, Vrc6Audio};
+use crate::mapper::{bank_offset, ChrMemory, Mapper, PrgRam};
+use crate::rom::{Mirroring, Rom};
+use log::debug;
+
+const PRG_16K_BANK_SIZE: usize = 0x4000;
+const PRG_8K_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+/// CPU cycles per scanline scaled by 3, the prescaler counts down by 3 each cycle
+const PRESCALER_PERIOD: i16 = 341;
+
+/// Konami VRC6 (mappers 24 and 26)
+///
+/// A 16K PRG bank at $8000, an 8K bank at $C000 and the last 8K fixed at $E000, with CHR in
+/// eight 1K banks. The IRQ counter counts up towards $FF either every CPU cycle or every
+/// scanline, which is approximated from CPU cycles with a prescaler. The board also carries
+/// the VRC6 sound chip. Mapper 26 boards swap address lines A0 and A1, which is undone before
+/// decoding any register.
+///
+/// - https://www.nesdev.org/wiki/VRC6
+pub struct Vrc6 {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    prg_ram: PrgRam,
+    swapped: bool,
+    prg_banks: [u8; 2],
+    chr_banks: [u8; 8],
+    mirroring: Mirroring,
+    prg_ram_enabled: bool,
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_prescaler: i16,
+    irq_enabled: bool,
+    irq_enable_after_ack: bool,
+    irq_cycle_mode: bool,
+    irq_pending: bool,
+    audio: Vrc6Audio,
+}
+
+impl Vrc6 {
+    pub fn new(mut rom: Rom) -> Self {
+        let swapped = rom.mapper() == 26;
+        let mirroring = rom.mirroring();
+        let chr = ChrMemory::new(&mut rom);
+        let prg_ram = PrgRam::new(&rom);
+        Vrc6 {
+            prg_rom: rom.prg_rom,
+            chr,
+            prg_ram,
+            swapped,
+            prg_banks: [0; 2],
+            chr_banks: [0; 8],
+            mirroring,
+            prg_ram_enabled: false,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_prescaler: PRESCALER_PERIOD,
+            irq_enabled: false,
+            irq_enable_after_ack: false,
+            irq_cycle_mode: false,
+            irq_pending: false,
+            audio: Vrc6Audio::new(),
+        }
+    }
+
+    /// returns the offset into prg_rom for an address in $8000-$FFFF
+    fn prg_offset(&self, address: u16) -> usize {
+        let len = self.prg_rom.len();
+        match address {
+            0x8000..=0xbfff => {
+                bank_offset(len, self.prg_banks[0] as usize, PRG_16K_BANK_SIZE, address)
+            }
+            0xc000..=0xdfff => {
+                bank_offset(len, self.prg_banks[1] as usize, PRG_8K_BANK_SIZE, address)
+            }
+            _ => bank_offset(len, len / PRG_8K_BANK_SIZE - 1, PRG_8K_BANK_SIZE, address),
+        }
+    }
+
+    /// returns the offset into chr for an address in $0000-$1FFF
+    fn chr_offset(&self, address: u16) -> usize {
+        let bank = self.chr_banks[address as usize / CHR_BANK_SIZE] as usize;
+        bank_offset(self.chr.len(), bank, CHR_BANK_SIZE, address)
+    }
+
+    /// counts up towards $FF, raising an IRQ and reloading when it overflows
+    fn clock_irq_counter(&mut self) {
+        if self.irq_counter == 0xff {
+            self.irq_counter = self.irq_latch;
+            debug!("vrc6 irq");
+            self.irq_pending = true;
+        } else {
+            self.irq_counter += 1;
+        }
+    }
+}
+
+impl Mapper for Vrc6 {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x6000..=0x7fff if self.prg_ram_enabled => self.prg_ram.read(address),
+            0x8000..=0xffff => self.prg_rom[self.prg_offset(address)],
+            _ => {
+                debug!("unmapped cartridge read @ {:04X}", address);
+                0
+            }
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        let address = if self.swapped {
+            (address & !0x0003) | (address & 0x0001) << 1 | (address & 0x0002) >> 1
+        } else {
+            address
+        };
+        match address & 0xf003 {
+            0x6000..=0x7fff if self.prg_ram_enabled => self.prg_ram.write(address, data),
+            0x8000..=0x8fff => self.prg_banks[0] = data & 0x0f,
+            0x9000..=0xb002 => self.audio.write(address, data),
+            0xb003 => {
+                self.mirroring = match (data >> 2) & 0x03 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLower,
+                    _ => Mirroring::SingleScreenUpper,
+                };
+                self.prg_ram_enabled = data & 0x80 != 0;
+            }
+            0xc000..=0xcfff => self.prg_banks[1] = data & 0x1f,
+            0xd000..=0xd003 => self.chr_banks[(address & 0x03) as usize] = data,
+            0xe000..=0xe003 => self.chr_banks[4 + (address & 0x03) as usize] = data,
+            0xf000 => self.irq_latch = data,
+            0xf001 => {
+                self.irq_enable_after_ack = data & 0x01 != 0;
+                self.irq_enabled = data & 0x02 != 0;
+                self.irq_cycle_mode = data & 0x04 != 0;
+                self.irq_pending = false;
+                if self.irq_enabled {
+                    self.irq_counter = self.irq_latch;
+                    self.irq_prescaler = PRESCALER_PERIOD;
+                }
+            }
+            0xf002 => {
+                self.irq_pending = false;
+                self.irq_enabled = self.irq_enable_after_ack;
+            }
+            _ => debug!("ignoring cartridge write {:02X} @ {:04X}", data, address),
+        }
+    }
+
+    fn ppu_read(&self, address: u16) -> u8 {
+        self.chr.read(self.chr_offset(address))
+    }
+
+    fn ppu_write(&mut self, address: u16, data: u8) {
+        self.chr.write(self.chr_offset(address), data);
+    }
+
+    fn prg_ram(&mut self) -> &mut [u8] {
+        self.prg_ram.data_mut()
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn cpu_tick(&mut self) {
+        self.audio.tick();
+        if !self.irq_enabled {
+            return;
+        }
+        if self.irq_cycle_mode {
+            self.clock_irq_counter();
+            return;
+        }
+        self.irq_prescaler -= 3;
+        if self.irq_prescaler <= 0 {
+            self.irq_prescaler += PRESCALER_PERIOD;
+            self.clock_irq_counter();
+        }
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup_vrc6(swapped: bool) -> Vrc6 {
+        // 16 8K PRG banks and 16 CHR banks, each tagged with their bank number
+        let mut rom = Rom::new_from_vec(
+            (0..16)
+                .flat_map(|bank| vec![bank as u8; PRG_8K_BANK_SIZE])
+                .collect(),
+        );
+        rom.chr_rom = (0..16)
+            .flat_map(|bank| vec![bank as u8; CHR_BANK_SIZE])
+            .collect();
+        let mut vrc6 = Vrc6::new(rom);
+        vrc6.swapped = swapped;
+        vrc6
+    }
+
+    #[test]
+    fn test_banks() {
+        let mut vrc6 = setup_vrc6(false);
+        vrc6.cpu_write(0x8000, 2);
+        vrc6.cpu_write(0xc000, 7);
+        vrc6.cpu_write(0xe002, 9);
+        assert_eq!(vrc6.cpu_read(0x8000), 4);
+        assert_eq!(vrc6.cpu_read(0xa000), 5);
+        assert_eq!(vrc6.cpu_read(0xc000), 7);
+        assert_eq!(vrc6.cpu_read(0xe000), 15);
+        assert_eq!(vrc6.ppu_read(0x1800), 9);
+    }
+
+    #[test]
+    fn test_swapped_address_lines() {
+        let mut vrc6 = setup_vrc6(true);
+        // $D001 on a mapper 26 board is the third CHR register
+        vrc6.cpu_write(0xd001, 5);
+        assert_eq!(vrc6.ppu_read(0x0800), 5);
+        vrc6.cpu_write(0xb003, 0x84);
+        assert_eq!(vrc6.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn test_cycle_irq() {
+        let mut vrc6 = setup_vrc6(false);
+        vrc6.cpu_write(0xf000, 0xfd);
+        vrc6.cpu_write(0xf001, 0x07);
+        vrc6.cpu_tick();
+        vrc6.cpu_tick();
+        assert!(!vrc6.irq());
+        vrc6.cpu_tick();
+        assert!(vrc6.irq());
+        vrc6.cpu_write(0xf002, 0);
+        assert!(!vrc6.irq());
+        assert!(vrc6.irq_enabled);
+    }
+
+    #[test]
+    fn test_scanline_irq() {
+        let mut vrc6 = setup_vrc6(false);
+        vrc6.cpu_write(0xf000, 0xfe);
+        vrc6.cpu_write(0xf001, 0x02);
+        // two scanlines of 113.67 CPU cycles
+        for _ in 0..227 {
+            vrc6.cpu_tick();
+        }
+        assert!(!vrc6.irq());
+        vrc6.cpu_tick();
+        assert!(vrc6.irq());
+    }
+}
@@ -0,0 +1,118 @@
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use crate::bus::Bus;
+use crate::cpu::Cpu;
+use crate::rom::Rom;
+
+/// nestest's automatic mode, which runs every test without needing the PPU or a controller
+///
+/// - https://www.qmtpro.com/~nes/misc/nestest.txt
+pub const AUTOMATIC_START: u16 = 0xc000;
+
+/// runs nestest in automatic mode, returning the trace of its first `lines` instructions in
+/// the format of nestest.log
+pub fn trace(rom: Rom, lines: usize) -> Vec<String> {
+    let mut cpu = Cpu::new(Bus::new(rom));
+    cpu.reset();
+    cpu.set_pc(AUTOMATIC_START);
+    let mut trace = Vec::with_capacity(lines);
+    while trace.len() < lines {
+        trace.push(cpu.trace());
+        cpu.tick();
+    }
+    trace
+}
+
+/// The first line where a trace differs from the golden log
+#[derive(Debug, PartialEq, Eq)]
+pub struct Divergence {
+    /// line number, counted from 1
+    pub line: usize,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "trace diverges at line {}\nexpected: {}\nactual:   {}",
+            self.line, self.expected, self.actual
+        )
+    }
+}
+
+/// compares a trace with a log line by line, ignoring line endings. A log or trace that ends
+/// early diverges with an empty line.
+pub fn first_divergence(log: &str, trace: &[String]) -> Option<Divergence> {
+    let expected: Vec<&str> = log.lines().map(str::trim_end).collect();
+    (0..expected.len().max(trace.len())).find_map(|i| {
+        let expected = expected.get(i).copied().unwrap_or_default();
+        let actual = trace.get(i).map(String::as_str).unwrap_or_default();
+        (expected != actual).then(|| Divergence {
+            line: i + 1,
+            expected: expected.to_string(),
+            actual: actual.to_string(),
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    fn lines(trace: &str) -> Vec<String> {
+        trace.lines().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_first_divergence() {
+        let log = "C000  4C F5 C5  JMP $C5F5\r\nC5F5  A2 00     LDX #$00\r\n";
+        assert_eq!(
+            first_divergence(
+                log,
+                &lines("C000  4C F5 C5  JMP $C5F5\nC5F5  A2 00     LDX #$00")
+            ),
+            None
+        );
+        assert_eq!(
+            first_divergence(
+                log,
+                &lines("C000  4C F5 C5  JMP $C5F5\nC5F5  A2 01     LDX #$01")
+            ),
+            Some(Divergence {
+                line: 2,
+                expected: "C5F5  A2 00     LDX #$00".to_string(),
+                actual: "C5F5  A2 01     LDX #$01".to_string(),
+            })
+        );
+        assert_eq!(
+            first_divergence(log, &lines("C000  4C F5 C5  JMP $C5F5")).map(|d| d.line),
+            Some(2)
+        );
+        assert_eq!(
+            first_divergence(&log[..27], &lines(log)).map(|d| d.actual),
+            Some("C5F5  A2 00     LDX #$00".to_string())
+        );
+    }
+
+    #[test]
+    #[ignore = "nestest.nes and nestest.log aren't in the tree, copy them into tests/ to run"]
+    fn test_nestest() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests");
+        let read = |name: &str| {
+            fs::read(dir.join(name))
+                .unwrap_or_else(|e| panic!("unable to read {}: {}", dir.join(name).display(), e))
+        };
+        let rom = read("nestest.nes");
+        let log = String::from_utf8(read("nestest.log")).unwrap();
+        let rom = Rom::new_from_ines(&rom).unwrap();
+        let trace = trace(rom, log.lines().count());
+        if let Some(divergence) = first_divergence(&log, &trace) {
+            panic!("{}", divergence);
+        }
+    }
+}
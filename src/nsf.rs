@@ -0,0 +1,524 @@
+use crate::audio::CPU_CLOCK_HZ;
+use crate::bus::Bus;
+use crate::cpu::Cpu;
+use crate::mapper::NsfMapper;
+use log::debug;
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt;
+use std::rc::Rc;
+
+const NSF_HEADER_SIZE: usize = 0x80;
+const NSF_MAGIC: &[u8] = b"NESM\x1a";
+const NSFE_MAGIC: &[u8] = b"NSFE";
+/// PLAY periods assumed when a file doesn't say, in microseconds
+const NTSC_PLAY_SPEED: u16 = 16639;
+const PAL_PLAY_SPEED: u16 = 19997;
+
+/// returns the PLAY period a file asks for, or the region's default when it's missing or 0,
+/// which some rips store and would otherwise call PLAY without ever letting time pass
+fn play_speed(speed: Option<u16>, pal: bool) -> u16 {
+    match speed {
+        Some(speed) if speed != 0 => speed,
+        _ if pal => PAL_PLAY_SPEED,
+        _ => NTSC_PLAY_SPEED,
+    }
+}
+
+/// routines are called with this return address and are finished once the CPU reaches it. It
+/// sits in the unused space below the bank registers.
+const RETURN_ADDRESS: u16 = 0x5000;
+/// cycles INIT may take before giving up on it returning, about a second
+const INIT_CYCLE_LIMIT: usize = 1_789_773;
+
+/// Problems found while loading an NSF or NSFe file
+#[derive(Debug, PartialEq, Eq)]
+pub enum NsfError {
+    BadMagic,
+    Truncated { expected: usize, actual: usize },
+    MissingChunk(&'static str),
+    BadLoadAddress(u16),
+}
+
+impl fmt::Display for NsfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NsfError::BadMagic => write!(f, "missing NESM or NSFE magic, not an NSF file"),
+            NsfError::Truncated { expected, actual } => write!(
+                f,
+                "truncated nsf, expected {} bytes but found {}",
+                expected, actual
+            ),
+            NsfError::MissingChunk(id) => write!(f, "missing required {} chunk", id),
+            NsfError::BadLoadAddress(address) => {
+                write!(f, "unsupported load address {:04X}", address)
+            }
+        }
+    }
+}
+
+impl Error for NsfError {}
+
+/// returns true if the data looks like an NSF or NSFe file
+pub fn is_nsf(data: &[u8]) -> bool {
+    data.starts_with(NSF_MAGIC) || data.starts_with(NSFE_MAGIC)
+}
+
+/// An NES Sound Format tune
+///
+/// Rather than a cartridge image an NSF holds a music engine plus its data, along with the
+/// address to load it at and two routines: INIT selects a song and PLAY is called at a fixed
+/// rate to advance it. NSFe stores the same information in tagged chunks along with optional
+/// track titles.
+///
+/// - https://www.nesdev.org/wiki/NSF
+/// - https://www.nesdev.org/wiki/NSFe
+#[derive(Debug)]
+pub struct Nsf {
+    pub title: String,
+    pub artist: String,
+    pub copyright: String,
+    pub songs: u8,
+    /// 0-based index of the song to play first
+    pub starting_song: u8,
+    pub load_address: u16,
+    pub init_address: u16,
+    pub play_address: u16,
+    /// PLAY period in microseconds
+    pub play_speed: u16,
+    pub pal: bool,
+    /// initial values of the bank registers, None if the tune doesn't bankswitch
+    pub banks: Option<[u8; 8]>,
+    /// bits for expansion audio chips the tune uses
+    pub expansion: u8,
+    pub track_titles: Vec<String>,
+    pub data: Vec<u8>,
+}
+
+/// reads a string from a fixed size, null padded field
+fn read_string(data: &[u8]) -> String {
+    let end = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).into_owned()
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+impl Nsf {
+    /// parses an NSF or NSFe file
+    pub fn new(data: &[u8]) -> Result<Nsf, NsfError> {
+        let mut nsf = if data.starts_with(NSF_MAGIC) {
+            Nsf::parse_nsf(data)?
+        } else if data.starts_with(NSFE_MAGIC) {
+            Nsf::parse_nsfe(data)?
+        } else {
+            return Err(NsfError::BadMagic);
+        };
+        if nsf.banks.is_none() && nsf.load_address < 0x8000 {
+            return Err(NsfError::BadLoadAddress(nsf.load_address));
+        }
+        nsf.starting_song = nsf.starting_song.min(nsf.songs.max(1) - 1);
+        debug!(
+            "nsf {:?} songs {} load {:04X} init {:04X} play {:04X}",
+            nsf.title, nsf.songs, nsf.load_address, nsf.init_address, nsf.play_address
+        );
+        Ok(nsf)
+    }
+
+    fn parse_nsf(data: &[u8]) -> Result<Nsf, NsfError> {
+        if data.len() < NSF_HEADER_SIZE {
+            return Err(NsfError::Truncated {
+                expected: NSF_HEADER_SIZE,
+                actual: data.len(),
+            });
+        }
+        let mut banks = [0; 8];
+        banks.copy_from_slice(&data[0x70..0x78]);
+        // PAL only tunes have bit 0 set and bit 1 (dual region) clear
+        let pal = data[0x7a] & 0b11 == 0b01;
+        Ok(Nsf {
+            title: read_string(&data[0x0e..0x2e]),
+            artist: read_string(&data[0x2e..0x4e]),
+            copyright: read_string(&data[0x4e..0x6e]),
+            songs: data[0x06],
+            starting_song: data[0x07].saturating_sub(1),
+            load_address: read_u16(data, 0x08),
+            init_address: read_u16(data, 0x0a),
+            play_address: read_u16(data, 0x0c),
+            play_speed: play_speed(
+                Some(if pal {
+                    read_u16(data, 0x78)
+                } else {
+                    read_u16(data, 0x6e)
+                }),
+                pal,
+            ),
+            pal,
+            banks: if banks.iter().any(|b| *b != 0) {
+                Some(banks)
+            } else {
+                None
+            },
+            expansion: data[0x7b],
+            track_titles: vec![],
+            data: data[NSF_HEADER_SIZE..].to_vec(),
+        })
+    }
+
+    fn parse_nsfe(data: &[u8]) -> Result<Nsf, NsfError> {
+        let mut nsf = Nsf::new_from_vec(0, vec![]);
+        let mut info = false;
+        let mut rate = None;
+        let mut offset = NSFE_MAGIC.len();
+        while offset + 8 <= data.len() {
+            let len = read_u16(data, offset) as usize | (read_u16(data, offset + 2) as usize) << 16;
+            let id = &data[offset + 4..offset + 8];
+            let start = offset + 8;
+            if start + len > data.len() {
+                return Err(NsfError::Truncated {
+                    expected: start + len,
+                    actual: data.len(),
+                });
+            }
+            let chunk = &data[start..start + len];
+            offset = start + len;
+
+            match id {
+                b"INFO" if len >= 9 => {
+                    info = true;
+                    nsf.load_address = read_u16(chunk, 0);
+                    nsf.init_address = read_u16(chunk, 2);
+                    nsf.play_address = read_u16(chunk, 4);
+                    nsf.pal = chunk[6] & 0b11 == 0b01;
+                    nsf.expansion = chunk[7];
+                    nsf.songs = chunk[8];
+                    nsf.starting_song = chunk.get(9).copied().unwrap_or(0);
+                }
+                b"DATA" => nsf.data = chunk.to_vec(),
+                b"BANK" => {
+                    let mut banks = [0; 8];
+                    for (bank, value) in banks.iter_mut().zip(chunk) {
+                        *bank = *value;
+                    }
+                    nsf.banks = Some(banks);
+                }
+                b"RATE" if len >= 2 => rate = Some(read_u16(chunk, 0)),
+                b"auth" => {
+                    let mut strings = chunk.split(|b| *b == 0).map(read_string);
+                    nsf.title = strings.next().unwrap_or_default();
+                    nsf.artist = strings.next().unwrap_or_default();
+                    nsf.copyright = strings.next().unwrap_or_default();
+                }
+                b"tlbl" => {
+                    nsf.track_titles = chunk.split(|b| *b == 0).map(read_string).collect();
+                    nsf.track_titles.truncate(nsf.songs.max(1) as usize);
+                }
+                b"NEND" => break,
+                _ => debug!("skipping nsfe chunk {}", String::from_utf8_lossy(id)),
+            }
+        }
+        if !info {
+            return Err(NsfError::MissingChunk("INFO"));
+        }
+        if nsf.data.is_empty() {
+            return Err(NsfError::MissingChunk("DATA"));
+        }
+        nsf.play_speed = play_speed(rate, nsf.pal);
+        Ok(nsf)
+    }
+
+    /// creates a single song tune loaded at the given address, used for testing
+    pub fn new_from_vec(load_address: u16, data: Vec<u8>) -> Self {
+        Nsf {
+            title: String::new(),
+            artist: String::new(),
+            copyright: String::new(),
+            songs: 1,
+            starting_song: 0,
+            load_address,
+            init_address: load_address,
+            play_address: load_address,
+            play_speed: NTSC_PLAY_SPEED,
+            pal: false,
+            banks: None,
+            expansion: 0,
+            track_titles: vec![],
+            data,
+        }
+    }
+
+    pub fn is_bankswitched(&self) -> bool {
+        self.banks.is_some()
+    }
+
+    /// returns the bank registers at the start of each song
+    pub fn initial_banks(&self) -> [u8; 8] {
+        self.banks.unwrap_or([0, 1, 2, 3, 4, 5, 6, 7])
+    }
+
+    /// returns the title of a 0-based track, if the file has one
+    pub fn track_title(&self, track: u8) -> Option<&str> {
+        self.track_titles
+            .get(track as usize)
+            .map(String::as_str)
+            .filter(|title| !title.is_empty())
+    }
+}
+
+/// Plays an NSF using the CPU and APU
+///
+/// Each song starts from cleared memory with INIT called once, after which PLAY is called
+/// every play_speed microseconds. Between calls the CPU sits idle while the APU keeps running.
+pub struct Player {
+    pub cpu: Cpu,
+    pub nsf: Nsf,
+    track: u8,
+    period: f64,
+    debt: f64,
+}
+
+impl Player {
+    pub fn new(mut nsf: Nsf) -> Self {
+        let mapper = Rc::new(RefCell::new(NsfMapper::new(&mut nsf)));
+        let cpu = Cpu::new(Bus::new_with_mapper(mapper));
+        let period = nsf.play_speed as f64 * CPU_CLOCK_HZ / 1_000_000.0;
+        let mut player = Player {
+            cpu,
+            track: nsf.starting_song,
+            nsf,
+            period,
+            debt: 0.0,
+        };
+        player.start_track(player.track);
+        player
+    }
+
+    /// returns the 0-based index of the playing track
+    pub fn track(&self) -> u8 {
+        self.track
+    }
+
+    pub fn next_track(&mut self) {
+        self.start_track((self.track + 1) % self.nsf.songs.max(1));
+    }
+
+    pub fn prev_track(&mut self) {
+        let track = if self.track == 0 {
+            self.nsf.songs.max(1) - 1
+        } else {
+            self.track - 1
+        };
+        self.start_track(track);
+    }
+
+    /// resets memory and the APU then calls INIT for a 0-based track
+    pub fn start_track(&mut self, track: u8) {
+        debug!("nsf starting track {}", track);
+        self.track = track;
+        let bus = &mut self.cpu.bus;
+        bus.ram = [0; 2048];
+        for address in 0x6000..=0x7fff {
+            bus.write_u8(address, 0);
+        }
+        for address in 0x4000..=0x4013 {
+            bus.write_u8(address, 0);
+        }
+        bus.write_u8(0x4015, 0x00);
+        bus.write_u8(0x4015, 0x0f);
+        bus.write_u8(0x4017, 0x40);
+        if self.nsf.is_bankswitched() {
+            for (i, bank) in self.nsf.initial_banks().iter().enumerate() {
+                bus.write_u8(0x5ff8 + i as u16, *bank);
+            }
+        }
+        let region = if self.nsf.pal { 1 } else { 0 };
+        let init = self.nsf.init_address;
+        if self.call(init, track, region, INIT_CYCLE_LIMIT) >= INIT_CYCLE_LIMIT {
+            println!("nsf INIT routine did not return");
+        }
+        self.debt = 0.0;
+    }
+
+    /// calls PLAY then idles until the next call is due, returning the cycles run
+    pub fn run_frame(&mut self) -> usize {
+        self.debt += self.period;
+        let budget = self.debt as usize;
+        let play = self.nsf.play_address;
+        let mut cycles = self.call(play, 0, 0, budget);
+        while cycles < budget {
+            self.cpu.bus.tick(1);
+            cycles += 1;
+        }
+        self.debt -= cycles as f64;
+        cycles
+    }
+
+    /// calls a routine and steps the CPU until it returns or runs for limit cycles, returning
+    /// the cycles run
+    fn call(&mut self, address: u16, a: u8, x: u8, limit: usize) -> usize {
+        self.cpu.call(address, RETURN_ADDRESS, a, x);
+        let mut cycles = 0;
+        while self.cpu.pc() != RETURN_ADDRESS && cycles < limit {
+            let step = self.cpu.step();
+            self.cpu.bus.tick(step);
+            cycles += step as usize;
+        }
+        cycles
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nsf_header(songs: u8, start: u8, load: u16, init: u16, play: u16) -> Vec<u8> {
+        let mut data = vec![0; NSF_HEADER_SIZE];
+        data[..5].copy_from_slice(NSF_MAGIC);
+        data[5] = 1;
+        data[6] = songs;
+        data[7] = start;
+        data[8..10].copy_from_slice(&load.to_le_bytes());
+        data[0x0a..0x0c].copy_from_slice(&init.to_le_bytes());
+        data[0x0c..0x0e].copy_from_slice(&play.to_le_bytes());
+        data[0x0e..0x13].copy_from_slice(b"Title");
+        data[0x6e..0x70].copy_from_slice(&NTSC_PLAY_SPEED.to_le_bytes());
+        data
+    }
+
+    fn chunk(id: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut chunk = (data.len() as u32).to_le_bytes().to_vec();
+        chunk.extend(id);
+        chunk.extend(data);
+        chunk
+    }
+
+    #[test]
+    fn test_nsf_header() {
+        let mut data = nsf_header(5, 2, 0x8000, 0x8003, 0x8006);
+        data.extend([0xea; 16]);
+        let nsf = Nsf::new(&data).unwrap();
+        assert_eq!(nsf.title, "Title");
+        assert_eq!(nsf.songs, 5);
+        assert_eq!(nsf.starting_song, 1);
+        assert_eq!(nsf.init_address, 0x8003);
+        assert_eq!(nsf.play_speed, NTSC_PLAY_SPEED);
+        assert!(!nsf.is_bankswitched());
+        assert_eq!(nsf.data.len(), 16);
+    }
+
+    #[test]
+    fn test_nsfe() {
+        let mut data = NSFE_MAGIC.to_vec();
+        data.extend(chunk(
+            b"INFO",
+            &[0x00, 0x80, 0x03, 0x80, 0x06, 0x80, 0, 0, 2, 1],
+        ));
+        data.extend(chunk(b"DATA", &[0xea; 8]));
+        data.extend(chunk(b"auth", b"Song\0Artist\0\0Ripper\0"));
+        data.extend(chunk(b"tlbl", b"Intro\0Boss\0"));
+        data.extend(chunk(b"NEND", &[]));
+        let nsf = Nsf::new(&data).unwrap();
+        assert_eq!(nsf.title, "Song");
+        assert_eq!(nsf.artist, "Artist");
+        assert_eq!(nsf.songs, 2);
+        assert_eq!(nsf.starting_song, 1);
+        assert_eq!(nsf.track_title(1), Some("Boss"));
+        assert_eq!(nsf.track_title(2), None);
+        assert_eq!(nsf.data.len(), 8);
+        assert_eq!(nsf.play_speed, NTSC_PLAY_SPEED);
+    }
+
+    #[test]
+    fn test_zero_play_speed() {
+        let mut data = nsf_header(1, 1, 0x8000, 0x8000, 0x8000);
+        data[0x6e..0x70].copy_from_slice(&[0, 0]);
+        data.extend([0xea; 16]);
+        let nsf = Nsf::new(&data).unwrap();
+        assert_eq!(nsf.play_speed, NTSC_PLAY_SPEED);
+        assert!(Player::new(nsf).run_frame() > 0);
+
+        let mut data = NSFE_MAGIC.to_vec();
+        data.extend(chunk(
+            b"INFO",
+            &[0x00, 0x80, 0x00, 0x80, 0x00, 0x80, 1, 0, 1],
+        ));
+        data.extend(chunk(b"DATA", &[0xea; 8]));
+        data.extend(chunk(b"RATE", &[0, 0]));
+        let nsf = Nsf::new(&data).unwrap();
+        assert_eq!(nsf.play_speed, PAL_PLAY_SPEED);
+    }
+
+    #[test]
+    fn test_starting_song_clamped() {
+        let mut data = nsf_header(3, 10, 0x8000, 0x8000, 0x8000);
+        data.extend([0x60; 16]);
+        assert_eq!(Nsf::new(&data).unwrap().starting_song, 2);
+
+        let mut data = NSFE_MAGIC.to_vec();
+        data.extend(chunk(
+            b"INFO",
+            &[0x00, 0x80, 0x00, 0x80, 0x00, 0x80, 0, 0, 2, 5],
+        ));
+        data.extend(chunk(b"DATA", &[0x60; 8]));
+        assert_eq!(Nsf::new(&data).unwrap().starting_song, 1);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(Nsf::new(b"NES\x1a").unwrap_err(), NsfError::BadMagic);
+        assert_eq!(
+            Nsf::new(NSF_MAGIC).unwrap_err(),
+            NsfError::Truncated {
+                expected: NSF_HEADER_SIZE,
+                actual: 5
+            }
+        );
+        assert_eq!(
+            Nsf::new(NSFE_MAGIC).unwrap_err(),
+            NsfError::MissingChunk("INFO")
+        );
+        let data = nsf_header(1, 1, 0x6000, 0x6000, 0x6000);
+        assert_eq!(
+            Nsf::new(&data).unwrap_err(),
+            NsfError::BadLoadAddress(0x6000)
+        );
+    }
+
+    #[test]
+    fn test_player() {
+        let mut data = nsf_header(3, 1, 0x8000, 0x8000, 0x8003);
+        data.extend([
+            0x85, 0x00, // INIT: STA $00
+            0x60, //       RTS
+            0xe6, 0x01, // PLAY: INC $01
+            0x60, //       RTS
+        ]);
+        let mut player = Player::new(Nsf::new(&data).unwrap());
+        assert_eq!(player.cpu.bus.ram[0], 0);
+        player.next_track();
+        assert_eq!(player.track(), 1);
+        assert_eq!(player.cpu.bus.ram[0], 1);
+        let cycles = player.run_frame() + player.run_frame();
+        assert_eq!(player.cpu.bus.ram[1], 2);
+        assert!((cycles as f64 - 2.0 * player.period).abs() < 1.0);
+        player.prev_track();
+        player.prev_track();
+        assert_eq!(player.track(), 2);
+        assert_eq!(player.cpu.bus.ram[1], 0);
+    }
+
+    #[test]
+    fn test_track_wraps_with_many_songs() {
+        let mut data = nsf_header(200, 151, 0x8000, 0x8000, 0x8000);
+        data.extend([0x60; 16]);
+        let mut player = Player::new(Nsf::new(&data).unwrap());
+        player.prev_track();
+        assert_eq!(player.track(), 149);
+        player.start_track(0);
+        player.prev_track();
+        assert_eq!(player.track(), 199);
+        player.next_track();
+        assert_eq!(player.track(), 0);
+    }
+}
@@ -1,76 +1,354 @@
+use crate::frame::{self, HEIGHT, WIDTH};
+use crate::mapper::Mapper;
+use crate::rom::Mirroring;
 use bitflags::bitflags;
 use log::debug;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+mod loopy;
+mod sprite;
+
+use loopy::Loopy;
+use sprite::LineSprite;
+
+const DOTS_PER_SCANLINE: usize = 341;
+const VBLANK_SCANLINE: u16 = 241;
+const PRE_RENDER_SCANLINE: u16 = 261;
+
+/// Background tile data for the next tile and the shift registers feeding the pixel output
+///
+/// Each tile is fetched over 8 dots and loaded into the low byte of the shift registers,
+/// which shift once per dot so the high byte always holds the tile being drawn.
+#[derive(Default)]
+struct Background {
+    tile: u8,
+    attribute: u8,
+    pattern_lo: u8,
+    pattern_hi: u8,
+    shift_pattern_lo: u16,
+    shift_pattern_hi: u16,
+    shift_attribute_lo: u16,
+    shift_attribute_hi: u16,
+}
+
+impl Background {
+    fn load(&mut self) {
+        self.shift_pattern_lo = (self.shift_pattern_lo & 0xff00) | self.pattern_lo as u16;
+        self.shift_pattern_hi = (self.shift_pattern_hi & 0xff00) | self.pattern_hi as u16;
+        let attribute_lo = if self.attribute & 0b01 != 0 {
+            0xff
+        } else {
+            0x00
+        };
+        let attribute_hi = if self.attribute & 0b10 != 0 {
+            0xff
+        } else {
+            0x00
+        };
+        self.shift_attribute_lo = (self.shift_attribute_lo & 0xff00) | attribute_lo;
+        self.shift_attribute_hi = (self.shift_attribute_hi & 0xff00) | attribute_hi;
+    }
+
+    fn shift(&mut self) {
+        self.shift_pattern_lo <<= 1;
+        self.shift_pattern_hi <<= 1;
+        self.shift_attribute_lo <<= 1;
+        self.shift_attribute_hi <<= 1;
+    }
+
+    /// returns the pixel value (0-3) and palette (0-3) at the fine x scroll
+    fn pixel(&self, fine_x: u8) -> (u8, u8) {
+        let mux = 0x8000 >> fine_x;
+        let bit = |shift: u16| (shift & mux != 0) as u8;
+        (
+            bit(self.shift_pattern_hi) << 1 | bit(self.shift_pattern_lo),
+            bit(self.shift_attribute_hi) << 1 | bit(self.shift_attribute_lo),
+        )
+    }
+}
 
 pub struct Ppu {
-    pub chr_rom: Vec<u8>,
+    mapper: Rc<RefCell<dyn Mapper>>,
     pub palette: [u8; 32],
-    pub vram: [u8; 2048],
+    /// the console's 2K of nametable RAM followed by the 2K four screen cartridges add
+    pub vram: [u8; 4096],
     pub oam: [u8; 256],
     pub oam_addr: u8,
-    mirroring: bool, // false horizontal, true vertical
     pub ctrl_register: PpuCtrlRegister,
     mask_register: PpuMaskRegister,
-    addr_register: PpuAddrRegister,
     status_register: PpuStatusRegister,
-    pub scroll_register: PpuScrollRegister,
+    loopy: Loopy,
     buffer: u8,
-    cycle: usize,
+    dot: usize,
     scanline: u16,
+    odd_frame: bool,
+    background: Background,
+    sprites: Vec<LineSprite>,
+    next_sprites: Vec<LineSprite>,
+    sprite_limit: bool,
+    /// the picture as NES colour indices, WIDTH x HEIGHT, with the PPUMASK emphasis bits
+    /// for each pixel in bits 6-8
+    pub frame: Vec<u16>,
     pub has_nmi: Option<bool>,
 }
 
 impl Ppu {
     /// implements the Picture Processing Unit
     ///
-    /// This contains the registers, vram, chr_rom, pallets and oam data for graphics.
-    pub fn new(chr_rom: Vec<u8>, mirroring: bool) -> Self {
+    /// This contains the registers, vram, pallets and oam data for graphics. Pattern tables and
+    /// the nametable mirroring mode are provided by the cartridge mapper. The picture is drawn
+    /// one dot at a time into `frame` following the hardware's fetch pattern, so writes made
+    /// mid-frame, such as scroll splits for status bars, show up where they happen.
+    ///
+    /// - https://www.nesdev.org/wiki/PPU_rendering
+    pub fn new(mapper: Rc<RefCell<dyn Mapper>>) -> Self {
         Self {
-            chr_rom,
+            mapper,
             palette: [0; 32],
-            vram: [0; 2048],
+            vram: [0; 4096],
             oam: [0; 256],
             oam_addr: 0,
             ctrl_register: PpuCtrlRegister::new(),
             mask_register: PpuMaskRegister::new(),
-            addr_register: PpuAddrRegister::new(),
             status_register: PpuStatusRegister::new(),
-            scroll_register: PpuScrollRegister::new(),
-            mirroring,
+            loopy: Loopy::default(),
             buffer: 0,
-            cycle: 0,
+            dot: 0,
             scanline: 0,
+            odd_frame: false,
+            background: Background::default(),
+            sprites: Vec::new(),
+            next_sprites: Vec::new(),
+            sprite_limit: true,
+            frame: frame::new(),
             has_nmi: None,
         }
     }
 
+    /// advances the PPU by a number of dots, returning true when a frame was completed
     pub fn tick(&mut self, cycle: u8) -> bool {
-        self.cycle += cycle as usize;
-        debug!("ppu cycle {} scanline {}", self.cycle, self.scanline);
-        if self.cycle >= 341 {
-            self.set_sprite0_hit();
-            self.cycle -= 341;
-
-            self.scanline += 1;
-            if self.scanline == 241 {
-                self.status_register.set_vblank(true);
-                self.status_register.clear_sprite0();
-                if self.ctrl_register.nmi_starts_on_vblank_ok() {
-                    self.has_nmi = Some(true);
+        let mut frame_done = false;
+        for _ in 0..cycle {
+            frame_done |= self.step();
+        }
+        frame_done
+    }
+
+    /// returns the current scanline (0-261) and dot (0-340)
+    pub fn position(&self) -> (u16, usize) {
+        (self.scanline, self.dot)
+    }
+
+    /// runs a single dot
+    fn step(&mut self) -> bool {
+        let rendering = self.mask_register.rendering_enabled();
+        let visible = self.scanline < HEIGHT as u16;
+        let pre_render = self.scanline == PRE_RENDER_SCANLINE;
+
+        if pre_render && self.dot == 1 {
+            self.has_nmi = None;
+            self.status_register.clear_sprite0();
+            self.status_register
+                .set(PpuStatusRegister::SPRITE_OVERFLOW, false);
+            self.status_register.set_vblank(false);
+        }
+        if self.scanline == VBLANK_SCANLINE && self.dot == 1 {
+            self.status_register.set_vblank(true);
+            if self.ctrl_register.nmi_starts_on_vblank_ok() {
+                self.has_nmi = Some(true);
+            }
+        }
+
+        if rendering && (visible || pre_render) {
+            self.render_dot(pre_render);
+        }
+        if visible && (1..=WIDTH).contains(&self.dot) {
+            self.output_pixel();
+        }
+
+        // odd frames skip the last dot of the pre-render line while rendering
+        let last_dot = if pre_render && self.odd_frame && rendering {
+            DOTS_PER_SCANLINE - 2
+        } else {
+            DOTS_PER_SCANLINE - 1
+        };
+        if self.dot < last_dot {
+            self.dot += 1;
+            return false;
+        }
+        self.dot = 0;
+        self.sprites = std::mem::take(&mut self.next_sprites);
+        if pre_render {
+            self.scanline = 0;
+            self.odd_frame = !self.odd_frame;
+            return true;
+        }
+        self.scanline += 1;
+        false
+    }
+
+    /// performs the background fetches, scroll updates and sprite fetches for a dot
+    fn render_dot(&mut self, pre_render: bool) {
+        let dot = self.dot;
+        if (2..=257).contains(&dot) || (321..=337).contains(&dot) {
+            self.background.shift();
+            match (dot - 1) % 8 {
+                0 => {
+                    self.background.load();
+                    self.background.tile = self.read_vram(self.loopy.tile_address());
+                }
+                2 => {
+                    let attribute = self.read_vram(self.loopy.attribute_address());
+                    self.background.attribute = (attribute >> self.loopy.attribute_shift()) & 0x03;
+                }
+                4 => {
+                    let address = self.background_pattern_address();
+                    self.background.pattern_lo = self.read_pattern(address);
+                }
+                6 => {
+                    let address = self.background_pattern_address() + 8;
+                    self.background.pattern_hi = self.read_pattern(address);
                 }
+                7 => self.loopy.increment_x(),
+                _ => {}
             }
-            if self.scanline >= 262 {
-                self.scanline = 0;
-                self.has_nmi = None;
-                self.status_register.clear_sprite0();
-                self.status_register.set_vblank(false);
-                return true;
+        }
+        match dot {
+            256 => self.loopy.increment_y(),
+            257 => {
+                self.background.load();
+                self.loopy.copy_horizontal();
+                self.fetch_sprites(pre_render);
             }
+            280..=304 if pre_render => self.loopy.copy_vertical(),
+            _ => {}
+        }
+    }
+
+    fn background_pattern_address(&self) -> u16 {
+        self.ctrl_register.bg_bank_addr() + self.background.tile as u16 * 16 + self.loopy.fine_y()
+    }
+
+    /// finds the sprites on the next scanline and fetches their patterns
+    ///
+    /// The pre-render line doesn't evaluate sprites so nothing is drawn on scanline 0.
+    fn fetch_sprites(&mut self, pre_render: bool) {
+        self.next_sprites.clear();
+        if pre_render {
+            self.fetch_unused_sprite_slots(0);
+            return;
+        }
+        let height = self.ctrl_register.sprite_height();
+        let evaluation = sprite::evaluate(&self.oam, self.scanline, height, self.sprite_limit);
+        if evaluation.overflow {
+            self.status_register
+                .insert(PpuStatusRegister::SPRITE_OVERFLOW);
+        }
+        for &n in &evaluation.sprites {
+            let entry = &self.oam[n * 4..n * 4 + 4];
+            let (tile, attribute, x) = (entry[1], entry[2], entry[3]);
+            let row = self.scanline.wrapping_sub(entry[0] as u16);
+            let table = self.ctrl_register.sprite_bank_addr();
+            let address = sprite::pattern_address(tile, attribute, row, height, table);
+            let pattern_lo = self.read_pattern(address);
+            let pattern_hi = self.read_pattern(address + 8);
+            self.next_sprites.push(LineSprite {
+                x,
+                attribute,
+                pattern_lo,
+                pattern_hi,
+                zero: n == 0,
+            });
+        }
+        self.fetch_unused_sprite_slots(evaluation.sprites.len());
+    }
+
+    /// the sprite slots left empty by evaluation still fetch tile $FF, which in 8x16 mode
+    /// lives in the $1000 pattern table
+    fn fetch_unused_sprite_slots(&mut self, used: usize) {
+        let height = self.ctrl_register.sprite_height();
+        let table = self.ctrl_register.sprite_bank_addr();
+        let address = sprite::pattern_address(0xff, 0, 0, height, table);
+        for _ in used..sprite::SPRITES_PER_SCANLINE {
+            self.mapper.borrow_mut().ppu_address(address);
+        }
+    }
+
+    /// lifts the limit of 8 sprites per scanline, which removes flicker in games that
+    /// multiplex sprites but shows sprites games meant to hide
+    pub fn set_sprite_limit(&mut self, enabled: bool) {
+        self.sprite_limit = enabled;
+    }
+
+    /// whether sprite 0 has an opaque pixel at x that can hit the background
+    ///
+    /// The hit is checked whatever the sprite's priority, so a sprite in front of sprite 0
+    /// doesn't hide it.
+    ///
+    /// - https://www.nesdev.org/wiki/PPU_OAM#Sprite_zero_hits
+    fn sprite0_opaque(&self, x: usize) -> bool {
+        self.mask_register.show_sprites(x)
+            && self
+                .sprites
+                .first()
+                .map_or(false, |sprite| sprite.zero && sprite.pixel(x) != 0)
+    }
+
+    /// draws the pixel for the current dot into the frame
+    fn output_pixel(&mut self) {
+        let x = self.dot - 1;
+        let mask = self.mask_register;
+        let index = if !mask.rendering_enabled() {
+            // with rendering off the backdrop is drawn, unless v points into the palette
+            match self.loopy.v & 0x3fff {
+                addr @ 0x3f00..=0x3fff => palette_index(addr) as u8,
+                _ => 0,
+            }
+        } else {
+            self.pixel_index(x)
+        };
+        let mut colour = self.palette[index as usize] & 0x3f;
+        if mask.contains(PpuMaskRegister::GREYSCALE) {
+            colour &= 0x30;
+        }
+        self.frame[self.scanline as usize * WIDTH + x] =
+            (mask.emphasis() as u16) << 6 | colour as u16;
+    }
+
+    /// returns the palette index of the background and sprite pixels at x, setting the
+    /// sprite 0 hit flag when they overlap
+    fn pixel_index(&mut self, x: usize) -> u8 {
+        let (bg_pixel, bg_palette) = if self.mask_register.show_background(x) {
+            self.background.pixel(self.loopy.x)
+        } else {
+            (0, 0)
+        };
+        // the first opaque sprite in OAM order wins, even when it's behind the background
+        let sprite = if self.mask_register.show_sprites(x) {
+            self.sprites
+                .iter()
+                .map(|sprite| (sprite.pixel(x), sprite.attribute))
+                .find(|(pixel, _)| *pixel != 0)
+        } else {
+            None
+        };
+        if bg_pixel != 0 && x != 255 && self.sprite0_opaque(x) {
+            self.status_register.insert(PpuStatusRegister::SPRITE_0_HIT);
+        }
+        match sprite {
+            Some((pixel, attribute))
+                if bg_pixel == 0 || attribute & sprite::BEHIND_BACKGROUND == 0 =>
+            {
+                0x10 | (attribute & sprite::PALETTE) << 2 | pixel
+            }
+            _ if bg_pixel != 0 => bg_palette << 2 | bg_pixel,
+            _ => 0,
         }
-        false
     }
 
     pub fn read_oamdata(&self) -> u8 {
-        self.oam[self.oam_addr as usize] as u8
+        self.oam[self.oam_addr as usize]
     }
 
     pub fn write_oamdata(&mut self, input: u8) {
@@ -90,7 +368,7 @@ impl Ppu {
     }
 
     pub fn write_scrolldata(&mut self, input: u8) {
-        self.scroll_register.write(input);
+        self.loopy.write_scroll(input);
     }
 
     pub fn write_ppumask(&mut self, input: u8) {
@@ -98,22 +376,17 @@ impl Ppu {
     }
 
     pub fn write_ppuaddr(&mut self, input: u8) {
-        self.addr_register.update(input);
+        self.loopy.write_addr(input);
     }
 
     pub fn write_ppudata(&mut self, input: u8) {
-        let addr = self.addr_register.value;
+        let addr = self.loopy.v & 0x3fff;
         match addr {
-            0..=0x1fff => panic!("cannot write into chr_rom"),
-            0x2000..=0x2fff => {
+            0..=0x1fff => self.mapper.borrow_mut().ppu_write(addr, input),
+            0x2000..=0x3eff => {
                 self.vram[self.mirror_vram_addr(addr) as usize] = input;
             }
-            0x3000..=0x3eff => panic!("not expecting this to be used"),
-            0x3f10 | 0x3f14 | 0x3f18 | 0x3f1c => {
-                self.palette[(addr - 0x3f00 - 0x10) as usize] = input
-            }
-            0x3f00..=0x3fff => self.palette[(addr - 0x3f00) as usize] = input,
-            _ => panic!("unexpected ppudata write to {:02X}", addr),
+            _ => self.palette[palette_index(addr)] = input,
         }
         self.increment_vram();
     }
@@ -121,6 +394,7 @@ impl Ppu {
     pub fn write_ppuctrl(&mut self, input: u8) {
         let before = self.ctrl_register.nmi_starts_on_vblank_ok();
         self.ctrl_register.update(input);
+        self.loopy.write_ctrl(input);
         if !before
             && self.ctrl_register.nmi_starts_on_vblank_ok()
             && self.status_register.is_vblank()
@@ -131,140 +405,374 @@ impl Ppu {
 
     pub fn read_ppustatus(&mut self) -> u8 {
         let ret = self.status_register.read();
-        self.scroll_register.reset();
-        self.addr_register.reset();
+        self.loopy.reset_latch();
         self.status_register.set_vblank(false);
         ret
     }
 
+    /// moves v on after a PPUDATA access
+    ///
+    /// While rendering, the access instead bumps coarse x and y at once, like the fetch logic.
     fn increment_vram(&mut self) {
-        self.addr_register.inc(self.ctrl_register.vram_inc());
+        let rendering_line = self.scanline < HEIGHT as u16 || self.scanline == PRE_RENDER_SCANLINE;
+        if self.mask_register.rendering_enabled() && rendering_line {
+            self.loopy.increment_x();
+            self.loopy.increment_y();
+        } else {
+            self.loopy.v = (self.loopy.v + self.ctrl_register.vram_inc() as u16) & 0x3fff;
+        }
     }
 
-    /// check and update mask register show_sprites bit
-    fn set_sprite0_hit(&mut self) {
-        let (x, y) = (self.oam[3], self.oam[0]);
-        self.status_register.set(
-            PpuStatusRegister::SPRITE_0_HIT,
-            y as u16 == self.scanline
-                && x as usize <= self.cycle
-                && self.mask_register.contains(PpuMaskRegister::SHOW_SPRITES),
-        );
+    /// reads a byte from the pattern tables provided by the cartridge
+    pub fn read_chr(&self, addr: u16) -> u8 {
+        self.mapper.borrow().ppu_read(addr)
     }
 
-    /// calculates the mirrored vram addressed based on mirror modes
-    /// this supports a limited set of mirroring modes only horizontal and veritcal
-    fn mirror_vram_addr(&mut self, addr: u16) -> u16 {
-        let index = addr - 0x2000;
-        let quadrant = index / 0x400;
-        match (self.mirroring, quadrant) {
-            (false, 1) => index - 0x400,
-            (false, 2) => index - 0x400,
-            (false, 3) => index - 0x800,
-            (true, 2) => index - 0x800,
-            (true, 3) => index - 0x800,
-            _ => index,
+    /// fetches pattern data for rendering, exposing the address to the cartridge so boards
+    /// such as MMC3 can watch address line A12
+    fn read_pattern(&mut self, addr: u16) -> u8 {
+        let mut mapper = self.mapper.borrow_mut();
+        mapper.ppu_address(addr);
+        mapper.ppu_read(addr)
+    }
+
+    /// reads from the pattern tables or nametables for rendering
+    fn read_vram(&self, addr: u16) -> u8 {
+        match addr {
+            0..=0x1fff => self.read_chr(addr),
+            _ => self.vram[self.mirror_vram_addr(addr) as usize],
         }
     }
 
-    /// returns the regions of memory for rendering background
-    pub fn get_background_addrs(&self) -> (&[u8], &[u8]) {
-        match (self.mirroring, self.ctrl_register.base_addr()) {
-            (false, 0x2000) | (false, 0x2400) | (true, 0x2000) | (true, 0x2800) => {
-                (&self.vram[0..0x400], &self.vram[0x400..0x800])
-            }
-            (false, 0x2800) | (false, 0x2c00) | (true, 0x2400) | (true, 0x2c00) => {
-                (&self.vram[0x400..0x800], &self.vram[0..0x400])
-            }
-            (_, _) => panic!(
-                "not supported {} {}",
-                self.mirroring,
-                self.ctrl_register.base_addr()
-            ),
+    /// returns the nametable mirroring mode currently selected by the cartridge
+    pub fn mirroring(&self) -> Mirroring {
+        self.mapper.borrow().mirroring()
+    }
+
+    /// calculates the mirrored vram addressed based on mirror modes
+    /// this supports horizontal, veritcal, both single screen and four screen modes,
+    /// $3000-$3EFF mirrors $2000-$2EFF
+    fn mirror_vram_addr(&self, addr: u16) -> u16 {
+        let index = (addr - 0x2000) % 0x1000;
+        let quadrant = index / 0x400;
+        match (self.mirroring(), quadrant) {
+            (Mirroring::Horizontal, 1) => index - 0x400,
+            (Mirroring::Horizontal, 2) => index - 0x400,
+            (Mirroring::Horizontal, 3) => index - 0x800,
+            (Mirroring::Vertical, 2) => index - 0x800,
+            (Mirroring::Vertical, 3) => index - 0x800,
+            (Mirroring::SingleScreenLower, _) => index % 0x400,
+            (Mirroring::SingleScreenUpper, _) => 0x400 + index % 0x400,
+            _ => index,
         }
     }
 
     pub fn read_data(&mut self) -> u8 {
-        let addr = self.addr_register.value;
+        let addr = self.loopy.v & 0x3fff;
         self.increment_vram();
         match addr {
             0..=0x1fff => {
                 let result = self.buffer;
-                self.buffer = self.chr_rom[addr as usize];
+                self.buffer = self.read_chr(addr);
                 result
             }
-            0x2000..=0x2fff => {
+            0x2000..=0x3eff => {
                 let result = self.buffer;
                 self.buffer = self.vram[self.mirror_vram_addr(addr) as usize];
                 result
             }
-            0x3000..=0x3eff => panic!("not expecting this to be used"),
-            0x3f10 | 0x3f14 | 0x3f18 | 0x3f1c => self.palette[(addr - 0x3f00 - 0x10) as usize],
-            0x3f00..=0x3fff => self.palette[(addr - 0x3f00) as usize],
-            _ => panic!("unexpected access"),
+            _ => {
+                // palette reads aren't buffered, the buffer gets the nametable underneath
+                self.buffer = self.vram[self.mirror_vram_addr(addr - 0x1000) as usize];
+                self.palette[palette_index(addr)]
+            }
         }
     }
 }
 
-pub struct PpuAddrRegister {
-    pub value: u16,
-    latch: bool,
+/// returns the index into palette ram for an address in $3F00-$3FFF
+///
+/// The backdrop entries of the sprite palettes ($3F10/$3F14/$3F18/$3F1C) mirror the ones of the
+/// background palettes.
+fn palette_index(addr: u16) -> usize {
+    let index = (addr & 0x1f) as usize;
+    if index >= 0x10 && index % 4 == 0 {
+        index - 0x10
+    } else {
+        index
+    }
 }
 
-impl PpuAddrRegister {
-    pub fn new() -> Self {
-        Self {
-            value: 0,
-            latch: true,
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper;
+    use crate::rom::Rom;
+    use rstest::rstest;
+
+    fn setup_ppu(mirroring: Mirroring) -> Ppu {
+        let rom = Rom::new_from_vec(vec![]).with_mirroring(mirroring);
+        Ppu::new(mapper::new(rom))
+    }
+
+    /// runs the ppu until it reaches a scanline and dot
+    fn run_to(ppu: &mut Ppu, scanline: u16, dot: usize) {
+        while ppu.position() != (scanline, dot) {
+            ppu.tick(1);
         }
     }
 
-    pub fn update(&mut self, data: u8) {
-        let b = self.value.to_le_bytes();
-        if self.latch {
-            self.value = u16::from_le_bytes([b[0], data]);
-        } else {
-            self.value = u16::from_le_bytes([data, b[1]]);
+    /// writes bytes through PPUADDR/PPUDATA
+    fn write_vram(ppu: &mut Ppu, addr: u16, data: &[u8]) {
+        ppu.write_ppuaddr((addr >> 8) as u8);
+        ppu.write_ppuaddr(addr as u8);
+        for byte in data {
+            ppu.write_ppudata(*byte);
         }
-        self.latch = !self.latch;
     }
 
-    pub fn inc(&mut self, input: u8) {
-        self.value = self.value.wrapping_add(input as u16) & 0x3fff;
+    #[rstest]
+    #[case(Mirroring::Horizontal, 0x2000, 0x0000)]
+    #[case(Mirroring::Horizontal, 0x2800, 0x0400)]
+    #[case(Mirroring::Horizontal, 0x2c00, 0x0400)]
+    #[case(Mirroring::Vertical, 0x2400, 0x0400)]
+    #[case(Mirroring::Vertical, 0x2c00, 0x0400)]
+    #[case(Mirroring::Vertical, 0x3400, 0x0400)]
+    #[case(Mirroring::FourScreen, 0x2800, 0x0800)]
+    #[case(Mirroring::FourScreen, 0x3c10, 0x0c10)]
+    fn test_mirror_vram_addr(
+        #[case] mirroring: Mirroring,
+        #[case] input: u16,
+        #[case] expected: u16,
+    ) {
+        let ppu = setup_ppu(mirroring);
+        let output = ppu.mirror_vram_addr(input);
+        assert_eq!(output, expected);
     }
 
-    pub fn reset(&mut self) {
-        self.latch = true;
+    #[test]
+    fn test_chr_ram_write() {
+        let mut ppu = setup_ppu(Mirroring::Horizontal);
+        ppu.write_ppuaddr(0x01);
+        ppu.write_ppuaddr(0x23);
+        ppu.write_ppudata(0x5a);
+        assert_eq!(ppu.read_chr(0x0123), 0x5a);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::rom::Rom;
-    use rstest::rstest;
+    #[test]
+    fn test_palette_mirrors() {
+        let mut ppu = setup_ppu(Mirroring::Horizontal);
+        ppu.write_ppuaddr(0x3f);
+        ppu.write_ppuaddr(0x10);
+        ppu.write_ppudata(0x2a);
+        ppu.write_ppuaddr(0x3f);
+        ppu.write_ppuaddr(0x00);
+        assert_eq!(ppu.read_data(), 0x2a);
+    }
 
     #[test]
-    fn test_ppu_addr_reg() {
-        let mut addr_reg = PpuAddrRegister::new();
-        assert_eq!(addr_reg.value, 0);
-        addr_reg.update(0x06);
-        addr_reg.update(0x50);
-        assert_eq!(addr_reg.value, 0x0650);
-        addr_reg.inc(0xa);
-        assert_eq!(addr_reg.value, 0x065a);
+    fn test_vblank_timing() {
+        let mut ppu = setup_ppu(Mirroring::Horizontal);
+        ppu.write_ppuctrl(0x80);
+        run_to(&mut ppu, VBLANK_SCANLINE, 1);
+        assert!(ppu.has_nmi.is_none());
+        ppu.tick(1);
+        assert_eq!(ppu.has_nmi, Some(true));
+        assert_eq!(ppu.read_ppustatus() & 0x80, 0x80);
+        run_to(&mut ppu, PRE_RENDER_SCANLINE, 2);
+        assert!(ppu.has_nmi.is_none());
+    }
+
+    #[test]
+    fn test_scroll_split() {
+        // CHR RAM tile 1 is solid colour 1 and fills the right half of the nametable, so
+        // scrolling by 128 pixels changes the colour at the left edge
+        let mut ppu = setup_ppu(Mirroring::Horizontal);
+        ppu.write_ppuaddr(0x00);
+        ppu.write_ppuaddr(0x10);
+        for _ in 0..8 {
+            ppu.write_ppudata(0xff);
+        }
+        ppu.write_ppuaddr(0x20);
+        ppu.write_ppuaddr(0x00);
+        for i in 0..0x3c0 {
+            ppu.write_ppudata((i % 32 >= 16) as u8);
+        }
+        ppu.write_ppuaddr(0x3f);
+        ppu.write_ppuaddr(0x01);
+        ppu.write_ppudata(0x16);
+        ppu.write_ppuaddr(0x00);
+        ppu.write_ppuaddr(0x00);
+        ppu.write_ppumask(0x0a);
+
+        // a horizontal scroll written mid-frame applies from the next scanline
+        run_to(&mut ppu, 100, 0);
+        ppu.write_scrolldata(128);
+        ppu.write_scrolldata(0);
+        run_to(&mut ppu, HEIGHT as u16, 0);
+        assert_eq!(ppu.frame[100 * WIDTH], 0x00);
+        assert_eq!(ppu.frame[100 * WIDTH + 128], 0x16);
+        assert_eq!(ppu.frame[101 * WIDTH], 0x16);
+        assert_eq!(ppu.frame[101 * WIDTH + 128], 0x00);
+    }
+
+    #[test]
+    fn test_sprite_priority() {
+        // tile 1 is solid colour 1, drawn by the background at 0-7 on the second tile row
+        let mut ppu = setup_ppu(Mirroring::Horizontal);
+        write_vram(&mut ppu, 0x0010, &[0xff; 8]);
+        write_vram(&mut ppu, 0x2020, &[0x01]);
+        write_vram(&mut ppu, 0x3f01, &[0x01]);
+        write_vram(&mut ppu, 0x3f11, &[0x11]);
+        write_vram(&mut ppu, 0x3f15, &[0x15]);
+        // sprite 0 at 0-7 is behind the background, sprite 1 at 4-11 is in front of it
+        ppu.oam = [0xff; 256];
+        ppu.oam[..8].copy_from_slice(&[9, 1, 0x20, 0, 9, 1, 0x01, 4]);
+        // rendering starts from v, which PPUDATA writes left in the palette
+        write_vram(&mut ppu, 0x0000, &[]);
+        ppu.write_ppumask(0x1e);
+
+        run_to(&mut ppu, HEIGHT as u16, 0);
+        let line = &ppu.frame[12 * WIDTH..13 * WIDTH];
+        assert_eq!(line[2], 0x01);
+        // sprite 0 still hides sprite 1 where both are opaque
+        assert_eq!(line[6], 0x01);
+        assert_eq!(line[9], 0x15);
+        assert_eq!(line[12], 0x00);
     }
 
     #[rstest]
-    #[case(false, 0x2000, 0x0000)]
-    #[case(false, 0x2800, 0x0400)]
-    #[case(false, 0x2c00, 0x0400)]
-    #[case(true, 0x2400, 0x0400)]
-    #[case(true, 0x2c00, 0x0400)]
-    fn test_mirror_vram_addr(#[case] mirroring: bool, #[case] input: u16, #[case] expected: u16) {
-        let mut ppu = Ppu::new(vec![], mirroring);
-        let output = ppu.mirror_vram_addr(input);
-        assert_eq!(output, expected);
+    #[case::left_shown(0x0a, [0x16, 0x16, 0x16])]
+    #[case::left_clipped(0x08, [0x0f, 0x0f, 0x16])]
+    #[case::greyscale(0x0b, [0x10, 0x10, 0x10])]
+    #[case::emphasis(0xaa, [0x156, 0x156, 0x156])]
+    #[case::rendering_off(0x00, [0x0f, 0x0f, 0x0f])]
+    fn test_mask(#[case] mask: u8, #[case] expected: [u16; 3]) {
+        // the background is solid colour $16 on a $0f backdrop
+        let mut ppu = setup_ppu(Mirroring::Horizontal);
+        write_vram(&mut ppu, 0x0010, &[0xff; 8]);
+        write_vram(&mut ppu, 0x2000, &[0x01; 32]);
+        write_vram(&mut ppu, 0x3f00, &[0x0f, 0x16]);
+        write_vram(&mut ppu, 0x0000, &[]);
+        ppu.write_ppumask(mask);
+
+        // scanline 0 is skipped as the pre-render line didn't fetch its first tiles
+        run_to(&mut ppu, 2, 0);
+        let line = &ppu.frame[WIDTH..];
+        assert_eq!([line[0], line[7], line[8]], expected);
+    }
+
+    #[test]
+    fn test_rendering_off_palette_backdrop() {
+        // with rendering off and v in the palette, that entry is drawn instead of the backdrop
+        let mut ppu = setup_ppu(Mirroring::Horizontal);
+        write_vram(&mut ppu, 0x3f00, &[0x0f, 0x16]);
+        write_vram(&mut ppu, 0x3f01, &[]);
+        run_to(&mut ppu, 1, 0);
+        assert_eq!(ppu.frame[0], 0x16);
+    }
+
+    #[test]
+    fn test_ppudata_increment_while_rendering() {
+        let mut ppu = setup_ppu(Mirroring::Horizontal);
+        ppu.write_ppumask(0x08);
+        run_to(&mut ppu, 100, 1);
+        // rendering has v at row 12, fine y 4; the access moves both coarse x and fine y on
+        let v = ppu.loopy.v;
+        ppu.read_data();
+        assert_eq!(ppu.loopy.v & 0x001f, (v + 1) & 0x001f);
+        assert_eq!(ppu.loopy.fine_y(), (v >> 12) + 1);
+
+        // during vblank it's the usual increment
+        run_to(&mut ppu, VBLANK_SCANLINE, 10);
+        write_vram(&mut ppu, 0x2000, &[]);
+        ppu.read_data();
+        assert_eq!(ppu.loopy.v, 0x2001);
+    }
+
+    #[rstest]
+    #[case::overlap(1, 20, 0x1e, Some(20))]
+    #[case::transparent(2, 20, 0x1e, None)]
+    #[case::behind_background(1, 20 | 0x2000, 0x1e, Some(20))]
+    #[case::left_edge(1, 0, 0x1e, Some(0))]
+    #[case::left_clipped(1, 0, 0x1a, None)]
+    #[case::last_column(1, 255, 0x1e, None)]
+    #[case::sprites_hidden(1, 20, 0x0e, None)]
+    fn test_sprite0_hit(
+        #[case] tile: u8,
+        #[case] x_attribute: u16,
+        #[case] mask: u8,
+        #[case] expected: Option<usize>,
+    ) {
+        // the background is solid on tile row 1 and sprite 0 covers lines 10-17
+        let mut ppu = setup_ppu(Mirroring::Horizontal);
+        write_vram(&mut ppu, 0x0010, &[0xff; 8]);
+        write_vram(&mut ppu, 0x2020, &[0x01; 32]);
+        ppu.oam = [0xff; 256];
+        let (x, attribute) = (x_attribute as u8, (x_attribute >> 8) as u8);
+        ppu.oam[..4].copy_from_slice(&[9, tile, attribute, x]);
+        write_vram(&mut ppu, 0x0000, &[]);
+        ppu.write_ppumask(mask);
+
+        run_to(&mut ppu, 10, 0);
+        assert_eq!(ppu.read_ppustatus() & 0x40, 0x00);
+        match expected {
+            Some(x) => {
+                run_to(&mut ppu, 10, x + 1);
+                assert_eq!(ppu.read_ppustatus() & 0x40, 0x00);
+                run_to(&mut ppu, 10, x + 2);
+                assert_eq!(ppu.read_ppustatus() & 0x40, 0x40);
+                // the flag stays set until the pre-render line
+                run_to(&mut ppu, PRE_RENDER_SCANLINE, 1);
+                assert_eq!(ppu.read_ppustatus() & 0x40, 0x40);
+                run_to(&mut ppu, PRE_RENDER_SCANLINE, 2);
+                assert_eq!(ppu.read_ppustatus() & 0x40, 0x00);
+            }
+            None => {
+                run_to(&mut ppu, HEIGHT as u16, 0);
+                assert_eq!(ppu.read_ppustatus() & 0x40, 0x00);
+            }
+        }
+    }
+
+    #[rstest]
+    #[case(0x00, [0x11, 0x11, 0x12, 0x12, 0x00])]
+    #[case(0x80, [0x12, 0x12, 0x11, 0x11, 0x00])]
+    fn test_tall_sprites(#[case] attribute: u8, #[case] expected: [u16; 5]) {
+        // odd tile 3 selects the $1000 table, with tile 2 drawn on top in colour 1 and tile 3
+        // below in colour 2
+        let mut ppu = setup_ppu(Mirroring::Horizontal);
+        write_vram(&mut ppu, 0x1020, &[0xff; 8]);
+        write_vram(&mut ppu, 0x1038, &[0xff; 8]);
+        write_vram(&mut ppu, 0x3f11, &[0x11, 0x12]);
+        ppu.oam = [0xff; 256];
+        ppu.oam[..4].copy_from_slice(&[49, 3, attribute, 0]);
+        write_vram(&mut ppu, 0x0000, &[]);
+        ppu.write_ppuctrl(0x20);
+        ppu.write_ppumask(0x14);
+
+        run_to(&mut ppu, HEIGHT as u16, 0);
+        let column: Vec<u16> = [50, 57, 58, 65, 66]
+            .iter()
+            .map(|y| ppu.frame[y * WIDTH])
+            .collect();
+        assert_eq!(column, expected);
+    }
+
+    #[test]
+    fn test_sprite_overflow_flag() {
+        let mut ppu = setup_ppu(Mirroring::Horizontal);
+        ppu.oam = [0xff; 256];
+        for i in 0..9 {
+            ppu.oam[i * 4] = 100;
+        }
+        ppu.write_ppumask(0x10);
+        run_to(&mut ppu, 100, 0);
+        assert_eq!(ppu.read_ppustatus() & 0x20, 0x00);
+        run_to(&mut ppu, 101, 0);
+        assert_eq!(ppu.read_ppustatus() & 0x20, 0x20);
+        run_to(&mut ppu, PRE_RENDER_SCANLINE, 2);
+        assert_eq!(ppu.read_ppustatus() & 0x20, 0x00);
     }
 }
 
@@ -320,6 +828,15 @@ impl PpuCtrlRegister {
         }
     }
 
+    /// sprites are 8 pixels wide and either 8 or 16 tall
+    pub fn sprite_height(&self) -> u16 {
+        if self.contains(PpuCtrlRegister::SPRITE_SIZE) {
+            16
+        } else {
+            8
+        }
+    }
+
     pub fn nmi_starts_on_vblank_ok(&mut self) -> bool {
         self.contains(PpuCtrlRegister::NMISTARTS_ON_VBI)
     }
@@ -361,6 +878,10 @@ impl PpuStatusRegister {
 
 bitflags! {
     pub struct PpuMaskRegister: u8 {
+        const GREYSCALE = 0b0000_0001;
+        const SHOW_BACKGROUND_LEFT = 0b0000_0010;
+        const SHOW_SPRITES_LEFT = 0b0000_0100;
+        const SHOW_BACKGROUND = 0b0000_1000;
         const SHOW_SPRITES = 0b0001_0000;
         const EMPHASIZE_RED = 0b0010_0000;
         const EMPHASIZE_GREEN = 0b0100_0000;
@@ -380,32 +901,25 @@ impl PpuMaskRegister {
     pub fn update(&mut self, data: u8) {
         self.bits = data;
     }
-}
 
-pub struct PpuScrollRegister {
-    pub x: u8,
-    pub y: u8,
-    latch: bool,
-}
+    pub fn rendering_enabled(&self) -> bool {
+        self.intersects(PpuMaskRegister::SHOW_BACKGROUND | PpuMaskRegister::SHOW_SPRITES)
+    }
 
-impl PpuScrollRegister {
-    pub fn new() -> Self {
-        PpuScrollRegister {
-            x: 0,
-            y: 0,
-            latch: false,
-        }
+    /// whether the background is drawn at x, which may be clipped in the left 8 pixels
+    pub fn show_background(&self, x: usize) -> bool {
+        self.contains(PpuMaskRegister::SHOW_BACKGROUND)
+            && (x >= 8 || self.contains(PpuMaskRegister::SHOW_BACKGROUND_LEFT))
     }
-    pub fn write(&mut self, data: u8) {
-        if !self.latch {
-            self.x = data;
-        } else {
-            self.y = data;
-        }
-        self.latch = !self.latch;
+
+    /// whether sprites are drawn at x, which may be clipped in the left 8 pixels
+    pub fn show_sprites(&self, x: usize) -> bool {
+        self.contains(PpuMaskRegister::SHOW_SPRITES)
+            && (x >= 8 || self.contains(PpuMaskRegister::SHOW_SPRITES_LEFT))
     }
 
-    pub fn reset(&mut self) {
-        self.latch = false;
+    /// returns the red, green and blue emphasis bits
+    pub fn emphasis(&self) -> u8 {
+        self.bits() >> 5
     }
 }
@@ -0,0 +1,168 @@
+/// PPU internal scroll and address registers
+///
+/// PPUSCROLL and PPUADDR share these, which is why writing one disturbs the other. `v` is the
+/// VRAM address used by PPUDATA and, while rendering, the position of the tile being fetched.
+/// `t` holds the address of the top left tile until it's copied into `v`, `x` is the fine x
+/// scroll and `w` selects the first or second write. Both addresses are laid out as
+/// 0yyy NNYY YYYX XXXX: fine y, nametable, coarse y and coarse x.
+///
+/// - https://www.nesdev.org/wiki/PPU_scrolling
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Loopy {
+    pub v: u16,
+    pub t: u16,
+    pub x: u8,
+    pub w: bool,
+}
+
+const COARSE_X: u16 = 0x001f;
+const COARSE_Y: u16 = 0x03e0;
+const NAMETABLE_X: u16 = 0x0400;
+const NAMETABLE_Y: u16 = 0x0800;
+const FINE_Y: u16 = 0x7000;
+
+impl Loopy {
+    /// PPUCTRL selects the nametable
+    pub fn write_ctrl(&mut self, data: u8) {
+        self.t = (self.t & !(NAMETABLE_X | NAMETABLE_Y)) | ((data as u16 & 0x03) << 10);
+    }
+
+    /// reading PPUSTATUS resets the write toggle
+    pub fn reset_latch(&mut self) {
+        self.w = false;
+    }
+
+    pub fn write_scroll(&mut self, data: u8) {
+        if !self.w {
+            self.t = (self.t & !COARSE_X) | (data as u16 >> 3);
+            self.x = data & 0x07;
+        } else {
+            self.t = (self.t & !(COARSE_Y | FINE_Y))
+                | ((data as u16 & 0xf8) << 2)
+                | ((data as u16 & 0x07) << 12);
+        }
+        self.w = !self.w;
+    }
+
+    /// the second write copies the whole address into v
+    pub fn write_addr(&mut self, data: u8) {
+        if !self.w {
+            self.t = (self.t & 0x00ff) | ((data as u16 & 0x3f) << 8);
+        } else {
+            self.t = (self.t & 0xff00) | data as u16;
+            self.v = self.t;
+        }
+        self.w = !self.w;
+    }
+
+    /// returns the address of the tile in the nametable
+    pub fn tile_address(&self) -> u16 {
+        0x2000 | (self.v & 0x0fff)
+    }
+
+    /// returns the address of the attribute byte covering the tile
+    pub fn attribute_address(&self) -> u16 {
+        0x23c0 | (self.v & 0x0c00) | ((self.v >> 4) & 0x38) | ((self.v >> 2) & 0x07)
+    }
+
+    /// returns the shift that selects the tile's two bits from its attribute byte
+    pub fn attribute_shift(&self) -> u8 {
+        (((self.v >> 4) & 0x04) | (self.v & 0x02)) as u8
+    }
+
+    pub fn fine_y(&self) -> u16 {
+        (self.v & FINE_Y) >> 12
+    }
+
+    /// moves v to the next tile, wrapping into the horizontally adjacent nametable
+    pub fn increment_x(&mut self) {
+        if self.v & COARSE_X == 31 {
+            self.v &= !COARSE_X;
+            self.v ^= NAMETABLE_X;
+        } else {
+            self.v += 1;
+        }
+    }
+
+    /// moves v down one pixel, wrapping into the vertically adjacent nametable after row 29
+    pub fn increment_y(&mut self) {
+        if self.v & FINE_Y != FINE_Y {
+            self.v += 0x1000;
+            return;
+        }
+        self.v &= !FINE_Y;
+        let mut coarse_y = (self.v & COARSE_Y) >> 5;
+        if coarse_y == 29 {
+            coarse_y = 0;
+            self.v ^= NAMETABLE_Y;
+        } else if coarse_y == 31 {
+            // rows 30 and 31 hold attributes, scrolling into them wraps without switching
+            coarse_y = 0;
+        } else {
+            coarse_y += 1;
+        }
+        self.v = (self.v & !COARSE_Y) | (coarse_y << 5);
+    }
+
+    /// restores the horizontal position from t at the end of each scanline
+    pub fn copy_horizontal(&mut self) {
+        let mask = COARSE_X | NAMETABLE_X;
+        self.v = (self.v & !mask) | (self.t & mask);
+    }
+
+    /// restores the vertical position from t before each frame
+    pub fn copy_vertical(&mut self) {
+        let mask = COARSE_Y | NAMETABLE_Y | FINE_Y;
+        self.v = (self.v & !mask) | (self.t & mask);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register_writes() {
+        // the sequence from the nesdev wiki's summary
+        let mut loopy = Loopy::default();
+        loopy.write_ctrl(0x00);
+        loopy.reset_latch();
+        loopy.write_scroll(0x7d);
+        assert_eq!((loopy.t, loopy.x, loopy.w), (0x000f, 0x05, true));
+        loopy.write_scroll(0x5e);
+        assert_eq!((loopy.t, loopy.w), (0x616f, false));
+        loopy.write_addr(0x3d);
+        assert_eq!((loopy.t, loopy.w), (0x3d6f, true));
+        loopy.write_addr(0xf0);
+        assert_eq!((loopy.t, loopy.v, loopy.w), (0x3df0, 0x3df0, false));
+    }
+
+    #[test]
+    fn test_increments_wrap_nametables() {
+        let mut loopy = Loopy {
+            v: 0x001f,
+            ..Loopy::default()
+        };
+        loopy.increment_x();
+        assert_eq!(loopy.v, 0x0400);
+
+        // fine y 7 on row 29 moves to the top of the nametable below
+        loopy.v = 0x7000 | (29 << 5);
+        loopy.increment_y();
+        assert_eq!(loopy.v, 0x0800);
+
+        loopy.v = 0x7000 | (31 << 5);
+        loopy.increment_y();
+        assert_eq!(loopy.v, 0x0000);
+    }
+
+    #[test]
+    fn test_attribute_address() {
+        let loopy = Loopy {
+            v: 0x0400 | (5 << 5) | 10,
+            ..Loopy::default()
+        };
+        assert_eq!(loopy.attribute_address(), 0x27c0 + 8 + 2);
+        assert_eq!(loopy.attribute_shift(), 2);
+    }
+}
@@ -0,0 +1,160 @@
+/// sprites the hardware can draw on one scanline
+pub const SPRITES_PER_SCANLINE: usize = 8;
+
+/// attribute bits of an OAM entry
+pub const PALETTE: u8 = 0x03;
+pub const BEHIND_BACKGROUND: u8 = 0x20;
+pub const FLIP_HORIZONTAL: u8 = 0x40;
+pub const FLIP_VERTICAL: u8 = 0x80;
+
+/// A sprite's pattern row and attributes, fetched for the scanline it appears on
+#[derive(Debug, Clone, Copy)]
+pub struct LineSprite {
+    pub x: u8,
+    pub attribute: u8,
+    pub pattern_lo: u8,
+    pub pattern_hi: u8,
+    /// whether this is OAM entry 0, which sets the sprite 0 hit flag
+    pub zero: bool,
+}
+
+impl LineSprite {
+    /// returns the sprite's pixel value (0-3) at a screen x coordinate
+    pub fn pixel(&self, x: usize) -> u8 {
+        let column = x.wrapping_sub(self.x as usize);
+        if column >= 8 {
+            return 0;
+        }
+        let bit = if self.attribute & FLIP_HORIZONTAL != 0 {
+            column
+        } else {
+            7 - column
+        };
+        ((self.pattern_hi >> bit) & 1) << 1 | ((self.pattern_lo >> bit) & 1)
+    }
+}
+
+/// returns the address of a sprite's pattern row, low plane, for a row counted from its top
+///
+/// 8x8 sprites use the pattern table selected by PPUCTRL. 8x16 sprites ignore it and take
+/// the table from bit 0 of the tile number, drawing the even tile above the odd one. Flipping
+/// vertically swaps the two halves as well as the rows within them.
+///
+/// - https://www.nesdev.org/wiki/PPU_OAM#Byte_1
+pub fn pattern_address(tile: u8, attribute: u8, row: u16, height: u16, table: u16) -> u16 {
+    let row = if attribute & FLIP_VERTICAL != 0 {
+        height - 1 - row
+    } else {
+        row
+    };
+    let (table, tile) = if height == 16 {
+        ((tile as u16 & 1) * 0x1000, (tile & 0xfe) as u16 + row / 8)
+    } else {
+        (table, tile as u16)
+    };
+    table + tile * 16 + row % 8
+}
+
+/// Sprites found on a scanline by evaluate()
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Evaluation {
+    /// indices of the sprites to draw in OAM order, which is also their priority
+    pub sprites: Vec<usize>,
+    pub overflow: bool,
+}
+
+/// finds the sprites covering a scanline, the way the PPU fills secondary OAM
+///
+/// Only the first 8 sprites are kept unless `limit` is false. After finding 8 the hardware
+/// keeps looking for a 9th to set the overflow flag, but a bug makes it step through the
+/// bytes of each entry diagonally, comparing tile numbers and attributes as if they were Y
+/// coordinates. This gives both false positives and false negatives, which are reproduced
+/// here whether or not the limit is enforced.
+///
+/// - https://www.nesdev.org/wiki/PPU_sprite_evaluation
+pub fn evaluate(oam: &[u8; 256], scanline: u16, height: u16, limit: bool) -> Evaluation {
+    let in_range = |y: u8| scanline.wrapping_sub(y as u16) < height;
+    let mut evaluation = Evaluation::default();
+    let mut n = 0;
+    while n < 64 && evaluation.sprites.len() < SPRITES_PER_SCANLINE {
+        if in_range(oam[n * 4]) {
+            evaluation.sprites.push(n);
+        }
+        n += 1;
+    }
+
+    let mut m = 0;
+    for n in n..64 {
+        if in_range(oam[n * 4 + m]) {
+            evaluation.overflow = true;
+            break;
+        }
+        m = (m + 1) & 3;
+    }
+
+    if !limit {
+        evaluation
+            .sprites
+            .extend((n..64).filter(|&n| in_range(oam[n * 4])));
+    }
+    evaluation
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// returns OAM with sprites at the given Y coordinates and the rest hidden below the screen
+    fn oam_with(ys: &[u8]) -> [u8; 256] {
+        let mut oam = [0xff; 256];
+        for (i, y) in ys.iter().enumerate() {
+            oam[i * 4] = *y;
+            oam[i * 4 + 1] = 0;
+            oam[i * 4 + 2] = 0;
+        }
+        oam
+    }
+
+    #[test]
+    fn test_sprite_limit() {
+        let oam = oam_with(&[10, 50, 10, 10, 10, 10, 10, 10, 10, 10, 10]);
+        let evaluation = evaluate(&oam, 12, 8, true);
+        assert_eq!(evaluation.sprites, [0, 2, 3, 4, 5, 6, 7, 8]);
+        assert!(evaluation.overflow);
+
+        let evaluation = evaluate(&oam, 12, 8, false);
+        assert_eq!(evaluation.sprites, [0, 2, 3, 4, 5, 6, 7, 8, 9, 10]);
+        assert!(evaluation.overflow);
+
+        assert_eq!(evaluate(&oam, 20, 8, true), Evaluation::default());
+        assert_eq!(evaluate(&oam, 20, 16, false).sprites.len(), 10);
+    }
+
+    #[test]
+    fn test_pattern_address() {
+        assert_eq!(pattern_address(0x12, 0, 3, 8, 0x1000), 0x1123);
+        assert_eq!(pattern_address(0x12, FLIP_VERTICAL, 3, 8, 0x0000), 0x0124);
+        // tall sprites pick the table from bit 0 and continue into the next tile
+        assert_eq!(pattern_address(0x13, 0, 3, 16, 0x0000), 0x1123);
+        assert_eq!(pattern_address(0x13, 0, 11, 16, 0x0000), 0x1133);
+        assert_eq!(pattern_address(0x12, FLIP_VERTICAL, 0, 16, 0x1000), 0x0137);
+        assert_eq!(pattern_address(0x12, FLIP_VERTICAL, 15, 16, 0x1000), 0x0120);
+    }
+
+    #[test]
+    fn test_overflow_bug() {
+        // exactly 8 sprites on the line, but after the 9th misses the next comparison reads
+        // the 10th sprite's tile number, which happens to be in range
+        let mut oam = oam_with(&[10, 10, 10, 10, 10, 10, 10, 10, 200, 200]);
+        oam[9 * 4 + 1] = 11;
+        let evaluation = evaluate(&oam, 12, 8, true);
+        assert_eq!(evaluation.sprites.len(), 8);
+        assert!(evaluation.overflow);
+
+        // and a real 9th sprite is missed when the diagonal read skips its Y coordinate
+        let mut oam = oam_with(&[10, 10, 10, 10, 10, 10, 10, 10, 200, 10]);
+        oam[9 * 4 + 1] = 200;
+        let evaluation = evaluate(&oam, 12, 8, true);
+        assert!(!evaluation.overflow);
+    }
+}
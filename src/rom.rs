@@ -1,6 +1,11 @@
+use crate::mapper;
 use bitflags::bitflags;
 use log::debug;
+use std::error::Error;
+use std::fmt;
 
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
 const PRG_ROM_PAGE_SIZE: usize = 0x4000;
 const PRG_RAM_PAGE_SIZE: usize = 0x2000;
 const CHR_ROM_PAGE_SIZE: usize = 0x2000;
@@ -9,83 +14,446 @@ const CHR_RAM_PAGE_SIZE: usize = 0x2000;
 bitflags! {
     pub struct RomFlags: u8 {
         const MIRRORING = 0b0000_0001;
+        const BATTERY = 0b0000_0010;
         const TRAINER = 0b0000_0100;
+        const FOUR_SCREEN = 0b0000_1000;
     }
 }
 
+/// nametable mirroring arrangement selected by the cartridge
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    SingleScreenLower,
+    SingleScreenUpper,
+    /// all four nametables are separate, using 2K of extra VRAM on the cartridge
+    FourScreen,
+}
+
+/// the flavour of header found at the start of the file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderFormat {
+    /// archaic iNES where bytes 7-15 may contain garbage such as "DiskDude!"
+    ArchaicInes,
+    Ines,
+    Nes20,
+}
+
+/// hardware the rom expects to be plugged into, from byte 7 (and 13 when extended)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleType {
+    Nes,
+    VsSystem,
+    Playchoice10,
+    Extended(u8),
+}
+
+/// CPU/PPU timing the rom was written for, from NES 2.0 byte 12
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timing {
+    Ntsc,
+    Pal,
+    MultiRegion,
+    Dendy,
+}
+
+/// reasons an iNES/NES 2.0 file could not be loaded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RomError {
+    BadMagic,
+    Truncated { expected: usize, actual: usize },
+    UnsupportedMapper(u16),
+    UnsupportedConsole(ConsoleType),
+    PrgRomTooSmall { minimum: usize, actual: usize },
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomError::BadMagic => write!(f, "missing NES<EOF> magic, not an iNES file"),
+            RomError::Truncated { expected, actual } => write!(
+                f,
+                "truncated rom, expected {} bytes but found {}",
+                expected, actual
+            ),
+            RomError::UnsupportedMapper(mapper) => write!(f, "unsupported mapper {}", mapper),
+            RomError::UnsupportedConsole(console) => {
+                write!(f, "unsupported console type {:?}", console)
+            }
+            RomError::PrgRomTooSmall { minimum, actual } => write!(
+                f,
+                "{} bytes of PRG ROM is too small, the mapper needs at least {}",
+                actual, minimum
+            ),
+        }
+    }
+}
+
+impl Error for RomError {}
+
+/// Validated contents of the 16 byte iNES / NES 2.0 header
+///
+/// - https://www.nesdev.org/wiki/INES
+/// - https://www.nesdev.org/wiki/NES_2.0
 #[derive(Debug)]
-struct RomHeader {
-    magic: bool,
-    prg_rom_bytes: usize,
-    chr_rom_bytes: usize,
-    prg_ram_bytes: usize,
-    chr_ram_bytes: usize,
-    flags: RomFlags,
+pub struct RomHeader {
+    pub format: HeaderFormat,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub prg_rom_bytes: usize,
+    pub chr_rom_bytes: usize,
+    pub prg_ram_bytes: usize,
+    pub prg_nvram_bytes: usize,
+    pub chr_ram_bytes: usize,
+    pub chr_nvram_bytes: usize,
+    pub console: ConsoleType,
+    pub timing: Timing,
+    pub flags: RomFlags,
+}
+
+impl RomHeader {
+    pub fn parse(data: &[u8]) -> Result<Self, RomError> {
+        if data.len() < HEADER_SIZE {
+            return Err(RomError::Truncated {
+                expected: HEADER_SIZE,
+                actual: data.len(),
+            });
+        }
+        if data[0..4] != [0x4e, 0x45, 0x53, 0x1a] {
+            return Err(RomError::BadMagic);
+        }
+
+        let format = match data[7] & 0x0c {
+            0x08 => HeaderFormat::Nes20,
+            0x00 if data[12..16] == [0, 0, 0, 0] => HeaderFormat::Ines,
+            _ => HeaderFormat::ArchaicInes,
+        };
+        let console = match data[7] & 0b11 {
+            0 => ConsoleType::Nes,
+            1 => ConsoleType::VsSystem,
+            2 => ConsoleType::Playchoice10,
+            _ => ConsoleType::Extended(data[13] & 0x0f),
+        };
+        let flags = RomFlags::from_bits_truncate(data[6]);
+        let mapper_low = (data[6] >> 4) as u16;
+
+        let header = match format {
+            HeaderFormat::Nes20 => RomHeader {
+                format,
+                mapper: ((data[8] & 0x0f) as u16) << 8 | (data[7] & 0xf0) as u16 | mapper_low,
+                submapper: data[8] >> 4,
+                prg_rom_bytes: nes20_rom_size(data[4], data[9] & 0x0f, PRG_ROM_PAGE_SIZE),
+                chr_rom_bytes: nes20_rom_size(data[5], data[9] >> 4, CHR_ROM_PAGE_SIZE),
+                prg_ram_bytes: nes20_ram_size(data[10] & 0x0f),
+                prg_nvram_bytes: nes20_ram_size(data[10] >> 4),
+                chr_ram_bytes: nes20_ram_size(data[11] & 0x0f),
+                chr_nvram_bytes: nes20_ram_size(data[11] >> 4),
+                console,
+                timing: match data[12] & 0b11 {
+                    0 => Timing::Ntsc,
+                    1 => Timing::Pal,
+                    2 => Timing::MultiRegion,
+                    _ => Timing::Dendy,
+                },
+                flags,
+            },
+            _ => RomHeader {
+                format,
+                mapper: match format {
+                    HeaderFormat::Ines => (data[7] & 0xf0) as u16 | mapper_low,
+                    _ => mapper_low,
+                },
+                submapper: 0,
+                prg_rom_bytes: data[4] as usize * PRG_ROM_PAGE_SIZE,
+                chr_rom_bytes: data[5] as usize * CHR_ROM_PAGE_SIZE,
+                prg_ram_bytes: if data[8] == 0 || format == HeaderFormat::ArchaicInes {
+                    PRG_RAM_PAGE_SIZE
+                } else {
+                    data[8] as usize * PRG_RAM_PAGE_SIZE
+                },
+                prg_nvram_bytes: 0,
+                chr_ram_bytes: if data[5] == 0 { CHR_RAM_PAGE_SIZE } else { 0 },
+                chr_nvram_bytes: 0,
+                console: if format == HeaderFormat::Ines {
+                    console
+                } else {
+                    ConsoleType::Nes
+                },
+                timing: Timing::Ntsc,
+                flags,
+            },
+        };
+
+        if !mapper::is_supported(header.mapper) {
+            return Err(RomError::UnsupportedMapper(header.mapper));
+        }
+        if header.console != ConsoleType::Nes {
+            return Err(RomError::UnsupportedConsole(header.console));
+        }
+        let minimum = mapper::min_prg_rom_bytes(header.mapper);
+        if header.prg_rom_bytes < minimum {
+            return Err(RomError::PrgRomTooSmall {
+                minimum,
+                actual: header.prg_rom_bytes,
+            });
+        }
+        debug!("{:?}", header);
+        Ok(header)
+    }
+
+    fn trainer_bytes(&self) -> usize {
+        if self.flags.contains(RomFlags::TRAINER) {
+            TRAINER_SIZE
+        } else {
+            0
+        }
+    }
+}
+
+/// decodes a NES 2.0 rom size which is either a count of pages or, when the most significant
+/// nibble is $F, an exponent-multiplier pair of the form 2^E * (MM * 2 + 1)
+fn nes20_rom_size(lsb: u8, msb: u8, page_size: usize) -> usize {
+    if msb == 0x0f {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0b11) as usize * 2 + 1;
+        2usize.saturating_pow(exponent).saturating_mul(multiplier)
+    } else {
+        ((msb as usize) << 8 | lsb as usize) * page_size
+    }
+}
+
+/// decodes a NES 2.0 ram size stored as a shift count of 64 bytes
+fn nes20_ram_size(shift: u8) -> usize {
+    if shift == 0 {
+        0
+    } else {
+        64 << shift
+    }
 }
 
 #[derive(Debug)]
 pub struct Rom {
     header: RomHeader,
-    prg_rom: Vec<u8>,
+    pub prg_rom: Vec<u8>,
     pub chr_rom: Vec<u8>,
+    /// 512 bytes loaded into $7000-$71FF by copier hardware before the game starts
+    pub trainer: Option<Vec<u8>>,
 }
 
 impl Rom {
-    pub fn new_from_ines(data: &[u8]) -> Self {
-        let prg_rom_bytes = data[4] as usize * PRG_ROM_PAGE_SIZE;
-        let chr_rom_bytes = data[5] as usize * CHR_ROM_PAGE_SIZE;
-        let header = RomHeader {
-            magic: data[0..4] == [0x4e, 0x45, 0x53, 0x1a],
-            prg_rom_bytes,
-            chr_rom_bytes,
-            prg_ram_bytes: if data[8] == 0 {
-                PRG_RAM_PAGE_SIZE
-            } else {
-                data[8] as usize * PRG_RAM_PAGE_SIZE
-            },
-            chr_ram_bytes: if data[5] == 0 { CHR_RAM_PAGE_SIZE } else { 0 },
-            flags: RomFlags { bits: data[6] },
-        };
+    pub fn new_from_ines(data: &[u8]) -> Result<Self, RomError> {
+        let header = RomHeader::parse(data)?;
 
-        let prg_rom_offset = if header.flags.contains(RomFlags::TRAINER) {
-            528
-        } else {
-            16
+        let prg_rom_offset = HEADER_SIZE + header.trainer_bytes();
+        // NES 2.0 exponent-multiplier sizes can be too large to add up, no file holds that much
+        let chr_rom_offset = prg_rom_offset.checked_add(header.prg_rom_bytes);
+        let end = chr_rom_offset.and_then(|offset| offset.checked_add(header.chr_rom_bytes));
+        let (chr_rom_offset, end) = match (chr_rom_offset, end) {
+            (Some(chr_rom_offset), Some(end)) if data.len() >= end => (chr_rom_offset, end),
+            _ => {
+                return Err(RomError::Truncated {
+                    expected: end.unwrap_or(usize::MAX),
+                    actual: data.len(),
+                })
+            }
         };
-        let chr_rom_offset = prg_rom_offset + prg_rom_bytes;
 
-        Rom {
+        Ok(Rom {
+            prg_rom: data[prg_rom_offset..chr_rom_offset].to_vec(),
+            chr_rom: data[chr_rom_offset..end].to_vec(),
+            trainer: if header.trainer_bytes() > 0 {
+                Some(data[HEADER_SIZE..prg_rom_offset].to_vec())
+            } else {
+                None
+            },
             header,
-            prg_rom: data[prg_rom_offset..(prg_rom_offset + prg_rom_bytes)].to_vec(),
-            chr_rom: data[chr_rom_offset..(chr_rom_offset + chr_rom_bytes)].to_vec(),
-        }
+        })
     }
 
     pub fn new_from_vec(prg_rom: Vec<u8>) -> Self {
         Rom {
             header: RomHeader {
-                magic: false,
-                prg_rom_bytes: 0,
+                format: HeaderFormat::Ines,
+                mapper: 0,
+                submapper: 0,
+                prg_rom_bytes: prg_rom.len(),
                 chr_rom_bytes: 0,
-                prg_ram_bytes: 0,
+                prg_ram_bytes: PRG_RAM_PAGE_SIZE,
+                prg_nvram_bytes: 0,
                 chr_ram_bytes: 0,
+                chr_nvram_bytes: 0,
+                console: ConsoleType::Nes,
+                timing: Timing::Ntsc,
                 flags: RomFlags { bits: 0 },
             },
             prg_rom,
             chr_rom: vec![],
+            trainer: None,
         }
     }
-    pub fn read_byte(&self, address: u16) -> u8 {
-        // This only implements mapper0
-        // TODO: implement other mappers
-        let mut addr = address - 0x8000;
-        if self.prg_rom.len() <= 0x4000 && addr >= 0x4000 {
-            addr %= 0x4000;
+
+    pub fn header(&self) -> &RomHeader {
+        &self.header
+    }
+
+    /// returns the mapper number, 12 bits wide for NES 2.0 headers
+    pub fn mapper(&self) -> u16 {
+        self.header.mapper
+    }
+
+    /// returns true if the cartridge keeps its PRG RAM alive with a battery
+    pub fn has_battery(&self) -> bool {
+        self.header.flags.contains(RomFlags::BATTERY)
+    }
+
+    /// returns the mirroring wired on the board, where four screen overrides the mirroring bit
+    pub fn mirroring(&self) -> Mirroring {
+        if self.header.flags.contains(RomFlags::FOUR_SCREEN) {
+            Mirroring::FourScreen
+        } else if self.header.flags.contains(RomFlags::MIRRORING) {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
         }
-        self.prg_rom[addr as usize]
     }
 
-    pub fn mirroring(&self) -> bool {
-        self.header.flags.contains(RomFlags::MIRRORING)
+    /// overrides the header mirroring bits, useful for roms built with new_from_vec
+    pub fn with_mirroring(mut self, mirroring: Mirroring) -> Self {
+        self.header
+            .flags
+            .set(RomFlags::MIRRORING, mirroring == Mirroring::Vertical);
+        self.header
+            .flags
+            .set(RomFlags::FOUR_SCREEN, mirroring == Mirroring::FourScreen);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn ines(header: [u8; 16]) -> Vec<u8> {
+        let mut data = header.to_vec();
+        data.resize(HEADER_SIZE + header[4] as usize * PRG_ROM_PAGE_SIZE, 0);
+        data.resize(data.len() + header[5] as usize * CHR_ROM_PAGE_SIZE, 0);
+        data
+    }
+
+    #[test]
+    fn test_ines_header() {
+        let data = ines([
+            0x4e, 0x45, 0x53, 0x1a, 2, 1, 0x11, 0x00, 0, 0, 0, 0, 0, 0, 0, 0,
+        ]);
+        let rom = Rom::new_from_ines(&data).unwrap();
+        assert_eq!(rom.header().format, HeaderFormat::Ines);
+        assert_eq!(rom.mapper(), 1);
+        assert_eq!(rom.mirroring(), Mirroring::Vertical);
+        assert_eq!(rom.prg_rom.len(), 0x8000);
+        assert_eq!(rom.chr_rom.len(), 0x2000);
+        assert_eq!(rom.header().prg_ram_bytes, 0x2000);
+    }
+
+    #[test]
+    fn test_trainer() {
+        let mut data = ines([
+            0x4e, 0x45, 0x53, 0x1a, 1, 0, 0x04, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ]);
+        data.splice(HEADER_SIZE..HEADER_SIZE, vec![0x5a; TRAINER_SIZE]);
+        let rom = Rom::new_from_ines(&data).unwrap();
+        assert_eq!(rom.trainer, Some(vec![0x5a; TRAINER_SIZE]));
+        assert_eq!(rom.prg_rom, vec![0; PRG_ROM_PAGE_SIZE]);
+    }
+
+    #[test]
+    fn test_four_screen() {
+        // the four screen bit wins over the mirroring bit
+        let data = ines([
+            0x4e, 0x45, 0x53, 0x1a, 1, 1, 0x09, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ]);
+        let rom = Rom::new_from_ines(&data).unwrap();
+        assert_eq!(rom.mirroring(), Mirroring::FourScreen);
+        let rom = rom.with_mirroring(Mirroring::Horizontal);
+        assert_eq!(rom.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn test_archaic_header_ignores_byte7() {
+        let mut data = ines([
+            0x4e, 0x45, 0x53, 0x1a, 1, 1, 0x00, 0x44, 0, 0, 0, 0, 0, 0, 0, 0,
+        ]);
+        data[7..16].copy_from_slice(b"DiskDude!");
+        let rom = Rom::new_from_ines(&data).unwrap();
+        assert_eq!(rom.header().format, HeaderFormat::ArchaicInes);
+        assert_eq!(rom.mapper(), 0);
+    }
+
+    #[test]
+    fn test_nes20_header() {
+        let data = ines([
+            0x4e, 0x45, 0x53, 0x1a, 2, 0, 0x42, 0x08, 0x10, 0, 0x70, 0x07, 0x01, 0, 0, 0,
+        ]);
+        let header = Rom::new_from_ines(&data).unwrap().header;
+        assert_eq!(header.format, HeaderFormat::Nes20);
+        assert_eq!(header.mapper, 4);
+        assert_eq!(header.submapper, 1);
+        assert_eq!(header.prg_ram_bytes, 0);
+        assert_eq!(header.prg_nvram_bytes, 0x2000);
+        assert_eq!(header.chr_ram_bytes, 0x2000);
+        assert_eq!(header.timing, Timing::Pal);
+    }
+
+    #[rstest]
+    #[case(0x02, 0x0, 0x4000, 0x8000)]
+    #[case(0x02, 0x1, 0x4000, 0x408000)]
+    #[case(0x3d, 0xf, 0x4000, 0x18000)]
+    fn test_nes20_rom_size(
+        #[case] lsb: u8,
+        #[case] msb: u8,
+        #[case] page_size: usize,
+        #[case] expected: usize,
+    ) {
+        assert_eq!(nes20_rom_size(lsb, msb, page_size), expected);
+    }
+
+    #[rstest]
+    #[case(vec![0x4e, 0x45, 0x53], RomError::Truncated { expected: 16, actual: 3 })]
+    #[case(vec![0; 16], RomError::BadMagic)]
+    #[case(
+        vec![0x4e, 0x45, 0x53, 0x1a, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xea],
+        RomError::Truncated { expected: 0x4010, actual: 17 }
+    )]
+    #[case(
+        vec![0x4e, 0x45, 0x53, 0x1a, 0xff, 0, 0, 0x08, 0, 0x0f, 0, 0, 0, 0, 0, 0],
+        RomError::Truncated { expected: usize::MAX, actual: 16 }
+    )]
+    #[case(
+        vec![0x4e, 0x45, 0x53, 0x1a, 0xfc, 0xfc, 0, 0x08, 0, 0xff, 0, 0, 0, 0, 0, 0],
+        RomError::Truncated { expected: usize::MAX, actual: 16 }
+    )]
+    #[case(
+        vec![0x4e, 0x45, 0x53, 0x1a, 1, 0, 0xf0, 0xf0, 0, 0, 0, 0, 0, 0, 0, 0],
+        RomError::UnsupportedMapper(255)
+    )]
+    #[case(
+        vec![0x4e, 0x45, 0x53, 0x1a, 1, 0, 0, 0x01, 0, 0, 0, 0, 0, 0, 0, 0],
+        RomError::UnsupportedConsole(ConsoleType::VsSystem)
+    )]
+    #[case(
+        vec![0x4e, 0x45, 0x53, 0x1a, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+        RomError::PrgRomTooSmall { minimum: 0x4000, actual: 0 }
+    )]
+    #[case(
+        vec![0x4e, 0x45, 0x53, 0x1a, 0x0c, 0, 0x10, 0x08, 0, 0x0f, 0, 0, 0, 0, 0, 0],
+        RomError::PrgRomTooSmall { minimum: 0x4000, actual: 8 }
+    )]
+    #[case(
+        vec![0x4e, 0x45, 0x53, 0x1a, 1, 1, 0x40, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+        RomError::PrgRomTooSmall { minimum: 0x8000, actual: 0x4000 }
+    )]
+    fn test_rom_errors(#[case] data: Vec<u8>, #[case] expected: RomError) {
+        assert_eq!(Rom::new_from_ines(&data).unwrap_err(), expected);
     }
 }
@@ -0,0 +1,148 @@
+use crate::apu::Channel;
+use crate::audio::Resampler;
+use hound::{SampleFormat, WavSpec, WavWriter};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+/// recordings always use this rate so they don't depend on the host's audio device
+pub const SAMPLE_RATE: u32 = 44_100;
+
+/// One resampled mono WAV file
+struct Track {
+    resampler: Resampler,
+    writer: WavWriter<BufWriter<File>>,
+    buffer: Vec<f32>,
+}
+
+impl Track {
+    fn create(path: &Path) -> hound::Result<Self> {
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        Ok(Track {
+            resampler: Resampler::new(SAMPLE_RATE),
+            writer: WavWriter::create(path, spec)?,
+            buffer: Vec::new(),
+        })
+    }
+
+    fn write(&mut self, samples: &[f32]) -> hound::Result<()> {
+        self.buffer.clear();
+        self.resampler.process(samples, &mut self.buffer);
+        for sample in &self.buffer {
+            self.writer
+                .write_sample((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)?;
+        }
+        Ok(())
+    }
+}
+
+/// Records APU output to WAV files
+///
+/// The mixed output goes to the given path, and when recording channels each one is also
+/// written to its own file named after the channel, e.g. song-pulse1.wav. Output is resampled
+/// with a fixed ratio so the same input always produces the same file.
+pub struct WavRecorder {
+    mixed: Track,
+    channels: Option<Vec<Track>>,
+}
+
+impl WavRecorder {
+    pub fn create(path: &Path, channels: bool) -> hound::Result<Self> {
+        let channels = if channels {
+            Some(
+                Channel::ALL
+                    .iter()
+                    .map(|channel| Track::create(&channel_path(path, *channel)))
+                    .collect::<hound::Result<Vec<Track>>>()?,
+            )
+        } else {
+            None
+        };
+        Ok(WavRecorder {
+            mixed: Track::create(path)?,
+            channels,
+        })
+    }
+
+    /// appends a block of APU samples and, if recording channels, the matching per channel
+    /// samples from Apu::take_channel_samples()
+    pub fn write(
+        &mut self,
+        samples: &[f32],
+        channel_samples: Option<[Vec<f32>; 5]>,
+    ) -> hound::Result<()> {
+        self.mixed.write(samples)?;
+        if let (Some(tracks), Some(channel_samples)) = (&mut self.channels, channel_samples) {
+            for (track, samples) in tracks.iter_mut().zip(channel_samples.iter()) {
+                track.write(samples)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// writes the WAV headers, files are left truncated if this isn't called
+    pub fn finalize(self) -> hound::Result<()> {
+        self.mixed.writer.finalize()?;
+        for track in self.channels.into_iter().flatten() {
+            track.writer.finalize()?;
+        }
+        Ok(())
+    }
+}
+
+/// returns the path a channel is recorded to, derived from the mixed output's path
+fn channel_path(path: &Path, channel: Channel) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}-{}.wav", stem, channel.name()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apu::Apu;
+    use crate::audio::CPU_CLOCK_HZ;
+    use hound::WavReader;
+
+    #[test]
+    fn test_channel_path() {
+        assert_eq!(
+            channel_path(Path::new("out/song.wav"), Channel::Triangle),
+            PathBuf::from("out/song-triangle.wav")
+        );
+    }
+
+    #[test]
+    fn test_record() {
+        let dir = std::env::temp_dir().join(format!("crabbiness-wav-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.wav");
+
+        let mut apu = Apu::new();
+        apu.record_channels(true);
+        let mut recorder = WavRecorder::create(&path, true).unwrap();
+        for _ in 0..CPU_CLOCK_HZ as usize / 10 {
+            apu.tick();
+        }
+        recorder
+            .write(&apu.take_samples(), apu.take_channel_samples())
+            .unwrap();
+        recorder.finalize().unwrap();
+
+        let reader = WavReader::open(&path).unwrap();
+        assert_eq!(reader.spec().sample_rate, SAMPLE_RATE);
+        let duration = reader.duration() as i64;
+        assert!(
+            (duration - SAMPLE_RATE as i64 / 10).abs() < 100,
+            "{}",
+            duration
+        );
+        let reader = WavReader::open(dir.join("test-dmc.wav")).unwrap();
+        assert_eq!(reader.duration() as i64, duration);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}